@@ -0,0 +1,94 @@
+//! Compare the custom FFT with rustfft across sample sizes, in both directions, and reusing a
+//! planner with planning each transform.
+//!
+//! Run with `cargo bench --bench fft`.
+
+use compression::fft::{self, FftDirection, FftPlanner};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use num_complex::Complex32;
+
+/// Exponents of the sample sizes (powers of 2) to benchmark.
+const SIZE_EXPONENTS: std::ops::RangeInclusive<u32> = 10..=22;
+
+fn sample(sample_size: usize) -> Vec<Complex32> {
+    (0..sample_size)
+        .map(|x| Complex32::new((x % 17) as f32 - 8., (x % 5) as f32))
+        .collect()
+}
+
+fn compare(c: &mut Criterion, direction: FftDirection) {
+    let name = match direction {
+        FftDirection::Forward => "fft",
+        FftDirection::Inverse => "fft_inverse",
+    };
+    let mut group = c.benchmark_group(name);
+    // The largest sizes take long enough that fewer samples suffice
+    group.sample_size(10);
+    for exponent in SIZE_EXPONENTS {
+        let sample_size = 1 << exponent;
+        let input = sample(sample_size);
+        let planner = FftPlanner::new(sample_size, direction).unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("custom", sample_size),
+            &input,
+            |b, input| {
+                b.iter_batched_ref(
+                    || input.clone(),
+                    |buffer| planner.process(buffer).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+        let rustfft = rustfft::FftPlanner::new().plan_fft(sample_size, direction);
+        group.bench_with_input(
+            BenchmarkId::new("rustfft", sample_size),
+            &input,
+            |b, input| {
+                b.iter_batched_ref(
+                    || input.clone(),
+                    |buffer| rustfft.process(buffer),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn forward(c: &mut Criterion) {
+    compare(c, FftDirection::Forward);
+}
+
+fn inverse(c: &mut Criterion) {
+    compare(c, FftDirection::Inverse);
+}
+
+/// Many small transforms of the same size, like the blocks of an image.
+fn reuse(c: &mut Criterion) {
+    let (block_count, block_size) = (1000, 200);
+    let blocks: Vec<Vec<Complex32>> = (0..block_count).map(|_| sample(block_size)).collect();
+    let mut group = c.benchmark_group("planner_reuse");
+    group.bench_function("one-shot", |b| {
+        b.iter(|| {
+            for block in &blocks {
+                fft::fft(block).unwrap();
+            }
+        })
+    });
+    let planner = FftPlanner::new(block_size, FftDirection::Forward).unwrap();
+    group.bench_function("planner", |b| {
+        b.iter_batched_ref(
+            || blocks.clone(),
+            |blocks| {
+                for block in blocks.iter_mut() {
+                    planner.process(block).unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, forward, inverse, reuse);
+criterion_main!(benches);
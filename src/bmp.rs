@@ -1,6 +1,23 @@
-use crate::fft::{fft_2d, fft_2d_horizontal, fft_2d_inverse, fft_2d_vertical};
+use crate::dct::{
+    self, Block, QuantizationTable, BLOCK_SIZE, CHROMINANCE_QUANTIZATION, LUMINANCE_QUANTIZATION,
+};
+use crate::dump;
+use crate::entropy::{self, EntropyError};
+use crate::error::CompressionError;
+use crate::fft::{
+    fft_2d, fft_2d_horizontal, fft_2d_inverse64, fft_2d_inverse_real, fft_2d_vertical, fftshift_2d,
+    FftError,
+};
+use crate::format::PNG_MAGIC;
+use crate::header::{
+    self, BMP_DCT_MAGIC, BMP_DCT_VERSION, BMP_MAGIC, BMP_VERSION, BMP_WAVELET_MAGIC,
+    BMP_WAVELET_VERSION,
+};
+use crate::plotting::{self, PlotConfig};
+use crate::stats::CompressionStats;
+use crate::wavelet;
 use bmp;
-use num_complex::Complex32;
+use num_complex::{Complex32, Complex64};
 use plotly::{
     self,
     color::Rgb,
@@ -10,84 +27,1029 @@ use plotly::{
     Image, Layout, Plot,
 };
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, fs::File};
-use std::{
-    io::{Read, Write},
-    path::PathBuf,
-};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::{fmt::Debug, fs, io::Cursor};
 
+/// Compress a .bmp file (or a .png file with the `png` feature) for later decompression using
+/// [`decompress_bmp`].
+///
+/// The compression level divides the width and height of the frequencies kept. A level of 1
+/// keeps every frequency, so only the quantization of the coefficients is lost.
+///
+/// Progress is reported as a fraction as each channel is transformed, and 1.0 when done.
 pub fn compress_bmp(
-    bmp_file: &PathBuf,
-    compressed_file: &PathBuf,
+    bmp_file: &Path,
+    compressed_file: &Path,
     compression_level: f32,
-) -> Result<(), BoxedError> {
-    let original_image = ComplexImage::from_bitmap(&bmp_file)?;
-    let rounded_image = original_image.round_up();
-    let transformed_image = ComplexImage::new(
-        fft_2d(&rounded_image.red),
-        fft_2d(&rounded_image.green),
-        fft_2d(&rounded_image.blue),
-    );
-    let new_width = (transformed_image.width() as f32 / compression_level) as usize;
-    let new_height = (transformed_image.height() as f32 / compression_level) as usize;
-    let compressed_image = &transformed_image
-        .corners(new_width, new_height)
-        .map_err(|_| "compression must be no smaller than 1")?;
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    compress_bmp_per_channel(bmp_file, compressed_file, [compression_level; 3], progress)
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp`].
+pub fn compress_bmp_bytes(
+    input: &[u8],
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    compress_bmp_per_channel_bytes(input, [compression_level; 3], progress)
+}
+
+/// Like [`compress_bmp`], with separate compression levels for the red, green and blue channels.
+///
+/// Grayscale images are stored as a single channel, compressed at the level of the red channel.
+/// The alpha channel of transparent images is compressed at the lowest of the levels.
+pub fn compress_bmp_per_channel(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    levels: [f32; 3],
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed = compress_bmp_per_channel_bytes(&input, levels, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_per_channel`].
+pub fn compress_bmp_per_channel_bytes(
+    input: &[u8],
+    levels: [f32; 3],
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    levels
+        .into_iter()
+        .try_for_each(validate_compression_level)?;
+    let original_image = ComplexImage::from_image_bytes(input)?;
+    if original_image.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let channel_count = match original_image.is_grayscale() {
+        true => 1,
+        false => 3,
+    };
+    // Leave room for the final encoding step
+    let total = original_image.all_channels().len() as f32 + 1.;
+    let corners = transform_corners(&original_image, levels, channel_count, |c| {
+        progress((c + 1) as f32 / total)
+    })?;
     let compressed_data = CompressedData::new(
-        convert_complex_to_raw(&compressed_image.red),
-        convert_complex_to_raw(&compressed_image.green),
-        convert_complex_to_raw(&compressed_image.blue),
-        transformed_image.size(),
+        &[corners],
+        original_image.alpha.is_some(),
         original_image.size(),
+        None,
+    )?;
+    let encoded = header::with_header(
+        BMP_MAGIC,
+        BMP_VERSION,
+        &bincode::serialize(&compressed_data)?,
     );
-    let encoded = bincode::serialize(&compressed_data)?;
-    let mut file = File::create(compressed_file)?;
-    file.write_all(&encoded)?;
-    Ok(())
+    progress(1.);
+    Ok(encoded)
 }
 
-pub fn decompress_bmp(compressed_file: &PathBuf, output_file: &PathBuf) -> Result<(), BoxedError> {
-    let mut encoded: Vec<u8> = Vec::new();
-    let mut file = File::open(compressed_file)?;
-    file.read_to_end(&mut encoded)?;
-    let compressed_data: CompressedData = bincode::deserialize(&encoded)?;
-    let compressed_image = ComplexImage::new(
-        convert_raw_to_complex(&compressed_data.red),
-        convert_raw_to_complex(&compressed_data.green),
-        convert_raw_to_complex(&compressed_data.blue),
+/// Compress a .bmp file like [`compress_bmp`] in double precision, for high dynamic range
+/// images where the quantization of the 16-bit coefficients is visible.
+///
+/// Coefficients are quantized relative to the largest (usually the average color), so large
+/// bright images lose the most detail. The channels are transformed in double precision and
+/// their coefficients quantized to 32 bits instead, at the cost of a larger compressed file.
+/// [`decompress_bmp`] restores the image in double precision too.
+#[cfg(feature = "high-precision")]
+pub fn compress_bmp_hp(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed = compress_bmp_hp_bytes(&input, compression_level, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_hp`].
+#[cfg(feature = "high-precision")]
+pub fn compress_bmp_hp_bytes(
+    input: &[u8],
+    compression_level: f32,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    validate_compression_level(compression_level)?;
+    let image = ComplexImage::from_image_bytes(input)?;
+    if image.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let (width, height) = image.size();
+    let new_width = kept_size(width, compression_level);
+    let new_height = kept_size(height, compression_level);
+    let channels = residual_channels(&image);
+    // Leave room for the final encoding step
+    let total = channels.len() as f32 + 1.;
+    let corners = channels
+        .iter()
+        .enumerate()
+        .map(|(c, channel)| {
+            let samples: Channel<Complex64> = channel
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|x| Complex64::new(x.re as f64, 0.))
+                        .collect()
+                })
+                .collect();
+            let transformed = crate::fft::fft_2d64(&samples)?;
+            progress((c + 1) as f32 / total);
+            Ok(channel_corners(&transformed, new_width, new_height))
+        })
+        .collect::<Result<Vec<_>, CompressionError>>()?;
+    let compressed_data = CompressedData::new_wide(&corners, image.alpha.is_some(), image.size())?;
+    let encoded = header::with_header(
+        BMP_MAGIC,
+        BMP_VERSION,
+        &bincode::serialize(&compressed_data)?,
     );
-    let transformed_image = compressed_image.from_corners(&compressed_data.transformed_size);
-    let rounded_image = ComplexImage::new(
-        fft_2d_inverse(&transformed_image.red),
-        fft_2d_inverse(&transformed_image.green),
-        fft_2d_inverse(&transformed_image.blue),
+    progress(1.);
+    Ok(encoded)
+}
+
+/// Compress a .bmp file like [`compress_bmp`], also storing the residual between the
+/// decompressed and the original colors, so that [`decompress_bmp`] restores the image exactly.
+///
+/// The compressed file is larger than the lossy one but smaller than the original, the more
+/// the lossy compression keeps (lower levels) the smaller the residual.
+pub fn compress_bmp_lossless(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed = compress_bmp_lossless_bytes(&input, compression_level, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_lossless`].
+pub fn compress_bmp_lossless_bytes(
+    input: &[u8],
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let original = ComplexImage::from_image_bytes(input)?;
+    let lossy = compress_bmp_bytes(input, compression_level, progress)?;
+    let restored = decompress_image(&lossy)?;
+    // Taken from the original alone, its reconstruction may come out grayscale when it is not
+    let color_count = color_count(&original);
+    let residual: Vec<i16> = residual_channels(&original)
+        .iter()
+        .zip(stored_channels(&restored, color_count))
+        .flat_map(|(original, restored)| {
+            original
+                .iter()
+                .flatten()
+                .zip(restored.iter().flatten())
+                .map(|(a, b)| to_color_value(*a) as i16 - to_color_value(*b) as i16)
+        })
+        .collect();
+    let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, &lossy)?;
+    let mut compressed_data: CompressedData = bincode::deserialize(encoded)?;
+    compressed_data.residual = Some((color_count, entropy::encode(&residual)?));
+    Ok(header::with_header(
+        BMP_MAGIC,
+        BMP_VERSION,
+        &bincode::serialize(&compressed_data)?,
+    ))
+}
+
+/// Channels of an image that the residual of [`compress_bmp_lossless`] is stored for: the red,
+/// green and blue channels, or only the first for grayscale images, followed by the alpha
+/// channel if present.
+fn residual_channels(image: &ComplexImage) -> Vec<&ComplexChannel> {
+    stored_channels(image, color_count(image))
+}
+
+/// Number of color channels stored for an image: 1 if it is grayscale, otherwise 3.
+fn color_count(image: &ComplexImage) -> usize {
+    match image.is_grayscale() {
+        true => 1,
+        false => 3,
+    }
+}
+
+/// The first color channels of an image, followed by the alpha channel if present.
+fn stored_channels(image: &ComplexImage, color_count: usize) -> Vec<&ComplexChannel> {
+    image.channels()[..color_count]
+        .iter()
+        .copied()
+        .chain(image.alpha.as_ref())
+        .collect()
+}
+
+/// Add the entropy coded residual of [`compress_bmp_lossless`], of the color count of the
+/// original image, to the colors of a decompressed image.
+fn add_residual(
+    image: &ComplexImage,
+    color_count: usize,
+    residual: &[u8],
+) -> Result<ComplexImage, EntropyError> {
+    let (width, pixel_count) = (image.width(), image.width() * image.height());
+    let channel_count = color_count + image.alpha.is_some() as usize;
+    let residual = entropy::decode(residual, channel_count * pixel_count)?;
+    if !matches!(color_count, 1 | 3) || channel_count * pixel_count != residual.len() {
+        return Err(EntropyError::Malformed);
+    }
+    // The colors of grayscale images were all restored from a single channel
+    let mut channels: Vec<ComplexChannel> = stored_channels(image, color_count)
+        .iter()
+        .zip(residual.chunks_exact(pixel_count.max(1)))
+        .map(|(channel, residual)| {
+            let values: Vec<Complex32> = channel
+                .iter()
+                .flatten()
+                .zip(residual)
+                .map(|(value, residual)| {
+                    Complex32::from((to_color_value(*value) as i16 + residual) as f32)
+                })
+                .collect();
+            values
+                .chunks(width.max(1))
+                .map(<[Complex32]>::to_vec)
+                .collect()
+        })
+        .collect();
+    if color_count == 1 {
+        channels.splice(1..1, [channels[0].clone(), channels[0].clone()]);
+    }
+    Ok(ComplexImage::from_iter(channels))
+}
+
+/// Compress a .bmp file like [`compress_bmp`], in independently transformed square tiles of
+/// the tile size, for later decompression using [`decompress_bmp`].
+///
+/// Only a single tile is transformed at a time, so huge images are compressed without the
+/// memory of transforming them whole. Tiles at the right and bottom edges extend past the image
+/// by repeating its last row and column.
+///
+/// Tiles also extend into their right and bottom neighbours by the overlap (no larger than the
+/// tile size), and are crossfaded there when decompressed. This hides the seams between tiles
+/// that are quantized independently, 0 places the tiles side by side.
+///
+/// Progress is reported as a fraction as each tile is transformed, and 1.0 when done.
+pub fn compress_bmp_tiled(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    compression_level: f32,
+    tile_size: usize,
+    overlap: usize,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed =
+        compress_bmp_tiled_bytes(&input, compression_level, tile_size, overlap, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_tiled`].
+pub fn compress_bmp_tiled_bytes(
+    input: &[u8],
+    compression_level: f32,
+    tile_size: usize,
+    overlap: usize,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    validate_compression_level(compression_level)?;
+    if tile_size == 0 {
+        return Err(CompressionError::InvalidParameter(
+            "tile size must be greater than 0",
+        ));
+    }
+    if overlap > tile_size {
+        return Err(CompressionError::InvalidParameter(
+            "tile overlap must be no larger than the tile size",
+        ));
+    }
+    let image = ComplexImage::from_image_bytes(input)?;
+    if image.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let channel_count = match image.is_grayscale() {
+        true => 1,
+        false => 3,
+    };
+    let (tile, origins) = tile_grid(image.size(), Some(tile_size), overlap);
+    // Leave room for the final encoding step
+    let total = origins.len() as f32 + 1.;
+    let tiles = origins
+        .iter()
+        .enumerate()
+        .map(|(t, origin)| {
+            let levels = [compression_level; 3];
+            let corners =
+                transform_corners(&image.crop(*origin, tile), levels, channel_count, |_| {})?;
+            progress((t + 1) as f32 / total);
+            Ok(corners)
+        })
+        .collect::<Result<Vec<_>, CompressionError>>()?;
+    let mut compressed_data =
+        CompressedData::new(&tiles, image.alpha.is_some(), image.size(), Some(tile_size))?;
+    compressed_data.tile_overlap = overlap;
+    let encoded = header::with_header(
+        BMP_MAGIC,
+        BMP_VERSION,
+        &bincode::serialize(&compressed_data)?,
+    );
+    progress(1.);
+    Ok(encoded)
+}
+
+/// Compression levels below 1 (and NaN) would keep more frequencies than the image has.
+fn validate_compression_level(compression_level: f32) -> Result<(), CompressionError> {
+    match compression_level.partial_cmp(&1.) {
+        Some(Ordering::Greater | Ordering::Equal) => Ok(()),
+        _ => Err(CompressionError::InvalidParameter(
+            "compression must be no smaller than 1",
+        )),
+    }
+}
+
+/// Number of the lowest frequencies of a line to keep at a compression level. At least the
+/// first (the average, or DC term) is kept, even at levels beyond the size of the line, so the
+/// image keeps its brightness.
+fn kept_size(size: usize, compression_level: f32) -> usize {
+    ((size as f32 / compression_level) as usize).max(1)
+}
+
+/// Transform each channel of an image and keep the corners sized by the compression levels of
+/// the red, green and blue channels (the alpha channel at the lowest level). Only the first of
+/// the color channels are kept, followed by the alpha channel if present.
+///
+/// Calls back with the index of each channel as it is transformed.
+fn transform_corners(
+    image: &ComplexImage,
+    levels: [f32; 3],
+    channel_count: usize,
+    mut transformed: impl FnMut(usize),
+) -> Result<Vec<ComplexChannel>, CompressionError> {
+    // Arbitrary sizes are supported by the transform, no padding is necessary
+    let transformed_image = image
+        .all_channels()
+        .iter()
+        .enumerate()
+        .map(|(c, channel)| {
+            if dump::dumping() {
+                let real: Channel<f32> = channel
+                    .iter()
+                    .map(|row| row.iter().map(|x| x.re).collect())
+                    .collect();
+                dump::dump(&format!("bmp_time_domain_{c}"), &real[..])?;
+            }
+            let channel = fft_2d(channel)?;
+            dump::dump(&format!("bmp_frequency_domain_{c}"), &channel[..])?;
+            transformed(c);
+            Ok(channel)
+        })
+        .collect::<Result<ComplexImage, CompressionError>>()?;
+    let alpha_level = levels.iter().copied().fold(f32::INFINITY, f32::min);
+    let sizes: Vec<(usize, usize)> = levels
+        .iter()
+        .chain([alpha_level].iter())
+        .map(|level| {
+            (
+                kept_size(transformed_image.width(), *level),
+                kept_size(transformed_image.height(), *level),
+            )
+        })
+        .collect();
+    let mut corners = transformed_image
+        .corners(&sizes)
+        .map_err(|_| CompressionError::InvalidParameter("compression must be no smaller than 1"))?;
+    let alpha = match transformed_image.alpha {
+        Some(_) => corners.pop(),
+        None => None,
+    };
+    corners.truncate(channel_count);
+    corners.extend(alpha);
+    for (c, corner) in corners.iter().enumerate() {
+        dump::dump(&format!("bmp_retained_{c}"), &corner[..])?;
+    }
+    Ok(corners)
+}
+
+/// Compress a .bmp file in 8x8 blocks of the discrete cosine transform (like JPEG), for later
+/// decompression using [`decompress_bmp`].
+///
+/// The colors are converted to luminance and chrominance, and each block is quantized by the
+/// standard JPEG tables scaled by the compression level: higher = smaller compressed size,
+/// lower = better quality. Grayscale images are stored as luminance only.
+///
+/// Progress is reported as a fraction as each channel is transformed, and 1.0 when done.
+pub fn compress_bmp_dct(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed = compress_bmp_dct_bytes(&input, compression_level, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_dct`].
+pub fn compress_bmp_dct_bytes(
+    input: &[u8],
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    compress_dct(input, compression_level, DctTables::Jpeg, progress)
+}
+
+/// Compress a .bmp file like [`compress_bmp_dct`], quantizing every plane by a custom table
+/// (scaled by the compression level) instead of the JPEG tables.
+///
+/// The table is stored in the compressed file, so decompression needs nothing but the file.
+/// Returns an error if an entry of the table is zero.
+pub fn compress_bmp_dct_with_table(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    compression_level: f32,
+    table: &QuantizationTable,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed = compress_bmp_dct_with_table_bytes(&input, compression_level, table, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_dct_with_table`].
+pub fn compress_bmp_dct_with_table_bytes(
+    input: &[u8],
+    compression_level: f32,
+    table: &QuantizationTable,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    if table.iter().flatten().any(|entry| *entry == 0) {
+        return Err(CompressionError::InvalidParameter(
+            "quantization table entries must be nonzero",
+        ));
+    }
+    compress_dct(
+        input,
+        compression_level,
+        DctTables::Custom(*table),
+        progress,
+    )
+}
+
+/// Read a quantization table from a text file, see [`parse_quantization_table`].
+pub fn load_quantization_table(path: &Path) -> Result<QuantizationTable, CompressionError> {
+    parse_quantization_table(&fs::read_to_string(path)?)
+}
+
+/// Parse a quantization table from its 64 entries, row by row, separated by whitespace or
+/// commas. Brackets are ignored, so a JSON array of 8 rows parses too.
+pub fn parse_quantization_table(text: &str) -> Result<QuantizationTable, CompressionError> {
+    let invalid = CompressionError::InvalidParameter(
+        "quantization table must be 64 integers from 1 to 65535",
     );
-    let restored_image = rounded_image.truncate(compressed_data.original_size);
-    ComplexImage::save_bitmap(&restored_image, output_file)?;
+    let entries = text
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | '[' | ']'))
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse::<u16>().ok().filter(|entry| *entry != 0))
+        .collect::<Option<Vec<u16>>>();
+    let Some(entries) = entries.filter(|entries| entries.len() == BLOCK_SIZE * BLOCK_SIZE) else {
+        return Err(invalid);
+    };
+    let mut table = [[0; BLOCK_SIZE]; BLOCK_SIZE];
+    for (row, entries) in table.iter_mut().zip(entries.chunks_exact(BLOCK_SIZE)) {
+        row.copy_from_slice(entries);
+    }
+    Ok(table)
+}
+
+fn compress_dct(
+    input: &[u8],
+    compression_level: f32,
+    tables: DctTables,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    if compression_level.partial_cmp(&0.) != Some(Ordering::Greater) {
+        return Err(CompressionError::InvalidParameter(
+            "compression must be greater than 0",
+        ));
+    }
+    let image = ComplexImage::from_image_bytes(input)?;
+    if image.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let channel_count = match image.is_grayscale() {
+        true => 1,
+        false => 3,
+    };
+    let alpha = image.alpha.as_ref().map(|alpha| {
+        alpha
+            .iter()
+            .map(|row| row.iter().map(|c| c.re).collect())
+            .collect()
+    });
+    let planes: Vec<Channel<f32>> = image
+        .to_ycbcr()
+        .into_iter()
+        .take(channel_count)
+        .chain(alpha)
+        .collect();
+    let mut values = Vec::new();
+    for (c, plane) in planes.iter().enumerate() {
+        let table = tables.table(c, channel_count);
+        values.extend(encode_blocks(plane, table, compression_level));
+        // Leave room for the final encoding step
+        progress((c + 1) as f32 / (planes.len() + 1) as f32);
+    }
+    let compressed_data = DctData {
+        coefficients: entropy::encode(&values)?,
+        compression_level,
+        tables,
+        channel_count,
+        alpha: image.alpha.is_some(),
+        original_size: image.size(),
+    };
+    let encoded = header::with_header(
+        BMP_DCT_MAGIC,
+        BMP_DCT_VERSION,
+        &bincode::serialize(&compressed_data)?,
+    );
+    progress(1.);
+    Ok(encoded)
+}
+
+/// Compress a .bmp file by the Haar wavelet transform (see [`wavelet::haar_2d`]), for later
+/// decompression using [`decompress_bmp`]. Sharp edges are blurred into blocks rather than
+/// ringing like they do with the FFT.
+///
+/// Each channel keeps as many of its largest coefficients as [`compress_bmp`] keeps at the
+/// same compression level, (width / level) * (height / level), and the rest are dropped.
+///
+/// Progress is reported as a fraction as each channel is transformed, and 1.0 when done.
+pub fn compress_bmp_wavelet(
+    bmp_file: &Path,
+    compressed_file: &Path,
+    compression_level: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(bmp_file)?;
+    let compressed = compress_bmp_wavelet_bytes(&input, compression_level, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .bmp file in memory, see [`compress_bmp_wavelet`].
+pub fn compress_bmp_wavelet_bytes(
+    input: &[u8],
+    compression_level: f32,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    validate_compression_level(compression_level)?;
+    let image = ComplexImage::from_image_bytes(input)?;
+    if image.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let channel_count = match image.is_grayscale() {
+        true => 1,
+        false => 3,
+    };
+    let (width, height) = image.size();
+    let kept = kept_size(width, compression_level) * kept_size(height, compression_level);
+    let channels = image.all_channels();
+    let planes: Vec<&ComplexChannel> = channels
+        .iter()
+        .take(channel_count)
+        .copied()
+        .chain(image.alpha.as_ref())
+        .collect();
+    // Leave room for the final encoding step
+    let total = planes.len() as f32 + 1.;
+    let coefficients: Vec<Vec<f32>> = planes
+        .iter()
+        .enumerate()
+        .map(|(c, plane)| {
+            let real: Channel<f32> = plane
+                .iter()
+                .map(|row| row.iter().map(|x| x.re).collect())
+                .collect();
+            let mut coefficients: Vec<f32> = wavelet::haar_2d(&real).concat();
+            // Drop what is no larger than the first coefficient past those kept
+            let mut magnitudes: Vec<f32> = coefficients.iter().map(|c| c.abs()).collect();
+            magnitudes.sort_by(|a, b| b.total_cmp(a));
+            if let Some(threshold) = magnitudes.get(kept) {
+                coefficients
+                    .iter_mut()
+                    .filter(|c| c.abs() <= *threshold)
+                    .for_each(|c| *c = 0.);
+            }
+            progress((c + 1) as f32 / total);
+            coefficients
+        })
+        .collect();
+    let peak = coefficients
+        .iter()
+        .flatten()
+        .map(|c| c.abs())
+        .fold(0., f32::max);
+    let quantization_step = match peak > 0. {
+        true => peak / i16::MAX as f32,
+        false => 1.,
+    };
+    let values: Vec<i16> = coefficients
+        .iter()
+        .flatten()
+        .map(|c| (c / quantization_step).round() as i16)
+        .collect();
+    let compressed_data = WaveletData {
+        coefficients: entropy::encode(&values)?,
+        quantization_step,
+        channel_count,
+        alpha: image.alpha.is_some(),
+        original_size: image.size(),
+    };
+    let encoded = header::with_header(
+        BMP_WAVELET_MAGIC,
+        BMP_WAVELET_VERSION,
+        &bincode::serialize(&compressed_data)?,
+    );
+    progress(1.);
+    Ok(encoded)
+}
+
+/// Decompress a .bmp file from [`compress_bmp`], [`compress_bmp_dct`] or
+/// [`compress_bmp_wavelet`].
+pub fn decompress_bmp(compressed_file: &Path, output_file: &Path) -> Result<(), CompressionError> {
+    let decompressed = decompress_bmp_bytes(&fs::read(compressed_file)?)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .bmp file in memory, see [`decompress_bmp`].
+pub fn decompress_bmp_bytes(compressed: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    decompress_image(compressed)?.to_bitmap_bytes()
+}
+
+/// Decompress a .bmp file like [`decompress_bmp`], writing it in the pixel format.
+pub fn decompress_bmp_with(
+    compressed_file: &Path,
+    output_file: &Path,
+    format: BmpFormat,
+) -> Result<(), CompressionError> {
+    let decompressed = decompress_bmp_bytes_with(&fs::read(compressed_file)?, format)?;
+    fs::write(output_file, decompressed)?;
     Ok(())
 }
 
+/// Decompress the contents of a compressed .bmp file in memory, see [`decompress_bmp_with`].
+pub fn decompress_bmp_bytes_with(
+    compressed: &[u8],
+    format: BmpFormat,
+) -> Result<Vec<u8>, CompressionError> {
+    decompress_image(compressed)?.to_bitmap_bytes_with(format)
+}
+
+/// Decompress an image from [`compress_bmp`] or [`compress_bmp_dct`] to a .png file.
+#[cfg(feature = "png")]
+pub fn decompress_png(compressed_file: &Path, output_file: &Path) -> Result<(), CompressionError> {
+    let decompressed = decompress_png_bytes(&fs::read(compressed_file)?)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed image in memory, see [`decompress_png`].
+#[cfg(feature = "png")]
+pub fn decompress_png_bytes(compressed: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    decompress_image(compressed)?.to_png_bytes()
+}
+
+/// Properties of an image compressed by [`compress_bmp`] or its variants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedInfo {
+    pub width: usize,
+    pub height: usize,
+    /// Number of color channels stored: 1 for grayscale images, otherwise 3
+    pub channel_count: usize,
+    pub alpha: bool,
+    /// Number of stored coefficients (complex for the FFT, real for the DCT and the nonzero ones
+    /// of the wavelet) in all channels
+    pub coefficient_count: usize,
+    /// Size of the square tiles, or None if the image was transformed whole
+    pub tile_size: Option<usize>,
+    /// Number of pixels each tile extends into its right and bottom neighbours
+    pub tile_overlap: usize,
+    /// Whether the image was compressed in blocks of the DCT, see [`compress_bmp_dct`]
+    pub dct: bool,
+    /// Whether the image was compressed by the Haar wavelet, see [`compress_bmp_wavelet`]
+    pub wavelet: bool,
+    /// Whether the original colors are restored exactly, see [`compress_bmp_lossless`]
+    pub lossless: bool,
+}
+
+/// Read the properties of a compressed image, without decompressing it.
+pub fn read_bmp_metadata(compressed_file: &Path) -> Result<CompressedInfo, CompressionError> {
+    read_bmp_metadata_bytes(&fs::read(compressed_file)?)
+}
+
+/// Read the properties of a compressed image in memory, see [`read_bmp_metadata`].
+pub fn read_bmp_metadata_bytes(compressed: &[u8]) -> Result<CompressedInfo, CompressionError> {
+    if compressed.starts_with(BMP_DCT_MAGIC) {
+        let encoded = header::strip_header(BMP_DCT_MAGIC, BMP_DCT_VERSION, compressed)?;
+        let compressed_data: DctData = bincode::deserialize(encoded)?;
+        let (width, height) = compressed_data.original_size;
+        let plane_count = compressed_data.channel_count + compressed_data.alpha as usize;
+        let block_count = width.div_ceil(BLOCK_SIZE) * height.div_ceil(BLOCK_SIZE);
+        return Ok(CompressedInfo {
+            width,
+            height,
+            channel_count: compressed_data.channel_count,
+            alpha: compressed_data.alpha,
+            coefficient_count: block_count * BLOCK_SIZE * BLOCK_SIZE * plane_count,
+            tile_size: None,
+            tile_overlap: 0,
+            dct: true,
+            wavelet: false,
+            lossless: false,
+        });
+    }
+    if compressed.starts_with(BMP_WAVELET_MAGIC) {
+        let encoded = header::strip_header(BMP_WAVELET_MAGIC, BMP_WAVELET_VERSION, compressed)?;
+        let compressed_data: WaveletData = bincode::deserialize(encoded)?;
+        let (width, height) = compressed_data.original_size;
+        let coefficient_count =
+            entropy::decode(&compressed_data.coefficients, compressed_data.value_count())?
+                .iter()
+                .filter(|value| **value != 0)
+                .count();
+        return Ok(CompressedInfo {
+            width,
+            height,
+            channel_count: compressed_data.channel_count,
+            alpha: compressed_data.alpha,
+            coefficient_count,
+            tile_size: None,
+            tile_overlap: 0,
+            dct: false,
+            wavelet: true,
+            lossless: false,
+        });
+    }
+    let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, compressed)?;
+    let compressed_data: CompressedData = bincode::deserialize(encoded)?;
+    let (width, height) = compressed_data.original_size;
+    let tile_count = tile_grid(
+        compressed_data.original_size,
+        compressed_data.tile_size,
+        compressed_data.tile_overlap,
+    )
+    .1
+    .len();
+    let plane_count = compressed_data.channel_sizes.len() / tile_count.max(1);
+    Ok(CompressedInfo {
+        width,
+        height,
+        channel_count: plane_count.saturating_sub(compressed_data.alpha as usize),
+        alpha: compressed_data.alpha,
+        coefficient_count: compressed_data
+            .channel_sizes
+            .iter()
+            .map(|(w, h)| w * h)
+            .sum(),
+        tile_size: compressed_data.tile_size,
+        tile_overlap: compressed_data.tile_overlap,
+        dct: false,
+        wavelet: false,
+        lossless: compressed_data.residual.is_some(),
+    })
+}
+
+fn decompress_image(compressed: &[u8]) -> Result<ComplexImage, CompressionError> {
+    if compressed.starts_with(BMP_DCT_MAGIC) {
+        return decompress_dct(compressed);
+    }
+    if compressed.starts_with(BMP_WAVELET_MAGIC) {
+        return decompress_wavelet(compressed);
+    }
+    let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, compressed)?;
+    let compressed_data: CompressedData = bincode::deserialize(encoded)?;
+    let (width, height) = compressed_data.original_size;
+    let (tile_size, overlap) = (compressed_data.tile_size, compressed_data.tile_overlap);
+    let (tile, origins) = tile_grid(compressed_data.original_size, tile_size, overlap);
+    // Each tile is restored as it is placed
+    let restored_tiles: Box<dyn Iterator<Item = Result<Vec<Channel<f32>>, FftError>>> =
+        match compressed_data.wide {
+            false => Box::new(compressed_data.tiles()?.into_iter().map(move |corners| {
+                corners
+                    .iter()
+                    .map(|channel| fft_2d_inverse_real(&fill_from_corners(channel, &tile)))
+                    .collect()
+            })),
+            true => Box::new(compressed_data.tiles64()?.into_iter().map(move |corners| {
+                corners
+                    .iter()
+                    .map(|channel| {
+                        let restored = fft_2d_inverse64(&fill_from_corners(channel, &tile))?;
+                        Ok(restored
+                            .iter()
+                            .map(|row| row.iter().map(|x| x.re as f32).collect())
+                            .collect())
+                    })
+                    .collect()
+            })),
+        };
+    let stride = tile_size.unwrap_or(usize::MAX);
+    let channel_count = 3 + compressed_data.alpha as usize;
+    let mut channels = vec![vec![vec![Complex32::default(); width]; height]; channel_count];
+    // Sum of the weights of the tiles covering each pixel, 1 unless weights are rounded
+    let mut weights = vec![vec![0.; width]; height];
+    for (restored_tile, (x, y)) in restored_tiles.zip(origins) {
+        let column_weights = crossfade(x, tile.0, (stride, overlap), width);
+        let row_weights = crossfade(y, tile.1, (stride, overlap), height);
+        // Drop what extends past the edges
+        let end = (x + tile.0).min(width);
+        for (row, row_weight) in weights[y..].iter_mut().zip(&row_weights) {
+            for (weight, column_weight) in row[x..end].iter_mut().zip(&column_weights) {
+                *weight += row_weight * column_weight;
+            }
+        }
+        for (channel, restored) in channels.iter_mut().zip(restored_tile?) {
+            for ((row, restored_row), row_weight) in
+                channel[y..].iter_mut().zip(restored).zip(&row_weights)
+            {
+                for ((value, restored), column_weight) in row[x..end]
+                    .iter_mut()
+                    .zip(restored_row)
+                    .zip(&column_weights)
+                {
+                    *value += Complex32::from(restored * row_weight * column_weight);
+                }
+            }
+        }
+    }
+    for channel in channels.iter_mut() {
+        for (row, weights) in channel.iter_mut().zip(&weights) {
+            for (value, weight) in row.iter_mut().zip(weights) {
+                *value /= *weight;
+            }
+        }
+    }
+    if dump::dumping() {
+        for (c, channel) in channels.iter().enumerate() {
+            let real: Channel<f32> = channel
+                .iter()
+                .map(|row| row.iter().map(|x| x.re).collect())
+                .collect();
+            dump::dump(&format!("bmp_restored_{c}"), &real[..])?;
+        }
+    }
+    let image = ComplexImage::from_iter(channels);
+    match &compressed_data.residual {
+        Some((color_count, residual)) => Ok(add_residual(&image, *color_count, residual)?),
+        None => Ok(image),
+    }
+}
+
+fn decompress_dct(compressed: &[u8]) -> Result<ComplexImage, CompressionError> {
+    let encoded = header::strip_header(BMP_DCT_MAGIC, BMP_DCT_VERSION, compressed)?;
+    let compressed_data: DctData = bincode::deserialize(encoded)?;
+    let (width, height) = compressed_data.original_size;
+    let block_values = width.div_ceil(BLOCK_SIZE) * height.div_ceil(BLOCK_SIZE) * 64;
+    let channel_count = compressed_data.channel_count;
+    let plane_count = channel_count + compressed_data.alpha as usize;
+    let values = entropy::decode(&compressed_data.coefficients, block_values * plane_count)?;
+    if !(1..=3).contains(&channel_count) || values.len() != block_values * plane_count {
+        return Err(EntropyError::Malformed.into());
+    }
+    let mut planes: Vec<Channel<f32>> = values
+        .chunks_exact(block_values.max(1))
+        .enumerate()
+        .map(|(c, values)| {
+            let table = compressed_data.tables.table(c, channel_count);
+            decode_blocks(
+                values,
+                compressed_data.original_size,
+                table,
+                compressed_data.compression_level,
+            )
+        })
+        .collect();
+    let alpha = match compressed_data.alpha {
+        true => planes.pop(),
+        false => None,
+    };
+    let mut planes = planes.into_iter();
+    let luminance = planes.next().unwrap_or_default();
+    // Grayscale images have neutral chrominance
+    let neutral = vec![vec![128.; width]; height];
+    let blue_difference = planes.next().unwrap_or_else(|| neutral.clone());
+    let red_difference = planes.next().unwrap_or(neutral);
+    let alpha = alpha.map(|alpha| {
+        alpha
+            .iter()
+            .map(|row| row.iter().map(|a| Complex32::from(*a)).collect())
+            .collect()
+    });
+    Ok(ComplexImage::from_ycbcr([luminance, blue_difference, red_difference]).with_alpha(alpha))
+}
+
+fn decompress_wavelet(compressed: &[u8]) -> Result<ComplexImage, CompressionError> {
+    let encoded = header::strip_header(BMP_WAVELET_MAGIC, BMP_WAVELET_VERSION, compressed)?;
+    let compressed_data: WaveletData = bincode::deserialize(encoded)?;
+    let (width, height) = compressed_data.original_size;
+    let (padded_width, padded_height) = compressed_data.padded_size();
+    let channel_count = compressed_data.channel_count;
+    let plane_count = channel_count + compressed_data.alpha as usize;
+    let plane_size = padded_width * padded_height;
+    let values = entropy::decode(&compressed_data.coefficients, compressed_data.value_count())?;
+    if !(1..=3).contains(&channel_count) || values.len() != plane_size * plane_count {
+        return Err(EntropyError::Malformed.into());
+    }
+    let mut planes: Vec<ComplexChannel> = values
+        .chunks_exact(plane_size.max(1))
+        .map(|values| {
+            let coefficients: Channel<f32> = values
+                .chunks_exact(padded_width)
+                .map(|row| {
+                    row.iter()
+                        .map(|x| *x as f32 * compressed_data.quantization_step)
+                        .collect()
+                })
+                .collect();
+            // Drop the padding
+            wavelet::haar_2d_inverse(&coefficients)[..height]
+                .iter()
+                .map(|row| row[..width].iter().map(|x| Complex32::from(*x)).collect())
+                .collect()
+        })
+        .collect();
+    let alpha = match compressed_data.alpha {
+        true => planes.pop(),
+        false => None,
+    };
+    if planes.len() == 1 {
+        planes = vec![planes[0].clone(); 3];
+    }
+    let [red, green, blue]: [ComplexChannel; 3] =
+        planes.try_into().map_err(|_| EntropyError::Malformed)?;
+    Ok(ComplexImage::new(red, green, blue).with_alpha(alpha))
+}
+
+/// Plot an image and its frequencies, whose magnitudes (relative to the largest) are raised to
+/// the power of `log_factor` to show the small ones.
 pub fn analyze_image(
-    filepath: &PathBuf,
+    filepath: &Path,
     log_factor: f32,
-    output_dir: &PathBuf,
-) -> Result<PathBuf, BoxedError> {
-    println!("Analyzing {filepath:?}... ");
-    let image = ComplexImage::from_bitmap(filepath)?.round_up();
+    output_dir: &Path,
+    config: PlotConfig,
+) -> Result<PathBuf, CompressionError> {
+    if !(log_factor.is_finite() && log_factor > 0.) {
+        return Err(CompressionError::InvalidParameter(
+            "log factor must be positive and finite",
+        ));
+    }
+    let image = ComplexImage::from_image(filepath)?.round_up();
     let horizontal = ComplexImage::new(
-        fft_2d_horizontal(&image.red),
-        fft_2d_horizontal(&image.green),
-        fft_2d_horizontal(&image.blue),
+        fft_2d_horizontal(&image.red)?,
+        fft_2d_horizontal(&image.green)?,
+        fft_2d_horizontal(&image.blue)?,
     );
     let vertical = ComplexImage::new(
-        fft_2d_vertical(&image.red),
-        fft_2d_vertical(&image.green),
-        fft_2d_vertical(&image.blue),
+        fft_2d_vertical(&image.red)?,
+        fft_2d_vertical(&image.green)?,
+        fft_2d_vertical(&image.blue)?,
     );
     let transformed = ComplexImage::new(
-        fft_2d_vertical(&horizontal.red),
-        fft_2d_vertical(&horizontal.green),
-        fft_2d_vertical(&horizontal.blue),
+        fft_2d_vertical(&horizontal.red)?,
+        fft_2d_vertical(&horizontal.green)?,
+        fft_2d_vertical(&horizontal.blue)?,
     );
     // Plot
     let layout = Layout::new()
@@ -97,9 +1059,8 @@ pub fn analyze_image(
                 .rows(1)
                 .pattern(GridPattern::Independent),
         )
-        .title(Title::new(&filepath.to_string_lossy()))
-        .width(1900)
-        .height(900);
+        .title(Title::new(&filepath.to_string_lossy()));
+    let layout = config.apply(layout, 1900, 900);
     let mut plot = Plot::new();
     plot.set_layout(layout);
     plot.add_trace(
@@ -127,21 +1088,34 @@ pub fn analyze_image(
             .y_axis("y4"),
     );
     // Write to file
-    let output_path = output_dir.join("analysis.html");
+    let output_path = plotting::analysis_path(filepath, output_dir);
     plot.write_html(&output_path);
     Ok(output_path)
 }
 
-type BoxedError = Box<dyn std::error::Error>;
-type Channel<T> = Vec<Vec<T>>;
-type ComplexChannel = Channel<Complex32>;
-type RawChannel = Channel<(f32, f32)>;
+/// Rows of values in a single channel of an image.
+pub type Channel<T> = Vec<Vec<T>>;
+pub type ComplexChannel = Channel<Complex32>;
+
+/// Pixel format of a written .bmp file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum BmpFormat {
+    /// 24-bit color
+    #[default]
+    Rgb24,
+    /// 8-bit grayscale, the luminance of colored images
+    Gray8,
+}
 
+/// An image as separate red, green and blue channels of complex values.
 #[derive(Clone)]
-struct ComplexImage {
+pub struct ComplexImage {
     pub red: ComplexChannel,
     pub green: ComplexChannel,
     pub blue: ComplexChannel,
+    /// Opacity, for images with transparency
+    pub alpha: Option<ComplexChannel>,
 }
 
 impl Debug for ComplexImage {
@@ -152,7 +1126,17 @@ impl Debug for ComplexImage {
 
 impl ComplexImage {
     pub fn new(red: ComplexChannel, green: ComplexChannel, blue: ComplexChannel) -> ComplexImage {
-        ComplexImage { red, green, blue }
+        ComplexImage {
+            red,
+            green,
+            blue,
+            alpha: None,
+        }
+    }
+
+    /// Add (or with None, remove) the alpha channel.
+    pub fn with_alpha(self, alpha: Option<ComplexChannel>) -> ComplexImage {
+        ComplexImage { alpha, ..self }
     }
 
     pub fn size(&self) -> (usize, usize) {
@@ -160,12 +1144,10 @@ impl ComplexImage {
     }
 
     pub fn width(&self) -> usize {
-        if self.red.is_empty() {
-            return 0;
-        }
-        assert_eq!(self.red[0].len(), self.green[0].len());
-        assert_eq!(self.red[0].len(), self.blue[0].len());
-        self.red[0].len()
+        let width = self.red.first().map_or(0, Vec::len);
+        assert_eq!(width, self.green.first().map_or(0, Vec::len));
+        assert_eq!(width, self.blue.first().map_or(0, Vec::len));
+        width
     }
 
     pub fn height(&self) -> usize {
@@ -174,35 +1156,55 @@ impl ComplexImage {
         self.red.len()
     }
 
+    /// Whether the image has no pixels, being zero in width or height.
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
     pub fn round_up(&self) -> Self {
         let new_width = 2f64.powf((self.width() as f64).log2().ceil()) as usize;
         let new_height = 2f64.powf((self.height() as f64).log2().ceil()) as usize;
         let extra_width = new_width - self.width();
         let extra_height = new_height - self.height();
-        Self::from_iter(self.channels().iter().map(|channel| {
-            let mut new_channel = channel.clone().to_owned();
+        Self::from_iter(self.all_channels().iter().map(|channel| {
+            let mut new_channel = (*channel).clone();
             new_channel
                 .iter_mut()
-                .map(|row| row.extend(vec![Complex32::default(); extra_width]))
-                .for_each(drop);
+                .for_each(|row| row.extend(vec![Complex32::default(); extra_width]));
             new_channel.extend(vec![vec![Complex32::default(); new_width]; extra_height]);
             new_channel
         }))
     }
 
-    pub fn truncate(&self, new_size: (usize, usize)) -> Self {
-        Self::from_iter(self.channels().iter().map(|channel| {
-            channel[..new_size.1]
-                .iter()
-                .map(|row| row[..new_size.0].to_vec())
-                .collect()
-        }))
+    /// Load a .bmp file, or a .png file with the `png` feature.
+    pub fn from_image(filepath: &Path) -> Result<ComplexImage, CompressionError> {
+        Self::from_image_bytes(&fs::read(filepath)?)
+    }
+
+    /// Load the contents of a .bmp file, or a .png file with the `png` feature.
+    pub fn from_image_bytes(bytes: &[u8]) -> Result<ComplexImage, CompressionError> {
+        if !bytes.starts_with(PNG_MAGIC) {
+            return Self::from_bitmap_bytes(bytes);
+        }
+        #[cfg(feature = "png")]
+        return Self::from_png_bytes(bytes);
+        #[cfg(not(feature = "png"))]
+        Err(crate::format::FormatError::UnsupportedFormat.into())
+    }
+
+    pub fn from_bitmap(filepath: &Path) -> Result<ComplexImage, CompressionError> {
+        Self::from_bitmap_bytes(&fs::read(filepath)?)
     }
 
-    pub fn from_bitmap(filepath: &PathBuf) -> Result<ComplexImage, BoxedError> {
-        let bmp_data = bmp::open(filepath)?;
+    /// Load the contents of a .bmp file, with the top row first whichever order the rows are
+    /// stored in.
+    pub fn from_bitmap_bytes(bytes: &[u8]) -> Result<ComplexImage, CompressionError> {
+        let bmp_data = bmp::from_reader(&mut Cursor::new(bytes))?;
         let width = bmp_data.get_width() as usize;
         let height = bmp_data.get_height() as usize;
+        // The bmp crate assumes rows stored bottom to top, but a negative height in the info
+        // header (after the file header it has already read) means top to bottom
+        let top_down = i32::from_le_bytes(bytes[22..26].try_into().unwrap()) < 0;
         let mut red = Vec::with_capacity(height);
         let mut green = Vec::with_capacity(height);
         let mut blue = Vec::with_capacity(height);
@@ -210,8 +1212,9 @@ impl ComplexImage {
             let mut r_row = Vec::with_capacity(width);
             let mut g_row = Vec::with_capacity(width);
             let mut b_row = Vec::with_capacity(width);
+            let stored_y = if top_down { height - 1 - y } else { y };
             for x in 0..width {
-                let pix = bmp_data.get_pixel(x as u32, y as u32);
+                let pix = bmp_data.get_pixel(x as u32, stored_y as u32);
                 r_row.push(Complex32::from(pix.r as f32));
                 g_row.push(Complex32::from(pix.g as f32));
                 b_row.push(Complex32::from(pix.b as f32));
@@ -223,8 +1226,28 @@ impl ComplexImage {
         Ok(ComplexImage::new(red, green, blue))
     }
 
-    pub fn save_bitmap(&self, filepath: &PathBuf) -> Result<(), BoxedError> {
-        let (width, height) = (self.red[0].len(), self.red.len());
+    pub fn to_bitmap_bytes(&self) -> Result<Vec<u8>, CompressionError> {
+        self.to_bitmap_bytes_with(BmpFormat::Rgb24)
+    }
+
+    /// Write a .bmp file in the pixel format.
+    pub fn save_bitmap_with(
+        &self,
+        filepath: &Path,
+        format: BmpFormat,
+    ) -> Result<(), CompressionError> {
+        fs::write(filepath, self.to_bitmap_bytes_with(format)?)?;
+        Ok(())
+    }
+
+    /// Encode the contents of a .bmp file in the pixel format, see [`Self::save_bitmap_with`].
+    ///
+    /// Rows are always stored bottom to top, with a positive height, which all readers support.
+    pub fn to_bitmap_bytes_with(&self, format: BmpFormat) -> Result<Vec<u8>, CompressionError> {
+        if format == BmpFormat::Gray8 {
+            return Ok(self.to_gray_bitmap_bytes());
+        }
+        let (width, height) = self.size();
         let mut bmp_image = bmp::Image::new(width as u32, height as u32);
         for y in 0..height {
             for x in 0..width {
@@ -232,85 +1255,204 @@ impl ComplexImage {
                     x as u32,
                     y as u32,
                     bmp::Pixel::new(
-                        (self.red[y][x].norm()) as u8,
-                        (self.green[y][x].norm()) as u8,
-                        (self.blue[y][x].norm()) as u8,
+                        to_color_value(self.red[y][x]),
+                        to_color_value(self.green[y][x]),
+                        to_color_value(self.blue[y][x]),
                     ),
                 );
             }
         }
-        bmp_image.save(filepath)?;
+        let mut bytes = Vec::new();
+        bmp_image.to_writer(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Encode an 8-bit .bmp file, with a palette of the 256 gray levels.
+    fn to_gray_bitmap_bytes(&self) -> Vec<u8> {
+        const HEADERS_SIZE: usize = 14 + 40;
+        const PALETTE_SIZE: usize = 256 * 4;
+        let (width, height) = self.size();
+        // Rows are padded to a multiple of 4 bytes
+        let row_size = width.div_ceil(4) * 4;
+        let data_offset = HEADERS_SIZE + PALETTE_SIZE;
+        let file_size = data_offset + row_size * height;
+        let mut bytes = Vec::with_capacity(file_size);
+        // File header
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&(file_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(data_offset as u32).to_le_bytes());
+        // Info header: size, width, height, planes, bits per pixel, no compression, image size,
+        // resolution (2835 pixels per meter is 72 DPI), palette size and important colors
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&(width as i32).to_le_bytes());
+        bytes.extend_from_slice(&(height as i32).to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8u16.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&((row_size * height) as u32).to_le_bytes());
+        bytes.extend_from_slice(&2835i32.to_le_bytes());
+        bytes.extend_from_slice(&2835i32.to_le_bytes());
+        bytes.extend_from_slice(&256u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        // Palette entries are blue, green, red and a reserved byte
+        bytes.extend((0..=255u8).flat_map(|gray| [gray, gray, gray, 0]));
+        // Rows are stored bottom to top
+        for y in (0..height).rev() {
+            bytes.extend((0..width).map(|x| {
+                let [r, g, b] = self.channels().map(|channel| to_color_value(channel[y][x]));
+                let luminance = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+                luminance.round() as u8
+            }));
+            bytes.extend(vec![0; row_size - width]);
+        }
+        bytes
+    }
+
+    #[cfg(feature = "png")]
+    pub fn from_png(filepath: &Path) -> Result<ComplexImage, CompressionError> {
+        Self::from_png_bytes(&fs::read(filepath)?)
+    }
+
+    #[cfg(feature = "png")]
+    pub fn from_png_bytes(bytes: &[u8]) -> Result<ComplexImage, CompressionError> {
+        let png_image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)?;
+        let has_alpha = png_image.color().has_alpha();
+        let png_image = png_image.to_rgba8();
+        let channel = |c: usize| -> ComplexChannel {
+            png_image
+                .rows()
+                .map(|row| row.map(|pix| Complex32::from(pix.0[c] as f32)).collect())
+                .collect()
+        };
+        let image = ComplexImage::new(channel(0), channel(1), channel(2));
+        Ok(image.with_alpha(has_alpha.then(|| channel(3))))
+    }
+
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, filepath: &Path) -> Result<(), CompressionError> {
+        fs::write(filepath, self.to_png_bytes()?)?;
         Ok(())
     }
 
-    /// Returns a new ComplexImage containing only the corners of this image.
-    /// Returns an error if the new_width or new_height are larger than the current width and height.
-    fn corners(&self, new_width: usize, new_height: usize) -> Result<Self, ()> {
-        if new_width >= self.width() || new_height >= self.height() {
+    #[cfg(feature = "png")]
+    pub fn to_png_bytes(&self) -> Result<Vec<u8>, CompressionError> {
+        let (width, height) = self.size();
+        let (width, height) = (width as u32, height as u32);
+        let color = |x: u32, y: u32| {
+            let (x, y) = (x as usize, y as usize);
+            self.channels().map(|channel| to_color_value(channel[y][x]))
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        match &self.alpha {
+            Some(alpha) => image::RgbaImage::from_fn(width, height, |x, y| {
+                let [r, g, b] = color(x, y);
+                image::Rgba([r, g, b, to_color_value(alpha[y as usize][x as usize])])
+            })
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)?,
+            None => image::RgbImage::from_fn(width, height, |x, y| image::Rgb(color(x, y)))
+                .write_to(&mut bytes, image::ImageOutputFormat::Png)?,
+        }
+        Ok(bytes.into_inner())
+    }
+
+    /// The part of the image at an origin (x, y) of a size (width, height), repeating the last
+    /// row and column past the edges.
+    fn crop(&self, (x, y): (usize, usize), (width, height): (usize, usize)) -> Self {
+        let (last_x, last_y) = (self.width() - 1, self.height() - 1);
+        ComplexImage::from_iter(self.all_channels().iter().map(|channel| {
+            (y..y + height)
+                .map(|j| {
+                    let row = &channel[j.min(last_y)];
+                    (x..x + width).map(|i| row[i.min(last_x)]).collect()
+                })
+                .collect()
+        }))
+    }
+
+    /// Returns the corners of each channel of this image, sized (width, height) per channel.
+    /// Returns an error if a new width or height is larger than the current width and height.
+    ///
+    /// Channels are in the order of [`Self::all_channels`], extra sizes are ignored.
+    fn corners(&self, sizes: &[(usize, usize)]) -> Result<Vec<ComplexChannel>, ()> {
+        if sizes
+            .iter()
+            .any(|(new_width, new_height)| *new_width > self.width() || *new_height > self.height())
+        {
             return Err(());
         }
-        let corner_width = new_width / 2;
-        let corner_height = new_height / 2;
-        let channels = self.channels();
-        let new_channels = channels
+        Ok(self
+            .all_channels()
             .iter()
-            .map(|c| self.channel_corners(c, &corner_width, &corner_height));
-        Ok(Self::from_iter(new_channels))
+            .zip(sizes)
+            .map(|(channel, (new_width, new_height))| {
+                channel_corners(channel, *new_width, *new_height)
+            })
+            .collect())
     }
 
-    fn channel_corners(
-        &self,
-        channel: &ComplexChannel,
-        corner_width: &usize,
-        corner_height: &usize,
-    ) -> ComplexChannel {
-        let inverse_width = self.width() - corner_width;
-        let inverse_height = self.height() - corner_height;
-        let vert_slice =
-            (0usize..corner_height.clone()).chain(inverse_height.clone()..self.height());
-        let mut new_channel = ComplexChannel::new();
-        for y in vert_slice {
-            let mut row: Vec<Complex32> = Vec::with_capacity(corner_width * 2);
-            row.extend_from_slice(&channel[y][..corner_width.clone()]);
-            row.extend_from_slice(&channel[y][inverse_width.clone()..self.width()]);
-            new_channel.push(row);
-        }
-        new_channel
-    }
-
-    fn from_corners(&self, original_size: &(usize, usize)) -> Self {
-        ComplexImage::from_iter(
-            self.channels()
+    /// Convert to luminance, blue-difference and red-difference planes (as in JPEG).
+    fn to_ycbcr(&self) -> [Channel<f32>; 3] {
+        let convert = |weights: [f32; 3], offset: f32| -> Channel<f32> {
+            (0..self.height())
+                .map(|y| {
+                    (0..self.width())
+                        .map(|x| {
+                            let [r, g, b] = self.channels().map(|channel| channel[y][x].re);
+                            offset + weights[0] * r + weights[1] * g + weights[2] * b
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        [
+            convert([0.299, 0.587, 0.114], 0.),
+            convert([-0.168736, -0.331264, 0.5], 128.),
+            convert([0.5, -0.418688, -0.081312], 128.),
+        ]
+    }
+
+    /// Convert from luminance, blue-difference and red-difference planes, see [`Self::to_ycbcr`].
+    fn from_ycbcr(planes: [Channel<f32>; 3]) -> Self {
+        let [luminance, blue_difference, red_difference] = planes;
+        let convert = |weights: [f32; 2]| -> ComplexChannel {
+            luminance
                 .iter()
-                .map(|channel| self.from_channel_corners(channel, &original_size)),
+                .zip(blue_difference.iter().zip(&red_difference))
+                .map(|(l, (cb, cr))| {
+                    l.iter()
+                        .zip(cb.iter().zip(cr))
+                        .map(|(l, (cb, cr))| {
+                            let value = l + weights[0] * (cb - 128.) + weights[1] * (cr - 128.);
+                            Complex32::from(value)
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        ComplexImage::new(
+            convert([0., 1.402]),
+            convert([-0.344136, -0.714136]),
+            convert([1.772, 0.]),
         )
     }
 
-    fn from_channel_corners(
-        &self,
-        channel: &ComplexChannel,
-        original_size: &(usize, usize),
-    ) -> ComplexChannel {
-        let mid_width = self.size().0 / 2;
-        let mid_height = self.size().1 / 2;
-        let missing_width = original_size.0 - self.size().0;
-        let missing_height = original_size.1 - self.size().1;
-        let pad_width = vec![Complex32::default(); missing_width];
-        let pad_height = vec![vec![Complex32::default(); original_size.0]; missing_height];
-        let mut new_channel = channel.clone();
-        new_channel
-            .iter_mut()
-            .map(|row| {
-                row.splice(mid_width..mid_width, pad_width.clone());
-            })
-            .for_each(drop);
-        new_channel.splice(mid_height..mid_height, pad_height);
-        new_channel
+    /// Whether the red, green and blue channels are identical.
+    pub fn is_grayscale(&self) -> bool {
+        self.red == self.green && self.red == self.blue
     }
 
     pub fn channels(&self) -> [&ComplexChannel; 3] {
         [&self.red, &self.green, &self.blue]
     }
+
+    /// The red, green and blue channels, followed by the alpha channel if present.
+    pub fn all_channels(&self) -> Vec<&ComplexChannel> {
+        self.channels()
+            .into_iter()
+            .chain(self.alpha.as_ref())
+            .collect()
+    }
 }
 
 impl FromIterator<ComplexChannel> for ComplexImage {
@@ -321,98 +1463,431 @@ impl FromIterator<ComplexChannel> for ComplexImage {
             iter.next().expect("expected green channel"),
             iter.next().expect("expected blue channel"),
         )
+        .with_alpha(iter.next())
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct CompressedData {
-    red: RawChannel,
-    green: RawChannel,
-    blue: RawChannel,
-    transformed_size: (usize, usize),
+    /// Entropy coded (quantized) corners of the red, green and blue channels, or of a single
+    /// channel for grayscale images, followed by the alpha channel if present, of each tile
+    coefficients: Vec<u8>,
+    /// Value of a single quantized unit of the coefficients
+    quantization_step: f32,
+    /// Size of the corners of each channel, which may be compressed at different levels
+    channel_sizes: Vec<(usize, usize)>,
+    /// Whether the last channel (of each tile) is the alpha channel
+    alpha: bool,
     original_size: (usize, usize),
+    /// Size of the square tiles, row by row, or None if the image was transformed whole
+    tile_size: Option<usize>,
+    /// Number of pixels each tile extends into its right and bottom neighbours
+    tile_overlap: usize,
+    /// Number of color channels of the original image (see [`color_count`]) and the entropy
+    /// coded difference between its colors and the decompressed ones, of the channels of
+    /// [`residual_channels`], if compressed losslessly
+    residual: Option<(usize, Vec<u8>)>,
+    /// Whether the coefficients are quantized to 32 rather than 16 bits and restored in double
+    /// precision, see `compress_bmp_hp`
+    wide: bool,
 }
 
 impl CompressedData {
+    /// Quantize the corners of the channels of each tile, see [`transform_corners`].
     pub fn new(
-        red: RawChannel,
-        green: RawChannel,
-        blue: RawChannel,
-        transformed_size: (usize, usize),
+        tiles: &[Vec<ComplexChannel>],
+        alpha: bool,
         original_size: (usize, usize),
-    ) -> Self {
-        CompressedData {
-            red,
-            green,
-            blue,
-            transformed_size,
+        tile_size: Option<usize>,
+    ) -> Result<Self, EntropyError> {
+        let corners: Vec<&ComplexChannel> = tiles.iter().flatten().collect();
+        let coefficients = || corners.iter().copied().flatten().flatten();
+        let peak = coefficients()
+            .map(|c| c.re.abs().max(c.im.abs()))
+            .fold(0., f32::max);
+        let quantization_step = match peak > 0. {
+            true => peak / i16::MAX as f32,
+            false => 1.,
+        };
+        let values: Vec<i16> = coefficients()
+            .flat_map(|c| [c.re, c.im])
+            .map(|value| (value / quantization_step).round() as i16)
+            .collect();
+        Ok(CompressedData {
+            coefficients: entropy::encode(&values)?,
+            quantization_step,
+            channel_sizes: corners
+                .iter()
+                .map(|channel| channel_size(channel))
+                .collect(),
+            alpha,
             original_size,
+            tile_size,
+            tile_overlap: 0,
+            residual: None,
+            wide: false,
+        })
+    }
+
+    /// Quantize the double precision corners of the channels of a whole image to 32 bits.
+    #[cfg(feature = "high-precision")]
+    fn new_wide(
+        corners: &[Channel<Complex64>],
+        alpha: bool,
+        original_size: (usize, usize),
+    ) -> Result<Self, EntropyError> {
+        let coefficients = || corners.iter().flatten().flatten();
+        let peak = coefficients()
+            .map(|c| c.re.abs().max(c.im.abs()))
+            .fold(0., f64::max);
+        let quantization_step = match peak > 0. {
+            true => (peak / i32::MAX as f64) as f32,
+            false => 1.,
+        };
+        let values: Vec<i32> = coefficients()
+            .flat_map(|c| [c.re, c.im])
+            .map(|value| (value / quantization_step as f64).round() as i32)
+            .collect();
+        Ok(CompressedData {
+            coefficients: entropy::encode_wide(&values)?,
+            quantization_step,
+            channel_sizes: corners.iter().map(channel_size).collect(),
+            alpha,
+            original_size,
+            tile_size: None,
+            tile_overlap: 0,
+            residual: None,
+            wide: true,
+        })
+    }
+
+    /// Decode the (dequantized) corners of the red, green and blue channels (repeating a
+    /// grayscale channel), followed by the alpha channel if present, of each tile.
+    pub fn tiles(&self) -> Result<Vec<Vec<ComplexChannel>>, EntropyError> {
+        let step = self.quantization_step;
+        self.decode_tiles(|re, im| Complex32::new(re as f32, im as f32) * step)
+    }
+
+    /// Decode the corners of each tile like [`Self::tiles`], dequantized in double precision.
+    pub fn tiles64(&self) -> Result<Vec<Vec<Channel<Complex64>>>, EntropyError> {
+        let step = self.quantization_step as f64;
+        self.decode_tiles(|re, im| Complex64::new(re as f64, im as f64) * step)
+    }
+
+    fn decode_tiles<T: Clone>(
+        &self,
+        dequantize: impl Fn(i32, i32) -> T,
+    ) -> Result<Vec<Vec<Channel<T>>>, EntropyError> {
+        if self.tile_size == Some(0) {
+            return Err(EntropyError::Malformed);
         }
+        let value_count: usize = self.channel_sizes.iter().map(|(w, h)| w * h * 2).sum();
+        let values: Vec<i32> = match self.wide {
+            true => entropy::decode_wide(&self.coefficients, value_count)?,
+            false => entropy::decode(&self.coefficients, value_count)?
+                .into_iter()
+                .map(i32::from)
+                .collect(),
+        };
+        if values.len() != value_count {
+            return Err(EntropyError::Malformed);
+        }
+        let mut coefficients = values
+            .chunks_exact(2)
+            .map(|pair| dequantize(pair[0], pair[1]));
+        let channels: Vec<Channel<T>> = self
+            .channel_sizes
+            .iter()
+            .map(|(width, height)| {
+                (0..*height)
+                    .map(|_| coefficients.by_ref().take(*width).collect())
+                    .collect()
+            })
+            .collect();
+        let tile_count = tile_grid(self.original_size, self.tile_size, self.tile_overlap)
+            .1
+            .len();
+        if tile_count == 0 || !channels.len().is_multiple_of(tile_count) {
+            return Err(EntropyError::Malformed);
+        }
+        channels
+            .chunks(channels.len() / tile_count)
+            .map(|tile| {
+                let mut channels = tile.to_vec();
+                let alpha = match self.alpha {
+                    true => Some(channels.pop().ok_or(EntropyError::Malformed)?),
+                    false => None,
+                };
+                if channels.len() == 1 {
+                    channels = vec![channels[0].clone(); 3];
+                }
+                if channels.len() != 3 {
+                    return Err(EntropyError::Malformed);
+                }
+                channels.extend(alpha);
+                Ok(channels)
+            })
+            .collect()
     }
+}
 
-    pub fn width(&self) -> usize {
-        if self.red.is_empty() {
-            return 0;
+/// Image compressed by [`compress_bmp_dct`].
+#[derive(Serialize, Deserialize)]
+struct DctData {
+    /// Entropy coded (quantized) blocks of the luminance and chrominance planes, or of the
+    /// luminance alone for grayscale images, and of the alpha channel if present, each in
+    /// zigzag order
+    coefficients: Vec<u8>,
+    /// Scale of the quantization tables
+    compression_level: f32,
+    tables: DctTables,
+    channel_count: usize,
+    /// Whether the alpha channel follows the luminance and chrominance planes
+    alpha: bool,
+    original_size: (usize, usize),
+}
+
+/// Image compressed by [`compress_bmp_wavelet`].
+#[derive(Serialize, Deserialize)]
+struct WaveletData {
+    /// Entropy coded (quantized) coefficients of the red, green and blue channels, or of a
+    /// single channel for grayscale images, followed by the alpha channel if present, each
+    /// padded to powers of two, row by row
+    coefficients: Vec<u8>,
+    /// Value of a single quantized unit of the coefficients
+    quantization_step: f32,
+    channel_count: usize,
+    alpha: bool,
+    original_size: (usize, usize),
+}
+
+impl WaveletData {
+    /// Size of the transformed channels, see [`wavelet::haar_2d`].
+    fn padded_size(&self) -> (usize, usize) {
+        let (width, height) = self.original_size;
+        (width.next_power_of_two(), height.next_power_of_two())
+    }
+
+    /// Number of coefficients of all the transformed channels.
+    fn value_count(&self) -> usize {
+        let (width, height) = self.padded_size();
+        width * height * (self.channel_count + self.alpha as usize)
+    }
+}
+
+/// Quantization tables of the planes of an image compressed in blocks of the DCT.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum DctTables {
+    /// The standard JPEG tables, see [`quantization_table`]
+    Jpeg,
+    /// The same table for every plane
+    Custom(QuantizationTable),
+}
+
+impl DctTables {
+    /// Table of a plane, given the number of luminance and chrominance planes before alpha.
+    fn table(&self, plane: usize, channel_count: usize) -> &QuantizationTable {
+        match self {
+            DctTables::Custom(table) => table,
+            DctTables::Jpeg if plane < channel_count => quantization_table(plane),
+            // The alpha channel is quantized like luminance
+            DctTables::Jpeg => &LUMINANCE_QUANTIZATION,
         }
-        assert_eq!(self.red[0].len(), self.green[0].len());
-        assert_eq!(self.red[0].len(), self.blue[0].len());
-        self.red[0].len()
     }
+}
 
-    pub fn height(&self) -> usize {
-        assert_eq!(self.red.len(), self.green.len());
-        assert_eq!(self.red.len(), self.blue.len());
-        self.red.len()
+/// Quantization table of a luminance (first) or chrominance plane.
+fn quantization_table(plane: usize) -> &'static QuantizationTable {
+    match plane {
+        0 => &LUMINANCE_QUANTIZATION,
+        _ => &CHROMINANCE_QUANTIZATION,
     }
 }
 
+/// Transform and quantize each block of a plane, row by row.
+///
+/// Blocks extending past the edges repeat the last row and column.
+fn encode_blocks(
+    plane: &Channel<f32>,
+    table: &QuantizationTable,
+    compression_level: f32,
+) -> Vec<i16> {
+    let (width, height) = (plane.first().map_or(0, Vec::len), plane.len());
+    let zigzag = dct::zigzag();
+    let mut values = Vec::new();
+    for block_y in (0..height).step_by(BLOCK_SIZE) {
+        for block_x in (0..width).step_by(BLOCK_SIZE) {
+            let mut block: Block = [[0.; BLOCK_SIZE]; BLOCK_SIZE];
+            for (y, row) in block.iter_mut().enumerate() {
+                let source = &plane[(block_y + y).min(height - 1)];
+                for (x, sample) in row.iter_mut().enumerate() {
+                    // Center the values around zero
+                    *sample = source[(block_x + x).min(width - 1)] - 128.;
+                }
+            }
+            let coefficients = dct::dct2_8x8(&block);
+            values.extend(zigzag.iter().map(|&(v, u)| {
+                let step = table[v][u] as f32 * compression_level;
+                (coefficients[v][u] / step).round() as i16
+            }));
+        }
+    }
+    values
+}
+
+/// Dequantize and inverse transform each block of a plane, see [`encode_blocks`].
+fn decode_blocks(
+    values: &[i16],
+    (width, height): (usize, usize),
+    table: &QuantizationTable,
+    compression_level: f32,
+) -> Channel<f32> {
+    let zigzag = dct::zigzag();
+    let mut plane = vec![vec![0.; width]; height];
+    let mut blocks = values.chunks_exact(BLOCK_SIZE * BLOCK_SIZE);
+    for block_y in (0..height).step_by(BLOCK_SIZE) {
+        for block_x in (0..width).step_by(BLOCK_SIZE) {
+            let mut coefficients: Block = [[0.; BLOCK_SIZE]; BLOCK_SIZE];
+            let block = blocks.next().expect("value count was validated");
+            for (&(v, u), value) in zigzag.iter().zip(block) {
+                coefficients[v][u] = *value as f32 * table[v][u] as f32 * compression_level;
+            }
+            let block = dct::idct2_8x8(&coefficients);
+            for (y, row) in block.iter().enumerate().take(height - block_y) {
+                for (x, sample) in row.iter().enumerate().take(width - block_x) {
+                    plane[block_y + y][block_x + x] = sample + 128.;
+                }
+            }
+        }
+    }
+    plane
+}
+
 impl Debug for CompressedData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "SerializableComplexImage {{ {}x{} -> {}x{} -> {}x{} }}",
-            self.width(),
-            self.height(),
-            self.transformed_size.0,
-            self.transformed_size.1,
-            self.original_size.0,
-            self.original_size.1,
+            "SerializableComplexImage {{ {:?} -> {}x{} }}",
+            self.channel_sizes, self.original_size.0, self.original_size.1,
         )
     }
 }
 
-fn convert_complex_to_raw(channel: &ComplexChannel) -> RawChannel {
-    channel
-        .iter()
-        .map(|row| row.iter().map(|c| (c.re, c.im)).collect())
-        .collect()
+/// Size (width, height) of the tiles of an image and the origin (x, y) of each tile, row by
+/// row. Without a tile size the image is a single tile.
+///
+/// Tiles are placed the tile size apart, and extend into their neighbours by the overlap unless
+/// the image fits in a single tile along that dimension.
+fn tile_grid(
+    (width, height): (usize, usize),
+    tile_size: Option<usize>,
+    overlap: usize,
+) -> ((usize, usize), Vec<(usize, usize)>) {
+    let tile_size = tile_size.unwrap_or(usize::MAX);
+    let extent = |size: usize| match size > tile_size {
+        true => tile_size + overlap,
+        false => size,
+    };
+    let tile = (extent(width), extent(height));
+    let stride = tile_size.max(1);
+    let origins = (0..height)
+        .step_by(stride)
+        .flat_map(|y| (0..width).step_by(stride).map(move |x| (x, y)))
+        .collect();
+    (tile, origins)
 }
 
-fn convert_raw_to_complex(channel: &RawChannel) -> ComplexChannel {
-    channel
-        .iter()
-        .map(|row| {
-            row.iter()
-                .map(|(re, im)| Complex32::new(re.clone(), im.clone()))
-                .collect()
-        })
+/// Weight of each pixel along one dimension of a tile at an origin, when reassembling tiles
+/// placed the stride apart that extend into their neighbours by the overlap.
+///
+/// The weight rises from 0 over the overlap with the previous tile and falls to 0 over the
+/// overlap with the next, as the squares of sine and cosine, so the weights sum to 1.
+fn crossfade(
+    origin: usize,
+    length: usize,
+    (stride, overlap): (usize, usize),
+    image_size: usize,
+) -> Vec<f32> {
+    let rising = |i: usize| {
+        let angle = std::f32::consts::FRAC_PI_2 * (i as f32 + 0.5) / overlap as f32;
+        angle.sin().powi(2)
+    };
+    let has_next = image_size - origin > stride;
+    (0..length)
+        .map(
+            |i| match (i < overlap && origin > 0, i >= stride && has_next) {
+                (true, _) => rising(i),
+                (false, true) => 1. - rising(i - stride),
+                (false, false) => 1.,
+            },
+        )
         .collect()
 }
 
-fn shift_vector<T>(channel: &mut Channel<T>) {
-    let (width, height) = (channel.len(), channel[0].len());
-    let (half_width, half_height) = (width / 2, height / 2);
-    let mut x2;
-    let mut y2;
-    for x in 0..half_width {
-        x2 = x + half_width;
-        for y in 0..half_height {
-            y2 = y + half_height;
-            channel[x].swap(y, y2);
-            channel[x2].swap(y, y2);
-        }
-        channel.swap(x, x2);
+/// Split the number of frequencies to keep of a line into the number of lowest positive (from
+/// 0) and negative (from the end) frequencies.
+///
+/// Odd numbers keep every positive frequency with its negative counterpart, so a real channel
+/// is restored without an imaginary residue.
+fn corner_split(size: usize) -> (usize, usize) {
+    (size.div_ceil(2), size / 2)
+}
+
+/// Keep the lowest frequencies of a channel, the new width and height split between the
+/// positive and negative frequencies by [`corner_split`].
+fn channel_corners<T: Copy>(
+    channel: &Channel<T>,
+    new_width: usize,
+    new_height: usize,
+) -> Channel<T> {
+    let (width, height) = channel_size(channel);
+    let (low_width, high_width) = corner_split(new_width);
+    let (low_height, high_height) = corner_split(new_height);
+    let inverse_width = width - high_width;
+    let inverse_height = height - high_height;
+    let vert_slice = (0usize..low_height).chain(inverse_height..height);
+    let mut new_channel = Channel::new();
+    for y in vert_slice {
+        let mut row = Vec::with_capacity(new_width);
+        row.extend_from_slice(&channel[y][..low_width]);
+        row.extend_from_slice(&channel[y][inverse_width..width]);
+        new_channel.push(row);
     }
+    new_channel
+}
+
+/// The inverse of [`channel_corners`], the zeros go between the positive and negative
+/// frequencies.
+fn fill_from_corners<T: Copy + Default>(
+    channel: &Channel<T>,
+    original_size: &(usize, usize),
+) -> Channel<T> {
+    let (width, height) = channel_size(channel);
+    let (mid_width, _) = corner_split(width);
+    let (mid_height, _) = corner_split(height);
+    let missing_width = original_size.0 - width;
+    let missing_height = original_size.1 - height;
+    let pad_width = vec![T::default(); missing_width];
+    let pad_height = vec![vec![T::default(); original_size.0]; missing_height];
+    let mut new_channel = channel.clone();
+    new_channel.iter_mut().for_each(|row| {
+        row.splice(mid_width..mid_width, pad_width.clone());
+    });
+    new_channel.splice(mid_height..mid_height, pad_height);
+    new_channel
+}
+
+/// Size (width, height) of a channel.
+fn channel_size<T>(channel: &Channel<T>) -> (usize, usize) {
+    (channel.first().map_or(0, Vec::len), channel.len())
+}
+
+/// Convert to a color value, clamping rather than wrapping values out of range.
+///
+/// Pixels are real, so the real part is used: the magnitude would turn small negative values
+/// (rounding error near black) into positive ones.
+fn to_color_value(value: Complex32) -> u8 {
+    value.re.round().clamp(0.0, 255.0) as u8
 }
 
 fn image_to_trace(image: &ComplexImage, log_factor: f32, shift: bool) -> Box<Image> {
@@ -431,23 +1906,836 @@ fn image_to_trace(image: &ComplexImage, log_factor: f32, shift: bool) -> Box<Ima
         }
         converted_image.push(row);
     }
+    let color = |value: f32| ((value / max_value).powf(log_factor).clamp(0., 1.) * 255.) as u8;
     let mut normalized_image: Channel<Rgb> = converted_image
         .iter()
         .map(|y| {
             y.iter()
-                .map(|pixel| {
-                    let (r, g, b) = pixel;
-                    Rgb::new(
-                        ((r / max_value).powf(log_factor) * 255.) as u8,
-                        ((g / max_value).powf(log_factor) * 255.) as u8,
-                        ((b / max_value).powf(log_factor) * 255.) as u8,
-                    )
-                })
+                .map(|&(r, g, b)| Rgb::new(color(r), color(g), color(b)))
                 .collect()
         })
         .collect();
-    if shift == true {
-        shift_vector(&mut normalized_image);
+    if shift {
+        fftshift_2d(&mut normalized_image);
     }
     Image::new(normalized_image).color_model(ColorModel::RGB)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compression_bmp_{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn uniform_channel(width: usize, height: usize, value: f32) -> ComplexChannel {
+        vec![vec![Complex32::from(value); width]; height]
+    }
+
+    #[test]
+    fn empty_image() {
+        let empty = ComplexImage::new(vec![], vec![], vec![]);
+        assert_eq!(empty.size(), (0, 0));
+        assert!(empty.is_empty());
+        let bytes = empty.to_bitmap_bytes().unwrap();
+        let result = compress_bmp_bytes(&bytes, 2., |_| {});
+        assert!(
+            matches!(result, Err(CompressionError::EmptyInput)),
+            "{result:?}"
+        );
+        let result = compress_bmp_dct_bytes(&bytes, 2., |_| {});
+        assert!(
+            matches!(result, Err(CompressionError::EmptyInput)),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn analysis_log_factor() {
+        let dir = test_dir("analysis_log_factor");
+        let path = dir.join("gradient.bmp");
+        let channel: ComplexChannel = (0..8)
+            .map(|y| (0..8).map(|x| Complex32::from((x * y) as f32)).collect())
+            .collect();
+        let image = ComplexImage::new(channel.clone(), channel.clone(), channel);
+        std::fs::write(&path, image.to_bitmap_bytes().unwrap()).unwrap();
+        for log_factor in [0., -1., f32::INFINITY, f32::NAN] {
+            let result = analyze_image(&path, log_factor, &dir, PlotConfig::default());
+            assert!(
+                matches!(result, Err(CompressionError::InvalidParameter(_))),
+                "{log_factor}: {result:?}"
+            );
+        }
+        let analysis = analyze_image(&path, 0.4, &dir, PlotConfig::default()).unwrap();
+        assert!(analysis.is_file());
+    }
+
+    #[test]
+    fn uniform_brightness() {
+        let gray = 137.;
+        let image = ComplexImage::new(
+            uniform_channel(20, 12, gray),
+            uniform_channel(20, 12, gray),
+            uniform_channel(20, 12, gray),
+        );
+        let bytes = image.to_bitmap_bytes().unwrap();
+        // Up to levels that would keep no frequency of either dimension
+        for level in [1., 2., 7., 13., 25., 1000.] {
+            let compressed = compress_bmp_bytes(&bytes, level, |_| {}).unwrap();
+            let decompressed = decompress_bmp_bytes(&compressed).unwrap();
+            let decompressed = ComplexImage::from_bitmap_bytes(&decompressed).unwrap();
+            assert_eq!(decompressed.size(), (20, 12));
+            for channel in decompressed.channels() {
+                let average = channel.iter().flatten().map(|x| x.re).sum::<f32>() / 240.;
+                assert!((average - gray).abs() <= 1., "{level}: {average}");
+            }
+        }
+    }
+
+    #[test]
+    fn save_clamps_colors() {
+        let image = ComplexImage::new(
+            uniform_channel(4, 4, 300.),
+            uniform_channel(4, 4, 255.6),
+            uniform_channel(4, 4, 127.6),
+        );
+        let bytes = image.to_bitmap_bytes().unwrap();
+        let restored = ComplexImage::from_bitmap_bytes(&bytes).unwrap();
+        assert_eq!(restored.red[0][0].re, 255.);
+        assert_eq!(restored.green[3][3].re, 255.);
+        assert_eq!(restored.blue[2][1].re, 128.);
+    }
+
+    #[test]
+    fn negative_values_are_black() {
+        let image = ComplexImage::new(
+            uniform_channel(2, 2, -3.),
+            uniform_channel(2, 2, 0.),
+            uniform_channel(2, 2, 3.),
+        );
+        let restored = ComplexImage::from_bitmap_bytes(&image.to_bitmap_bytes().unwrap()).unwrap();
+        assert_eq!(restored.red[0][0].re, 0.);
+        assert_eq!(restored.blue[1][1].re, 3.);
+    }
+
+    #[test]
+    fn corners_refill() {
+        for (width, height) in [(16, 16), (15, 9), (1023, 4), (8, 7)] {
+            // Each value identifies its position
+            let channel: ComplexChannel = (0..height)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| Complex32::new(x as f32 + 1., y as f32 + 1.))
+                        .collect()
+                })
+                .collect();
+            let image = ComplexImage::new(channel.clone(), channel.clone(), channel.clone());
+            for (new_width, new_height) in [
+                (width, height),
+                (width - 1, height - 1),
+                (width / 2, height / 2),
+                (3, 2),
+            ] {
+                let corners = image.corners(&[(new_width, new_height); 3]).unwrap();
+                assert_eq!(channel_size(&corners[0]), (new_width, new_height));
+                let refilled = fill_from_corners(&corners[0], &(width, height));
+                let ((low_width, high_width), (low_height, high_height)) =
+                    (corner_split(new_width), corner_split(new_height));
+                // An odd number of frequencies pairs each positive frequency with its negative
+                if new_width % 2 == 1 {
+                    let kept = |x: usize| x < low_width || x >= width - high_width;
+                    assert!((1..width).all(|x| kept(x) == kept(width - x)));
+                }
+                for (y, row) in refilled.iter().enumerate() {
+                    for (x, value) in row.iter().enumerate() {
+                        let kept = (x < low_width || x >= width - high_width)
+                            && (y < low_height || y >= height - high_height);
+                        match kept {
+                            true => assert_eq!(*value, channel[y][x], "{x}, {y}"),
+                            false => assert_eq!(*value, Complex32::default(), "{x}, {y}"),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn coefficients_round_trip() {
+        let gradient: ComplexChannel = (0..8)
+            .map(|y| {
+                (0..6)
+                    .map(|x| Complex32::new(x as f32 * 80., -y as f32))
+                    .collect()
+            })
+            .collect();
+        let corners = vec![gradient.clone(), uniform_channel(4, 2, 0.), gradient];
+        let compressed_data =
+            CompressedData::new(std::slice::from_ref(&corners), false, (12, 16), None).unwrap();
+        let restored = compressed_data.tiles().unwrap().remove(0);
+        assert_eq!(compressed_data.channel_sizes, [(6, 8), (4, 2), (6, 8)]);
+        for (a, b) in corners.iter().zip(&restored) {
+            for (a, b) in a.iter().flatten().zip(b.iter().flatten()) {
+                assert!(
+                    (a - b).norm() <= compressed_data.quantization_step,
+                    "{a} != {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_coefficients() {
+        let colors = [uniform_channel(4, 2, 10.), uniform_channel(4, 2, -3.)];
+        let alpha: ComplexChannel = (0..2)
+            .map(|y| (0..4).map(|x| Complex32::from((x + y) as f32)).collect())
+            .collect();
+        let tile = vec![colors[0].clone(), alpha.clone()];
+        let compressed_data = CompressedData::new(&[tile], true, (8, 4), None).unwrap();
+        let restored = compressed_data.tiles().unwrap().remove(0);
+        assert_eq!(restored.len(), 4);
+        for (a, b) in alpha.iter().flatten().zip(restored[3].iter().flatten()) {
+            assert!((a - b).norm() <= compressed_data.quantization_step);
+        }
+        assert_eq!(restored[0], restored[2]);
+        let compressed_data = CompressedData::new(&[colors.to_vec()], false, (8, 4), None).unwrap();
+        assert!(compressed_data.tiles().is_err());
+    }
+
+    #[test]
+    fn wav_header() {
+        let dir = test_dir("wav_header");
+        let compressed = dir.join("compressed.cbm");
+        std::fs::write(&compressed, b"CWV1\x01\x00").unwrap();
+        let error = decompress_bmp(&compressed, &dir.join("decompressed.bmp")).unwrap_err();
+        assert!(matches!(
+            error,
+            CompressionError::Format(crate::format::FormatError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn in_memory_round_trip() {
+        let image = ComplexImage::new(
+            uniform_channel(16, 16, 10.),
+            uniform_channel(16, 16, 100.),
+            uniform_channel(16, 16, 200.),
+        );
+        let original = image.to_bitmap_bytes().unwrap();
+        let compressed = compress_bmp_bytes(&original, 2., |_| {}).unwrap();
+        let decompressed = decompress_bmp_bytes(&compressed).unwrap();
+        assert_eq!(decompressed, original);
+    }
+
+    #[test]
+    fn per_channel_levels() {
+        let image = ComplexImage::new(
+            uniform_channel(16, 16, 10.),
+            uniform_channel(16, 16, 100.),
+            uniform_channel(16, 16, 200.),
+        );
+        let original = image.to_bitmap_bytes().unwrap();
+        let compressed = compress_bmp_per_channel_bytes(&original, [1.5, 8., 4.], |_| {}).unwrap();
+        let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, &compressed).unwrap();
+        let compressed_data: CompressedData = bincode::deserialize(encoded).unwrap();
+        assert_eq!(compressed_data.channel_sizes, [(10, 10), (2, 2), (4, 4)]);
+        let corners = compressed_data.tiles().unwrap().remove(0);
+        let coefficient_counts: Vec<usize> = corners
+            .iter()
+            .map(|channel| channel.iter().flatten().count())
+            .collect();
+        assert_eq!(coefficient_counts, [100, 4, 16]);
+        let decompressed = decompress_bmp_bytes(&compressed).unwrap();
+        assert_eq!(decompressed, original);
+    }
+
+    #[test]
+    fn grayscale_single_channel() {
+        let gradient: ComplexChannel = (0..32)
+            .map(|y| {
+                (0..32)
+                    .map(|x| Complex32::from((x * 7 + y * 3) as f32))
+                    .collect()
+            })
+            .collect();
+        let gray = ComplexImage::new(gradient.clone(), gradient.clone(), gradient.clone());
+        let mut tinted = gradient.clone();
+        tinted[0][0] += 1.;
+        assert!(gray.is_grayscale());
+        assert!(!ComplexImage::new(gradient.clone(), gradient, tinted).is_grayscale());
+        let compressed = compress_bmp_bytes(&gray.to_bitmap_bytes().unwrap(), 2., |_| {}).unwrap();
+        let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, &compressed).unwrap();
+        let compressed_data: CompressedData = bincode::deserialize(encoded).unwrap();
+        assert_eq!(compressed_data.channel_sizes.len(), 1);
+        let corners = compressed_data.tiles().unwrap();
+        let triplicated =
+            CompressedData::new(&corners, false, compressed_data.original_size, None).unwrap();
+        let size = encoded.len();
+        let triplicated_size = bincode::serialize(&triplicated).unwrap().len();
+        assert!(size * 5 < triplicated_size * 2);
+        let restored = decompress_bmp_bytes(&compressed).unwrap();
+        assert!(ComplexImage::from_bitmap_bytes(&restored)
+            .unwrap()
+            .is_grayscale());
+    }
+
+    #[test]
+    fn native_dimensions() {
+        let (width, height) = (300, 200);
+        // A single frequency that is only periodic in the native dimensions
+        let wave = |scale: f32| -> ComplexChannel {
+            (0..height)
+                .map(|_| {
+                    (0..width)
+                        .map(|x| {
+                            let phase = 2. * std::f32::consts::PI * (x as f32 / width as f32);
+                            Complex32::from(128. + scale * phase.cos())
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let image = ComplexImage::new(wave(50.), wave(100.), wave(-30.));
+        let original = image.to_bitmap_bytes().unwrap();
+        let compressed = compress_bmp_bytes(&original, 10., |_| {}).unwrap();
+        let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, &compressed).unwrap();
+        let compressed_data: CompressedData = bincode::deserialize(encoded).unwrap();
+        assert_eq!(compressed_data.original_size, (width, height));
+        assert_eq!(compressed_data.channel_sizes, vec![(30, 20); 3]);
+        let decompressed = decompress_bmp_bytes(&compressed).unwrap();
+        let restored = ComplexImage::from_bitmap_bytes(&decompressed).unwrap();
+        assert_eq!(restored.size(), (width, height));
+        let original = ComplexImage::from_bitmap_bytes(&original).unwrap();
+        for (a, b) in original.channels().iter().zip(restored.channels()) {
+            for (a, b) in a.iter().flatten().zip(b.iter().flatten()) {
+                assert!((a.re - b.re).abs() <= 1., "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_round_trip() {
+        // A different color in each tile, with smaller tiles at the right and bottom edges
+        let (width, height, tile_size) = (40, 36, 16);
+        let channel = |scale: usize| -> ComplexChannel {
+            (0..height)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| {
+                            Complex32::from(((x / tile_size * 3 + y / tile_size) * scale) as f32)
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let image = ComplexImage::new(channel(20), channel(10), channel(5));
+        let original = image.to_bitmap_bytes().unwrap();
+        let compressed = compress_bmp_tiled_bytes(&original, 2., tile_size, 0, |_| {}).unwrap();
+        let encoded = header::strip_header(BMP_MAGIC, BMP_VERSION, &compressed).unwrap();
+        let compressed_data: CompressedData = bincode::deserialize(encoded).unwrap();
+        assert_eq!(compressed_data.tile_size, Some(tile_size));
+        assert_eq!(compressed_data.channel_sizes, vec![(8, 8); 3 * 3 * 3]);
+        // Every pixel of every tile is restored, and no tile bleeds into its neighbours
+        assert_eq!(decompress_bmp_bytes(&compressed).unwrap(), original);
+        assert!(matches!(
+            compress_bmp_tiled_bytes(&original, 2., 0, 0, |_| {}),
+            Err(CompressionError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            compress_bmp_tiled_bytes(&original, 2., tile_size, tile_size + 1, |_| {}),
+            Err(CompressionError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn metadata() {
+        let gradient: ComplexChannel = (0..21)
+            .map(|y| (0..37).map(|x| Complex32::from((x + y) as f32)).collect())
+            .collect();
+        let gray = ComplexImage::new(gradient.clone(), gradient.clone(), gradient.clone());
+        let original = gray.to_bitmap_bytes().unwrap();
+        let info = read_bmp_metadata_bytes(&compress_bmp_bytes(&original, 2., |_| {}).unwrap());
+        let expected = CompressedInfo {
+            width: 37,
+            height: 21,
+            channel_count: 1,
+            alpha: false,
+            // The corners, halved in each dimension
+            coefficient_count: 18 * 10,
+            tile_size: None,
+            tile_overlap: 0,
+            dct: false,
+            wavelet: false,
+            lossless: false,
+        };
+        assert_eq!(info.unwrap(), expected);
+        let color = ComplexImage::new(gradient.clone(), uniform_channel(37, 21, 0.), gradient);
+        let compressed = compress_bmp_dct_bytes(&color.to_bitmap_bytes().unwrap(), 1., |_| {});
+        let info = read_bmp_metadata_bytes(&compressed.unwrap()).unwrap();
+        assert_eq!((info.channel_count, info.dct), (3, true));
+        assert_eq!(info.coefficient_count, 5 * 3 * 64 * 3);
+        let compressed = compress_bmp_wavelet_bytes(&color.to_bitmap_bytes().unwrap(), 2., |_| {});
+        let info = read_bmp_metadata_bytes(&compressed.unwrap()).unwrap();
+        assert_eq!((info.channel_count, info.wavelet), (3, true));
+        assert!(info.coefficient_count <= 18 * 10 * 3);
+    }
+
+    #[test]
+    fn wavelet_edges() {
+        // A sharp vertical edge, in a size that is not a power of two
+        let (width, height, edge) = (45, 30, 19);
+        let step: ComplexChannel = (0..height)
+            .map(|_| {
+                (0..width)
+                    .map(|x| Complex32::from(if x < edge { 60. } else { 190. }))
+                    .collect()
+            })
+            .collect();
+        let image = ComplexImage::new(step.clone(), step.clone(), step);
+        let original = image.to_bitmap_bytes().unwrap();
+        let overshoot = |compressed: Vec<u8>| {
+            let restored = decompress_image(&compressed).unwrap();
+            assert_eq!(restored.size(), (width, height));
+            restored.red.iter().flatten().fold(0., |overshoot: f32, c| {
+                overshoot.max(c.re - 190.).max(60. - c.re)
+            })
+        };
+        for level in [2., 4., 8.] {
+            let fft = overshoot(compress_bmp_bytes(&original, level, |_| {}).unwrap());
+            let haar = overshoot(compress_bmp_wavelet_bytes(&original, level, |_| {}).unwrap());
+            assert!(haar < fft / 2., "level {level}: {haar} >= {fft} / 2");
+        }
+    }
+
+    #[test]
+    fn crossfaded_tiles() {
+        let (width, height, tile_size) = (64, 48, 16);
+        let gradient: ComplexChannel = (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| Complex32::from((20 + 2 * x + y) as f32))
+                    .collect()
+            })
+            .collect();
+        let image = ComplexImage::new(gradient.clone(), gradient.clone(), gradient);
+        let original = image.to_bitmap_bytes().unwrap();
+        // Largest difference between neighbouring pixels across a tile boundary
+        let seam = |overlap: usize| {
+            let compressed =
+                compress_bmp_tiled_bytes(&original, 4., tile_size, overlap, |_| {}).unwrap();
+            let restored = decompress_bmp_bytes(&compressed).unwrap();
+            let restored = ComplexImage::from_bitmap_bytes(&restored).unwrap();
+            let channel = &restored.channels()[0];
+            let across_columns = (tile_size..width).step_by(tile_size).flat_map(|x| {
+                channel
+                    .iter()
+                    .map(move |row| (row[x].re - row[x - 1].re).abs())
+            });
+            let across_rows = (tile_size..height).step_by(tile_size).flat_map(|y| {
+                (0..width).map(move |x| (channel[y][x].re - channel[y - 1][x].re).abs())
+            });
+            across_columns.chain(across_rows).fold(0., f32::max)
+        };
+        // The gradient rises by at most 2 between neighbouring pixels
+        assert!(seam(0) > 10., "{}", seam(0));
+        for overlap in [4, 8] {
+            assert!(seam(overlap) <= 4., "overlap {overlap}: {}", seam(overlap));
+        }
+    }
+
+    #[test]
+    fn gray_bitmap() {
+        // Large enough for the palette to be outweighed by the smaller pixels
+        let gradient: ComplexChannel = (0..47)
+            .map(|y| {
+                (0..61)
+                    .map(|x| Complex32::from((x * 3 + y * 2) as f32))
+                    .collect()
+            })
+            .collect();
+        let gray = ComplexImage::new(gradient.clone(), gradient.clone(), gradient);
+        let compressed = compress_bmp_bytes(&gray.to_bitmap_bytes().unwrap(), 1.2, |_| {}).unwrap();
+        let rgb = decompress_bmp_bytes(&compressed).unwrap();
+        let gray8 = decompress_bmp_bytes_with(&compressed, BmpFormat::Gray8).unwrap();
+        // Bits per pixel
+        assert_eq!(u16::from_le_bytes([gray8[28], gray8[29]]), 8);
+        let restored = ComplexImage::from_bitmap_bytes(&gray8).unwrap();
+        assert_eq!(restored.to_bitmap_bytes().unwrap(), rgb);
+        assert!(gray8.len() < rgb.len());
+    }
+
+    #[test]
+    fn row_order() {
+        // Dark top left corner, bright bottom right, of an odd width for row padding
+        let gradient: ComplexChannel = (0..6)
+            .map(|y| {
+                (0..5)
+                    .map(|x| Complex32::from((x * 10 + y * 40) as f32))
+                    .collect()
+            })
+            .collect();
+        let image = ComplexImage::new(gradient.clone(), gradient.clone(), gradient);
+        let bottom_up = image.to_bitmap_bytes().unwrap();
+        // The same pixels with their rows stored top to bottom, marked by a negative height
+        let (offset, row_size) = (54, (5usize * 3).div_ceil(4) * 4);
+        let mut top_down = bottom_up[..offset].to_vec();
+        top_down[22..26].copy_from_slice(&(-6i32).to_le_bytes());
+        top_down.extend(bottom_up[offset..].chunks(row_size).rev().flatten());
+        for bytes in [&bottom_up, &top_down] {
+            let loaded = ComplexImage::from_bitmap_bytes(bytes).unwrap();
+            assert_eq!(loaded.red[0][0].re, 0.);
+            assert_eq!(loaded.red[5][4].re, 240.);
+            // Saving keeps the orientation, in bottom to top order
+            let saved = loaded.to_bitmap_bytes().unwrap();
+            assert_eq!(saved, bottom_up);
+            assert_eq!(
+                ComplexImage::from_bitmap_bytes(&saved).unwrap().red[0][0].re,
+                0.
+            );
+        }
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let gradient = |scale: usize| -> ComplexChannel {
+            (0..48)
+                .map(|y| {
+                    (0..64)
+                        .map(|x| Complex32::from((x * scale + y) as f32))
+                        .collect()
+                })
+                .collect()
+        };
+        let color = ComplexImage::new(gradient(1), gradient(2), gradient(3));
+        let gray = ComplexImage::new(gradient(2), gradient(2), gradient(2));
+        for image in [color, gray] {
+            let original = image.to_bitmap_bytes().unwrap();
+            let compressed = compress_bmp_lossless_bytes(&original, 2., |_| {}).unwrap();
+            assert_eq!(decompress_bmp_bytes(&compressed).unwrap(), original);
+            assert!(compressed.len() < original.len());
+        }
+    }
+
+    #[test]
+    fn lossless_grayscale_reconstruction() {
+        // Keeping only the average of each channel restores a color image as grayscale
+        let row = |values: [f32; 2]| vec![values.map(Complex32::from).to_vec()];
+        let image = ComplexImage::new(row([0., 10.]), row([10., 0.]), row([4., 6.]));
+        let original = image.to_bitmap_bytes().unwrap();
+        let lossy = compress_bmp_bytes(&original, 100., |_| {}).unwrap();
+        assert!(decompress_image(&lossy).unwrap().is_grayscale());
+        let compressed = compress_bmp_lossless_bytes(&original, 100., |_| {}).unwrap();
+        assert_eq!(decompress_bmp_bytes(&compressed).unwrap(), original);
+    }
+
+    #[cfg(feature = "high-precision")]
+    #[test]
+    fn high_precision_error() {
+        // A large bright image, whose coefficients are small next to the average color
+        let size = 256;
+        let wave = |x: usize, periods: f32| {
+            (2. * std::f32::consts::PI * periods * x as f32 / size as f32).cos()
+        };
+        let channel: ComplexChannel = (0..size)
+            .map(|y| {
+                (0..size)
+                    .map(|x| {
+                        Complex32::from((200. + 40. * wave(x, 3.) + 15. * wave(y, 5.)).round())
+                    })
+                    .collect()
+            })
+            .collect();
+        let image = ComplexImage::new(channel.clone(), channel.clone(), channel.clone());
+        let original = image.to_bitmap_bytes().unwrap();
+        let error = |compressed: &[u8]| {
+            let restored = decompress_image(compressed).unwrap();
+            let squares: f32 = restored
+                .red
+                .iter()
+                .flatten()
+                .zip(channel.iter().flatten())
+                .map(|(a, b)| (a.re - b.re).powi(2))
+                .sum();
+            (squares / (size * size) as f32).sqrt()
+        };
+        let single = error(&compress_bmp_bytes(&original, 1.01, |_| {}).unwrap());
+        let double = error(&compress_bmp_hp_bytes(&original, 1.01, |_| {}).unwrap());
+        assert!(double < single / 2., "{double} >= {single} / 2");
+    }
+
+    #[test]
+    fn identity_level() {
+        // Noise, which is cropped by any level above 1
+        let noise = |seed: usize| -> ComplexChannel {
+            (0..16)
+                .map(|y| {
+                    (0..24)
+                        .map(|x| {
+                            Complex32::from(((x * 37 + y * 91 + seed) * 2654435761 % 251) as f32)
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let image = ComplexImage::new(noise(0), noise(1), noise(2));
+        let original = image.to_bitmap_bytes().unwrap();
+        let max_error = |compressed: &[u8]| {
+            let restored = decompress_image(compressed).unwrap();
+            restored
+                .channels()
+                .iter()
+                .zip(image.channels())
+                .flat_map(|(a, b)| a.iter().flatten().zip(b.iter().flatten()))
+                .map(|(a, b)| (a.re - b.re).abs())
+                .fold(0., f32::max)
+        };
+        let compressed = compress_bmp_bytes(&original, 1., |_| {}).unwrap();
+        // Nothing is cropped, the error is only that of the quantization (a fraction of a step
+        // of about 1.5) and the transforms
+        assert!(max_error(&compressed) < 0.25);
+        #[cfg(feature = "high-precision")]
+        assert!(max_error(&compress_bmp_hp_bytes(&original, 1., |_| {}).unwrap()) < 1e-3);
+        assert_eq!(decompress_bmp_bytes(&compressed).unwrap(), original);
+        assert!(compress_bmp_bytes(&original, 0.9, |_| {}).is_err());
+    }
+
+    #[test]
+    fn invalid_compression_level() {
+        let image = ComplexImage::new(
+            uniform_channel(16, 16, 10.),
+            uniform_channel(16, 16, 100.),
+            uniform_channel(16, 16, 200.),
+        );
+        let original = image.to_bitmap_bytes().unwrap();
+        for level in [f32::NAN, -5.] {
+            for result in [
+                compress_bmp_bytes(&original, level, |_| {}),
+                compress_bmp_per_channel_bytes(&original, [2., level, 2.], |_| {}),
+                compress_bmp_tiled_bytes(&original, level, 8, 0, |_| {}),
+            ] {
+                assert!(matches!(
+                    result,
+                    Err(CompressionError::InvalidParameter(
+                        "compression must be no smaller than 1"
+                    ))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn progress_increases() {
+        let image = ComplexImage::new(
+            uniform_channel(16, 16, 10.),
+            uniform_channel(16, 16, 100.),
+            uniform_channel(16, 16, 200.),
+        );
+        let mut fractions = Vec::new();
+        compress_bmp_bytes(&image.to_bitmap_bytes().unwrap(), 2., |f| fractions.push(f)).unwrap();
+        assert_eq!(fractions, vec![0.25, 0.5, 0.75, 1.]);
+    }
+
+    #[test]
+    fn dct_round_trip() {
+        // Dimensions that are not multiples of the block size
+        let (width, height) = (37, 21);
+        let gradient = |scale: f32, offset: f32| -> ComplexChannel {
+            (0..height)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| Complex32::from(offset + scale * (x + 2 * y) as f32))
+                        .collect()
+                })
+                .collect()
+        };
+        let image = ComplexImage::new(gradient(3., 10.), gradient(1., 100.), gradient(-2., 240.));
+        let original = image.to_bitmap_bytes().unwrap();
+        let compressed = compress_bmp_dct_bytes(&original, 1., |_| {}).unwrap();
+        assert!(compressed.len() * 10 < original.len());
+        let decompressed = decompress_bmp_bytes(&compressed).unwrap();
+        let restored = ComplexImage::from_bitmap_bytes(&decompressed).unwrap();
+        assert_eq!(restored.size(), (width, height));
+        let original = ComplexImage::from_bitmap_bytes(&original).unwrap();
+        let psnr = crate::quality::psnr(&original, &restored);
+        assert!(psnr > 35., "PSNR {psnr}");
+    }
+
+    #[test]
+    fn custom_quantization_table() {
+        // Two flat blocks, whose only nonzero coefficients are their averages (times 8)
+        let channel: ComplexChannel = (0..8)
+            .map(|_| {
+                (0..16)
+                    .map(|x| Complex32::from(if x < 8 { 138. } else { 98. }))
+                    .collect()
+            })
+            .collect();
+        let image = ComplexImage::new(channel.clone(), channel.clone(), channel);
+        let original = image.to_bitmap_bytes().unwrap();
+        let table = [[7; BLOCK_SIZE]; BLOCK_SIZE];
+        let compressed = compress_bmp_dct_with_table_bytes(&original, 1., &table, |_| {}).unwrap();
+        let encoded = header::strip_header(BMP_DCT_MAGIC, BMP_DCT_VERSION, &compressed).unwrap();
+        let compressed_data: DctData = bincode::deserialize(encoded).unwrap();
+        assert_eq!(compressed_data.tables, DctTables::Custom(table));
+        let values = entropy::decode(&compressed_data.coefficients, 2 * 64).unwrap();
+        // Every coefficient is divided by the same step: 80 / 7 and -240 / 7, rounded
+        let mut expected = vec![0; 2 * 64];
+        (expected[0], expected[64]) = (11, -34);
+        assert_eq!(values, expected);
+        let restored = ComplexImage::from_image_bytes(&decompress_bmp_bytes(&compressed).unwrap());
+        let max_error = crate::quality::max_error(&image, &restored.unwrap());
+        assert!(max_error <= 1., "{max_error}");
+
+        let mut zero = table;
+        zero[3][5] = 0;
+        assert!(matches!(
+            compress_bmp_dct_with_table_bytes(&original, 1., &zero, |_| {}),
+            Err(CompressionError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn parse_table() {
+        let rows: Vec<String> = (1..=8)
+            .map(|y| {
+                format!(
+                    "[{}]",
+                    (1..=8)
+                        .map(|x| (x * y).to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+            .collect();
+        let json = format!("[{}]", rows.join(",\n"));
+        let table = parse_quantization_table(&json).unwrap();
+        assert_eq!(table[2][6], 21);
+        let text = json.replace(['[', ']', ','], " ");
+        assert_eq!(parse_quantization_table(&text).unwrap(), table);
+        for invalid in ["1 2 3", &json.replace("64", "0"), &json.replace("64", "-1")] {
+            assert!(parse_quantization_table(invalid).is_err());
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_round_trip() {
+        let image = ComplexImage::new(
+            uniform_channel(30, 20, 10.),
+            uniform_channel(30, 20, 100.),
+            uniform_channel(30, 20, 200.),
+        );
+        let original = image.to_png_bytes().unwrap();
+        assert!(original.starts_with(PNG_MAGIC));
+        let compressed = compress_bmp_bytes(&original, 2., |_| {}).unwrap();
+        let decompressed = decompress_png_bytes(&compressed).unwrap();
+        let restored = ComplexImage::from_image_bytes(&decompressed).unwrap();
+        assert_eq!(restored.size(), (30, 20));
+        for (a, b) in image.channels().iter().zip(restored.channels()) {
+            for (a, b) in a.iter().flatten().zip(b.iter().flatten()) {
+                assert!((a.re - b.re).abs() <= 1., "{a} != {b}");
+            }
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png_alpha_round_trip() {
+        let (width, height) = (24, 16);
+        let alpha: ComplexChannel = (0..height)
+            .map(|_| {
+                (0..width)
+                    .map(|x| Complex32::from(x as f32 * 10.))
+                    .collect()
+            })
+            .collect();
+        let image = ComplexImage::new(
+            uniform_channel(width, height, 10.),
+            uniform_channel(width, height, 100.),
+            uniform_channel(width, height, 200.),
+        )
+        .with_alpha(Some(alpha.clone()));
+        let original = image.to_png_bytes().unwrap();
+        let compressed = [
+            compress_bmp_bytes(&original, 1.01, |_| {}).unwrap(),
+            compress_bmp_dct_bytes(&original, 0.1, |_| {}).unwrap(),
+        ];
+        for compressed in compressed {
+            let decompressed = decompress_png_bytes(&compressed).unwrap();
+            let restored = ComplexImage::from_image_bytes(&decompressed).unwrap();
+            let restored_alpha = restored.alpha.expect("alpha was dropped");
+            let errors: Vec<f32> = alpha
+                .iter()
+                .flatten()
+                .zip(restored_alpha.iter().flatten())
+                .map(|(a, b)| (a.re - b.re).abs())
+                .collect();
+            let mean_error = errors.iter().sum::<f32>() / errors.len() as f32;
+            assert!(mean_error <= 5., "mean alpha error {mean_error}");
+        }
+        // Opaque images do not gain an alpha channel
+        let opaque = ComplexImage::new(
+            uniform_channel(8, 8, 1.),
+            uniform_channel(8, 8, 2.),
+            uniform_channel(8, 8, 3.),
+        );
+        let compressed = compress_bmp_bytes(&opaque.to_png_bytes().unwrap(), 2., |_| {}).unwrap();
+        let decompressed = decompress_png_bytes(&compressed).unwrap();
+        assert!(ComplexImage::from_image_bytes(&decompressed)
+            .unwrap()
+            .alpha
+            .is_none());
+    }
+
+    #[test]
+    fn dct_grayscale() {
+        let image = ComplexImage::new(
+            // A multiple of the quantization step of the DC coefficient away from 128
+            uniform_channel(16, 8, 96.),
+            uniform_channel(16, 8, 96.),
+            uniform_channel(16, 8, 96.),
+        );
+        let original = image.to_bitmap_bytes().unwrap();
+        let mut fractions = Vec::new();
+        let compressed = compress_bmp_dct_bytes(&original, 4., |f| fractions.push(f)).unwrap();
+        assert_eq!(fractions, vec![0.5, 1.]);
+        assert_eq!(decompress_bmp_bytes(&compressed).unwrap(), original);
+        assert!(matches!(
+            compress_bmp_dct_bytes(&original, 0., |_| {}),
+            Err(CompressionError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn dumped_stages() {
+        let dir = test_dir("dumped_stages").join("stages");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let image = ComplexImage::new(
+            uniform_channel(24, 16, 100.),
+            uniform_channel(24, 16, 50.),
+            uniform_channel(24, 16, 150.),
+        );
+        let input = image.to_bitmap_bytes().unwrap();
+        dump::set_dump_dir(Some(dir.clone()));
+        let compressed = compress_bmp_bytes(&input, 2., |_| {}).unwrap();
+        decompress_bmp_bytes(&compressed).unwrap();
+        dump::set_dump_dir(None);
+        // Cropped to half the width and height
+        for c in 0..3 {
+            for (stage, descr, shape) in [
+                ("bmp_time_domain", "'<f4'", "(16, 24)"),
+                ("bmp_frequency_domain", "'<c8'", "(16, 24)"),
+                ("bmp_retained", "'<c8'", "(8, 12)"),
+                ("bmp_restored", "'<f4'", "(16, 24)"),
+            ] {
+                let header = dump::read_header(&dir.join(format!("{stage}_{c}.npy")));
+                assert_eq!(header, (descr.into(), shape.into()), "{stage}_{c}");
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! # Codecs
+//! A uniform interface over the file formats, for callers that should not need to know which
+//! format they are compressing.
+//!
+
+use crate::error::CompressionError;
+use crate::stats::CompressionStats;
+use crate::{bmp, wav};
+use std::path::Path;
+
+/// Compresses files of one format, and decompresses them back.
+pub trait Codec {
+    /// Parameters controlling the compression.
+    type Params;
+
+    fn compress(
+        input: &Path,
+        output: &Path,
+        params: Self::Params,
+    ) -> Result<CompressionStats, CompressionError>;
+
+    fn decompress(input: &Path, output: &Path) -> Result<(), CompressionError>;
+}
+
+/// Codec for .wav files, see [`wav::compress_wav`].
+pub struct WavCodec;
+
+/// Parameters for [`WavCodec`].
+#[derive(Debug, Clone, Copy)]
+pub struct WavParams {
+    pub freq_cutoff: usize,
+    pub quantization: f32,
+    /// Compress in overlapping frames of this many samples, see [`wav::compress_wav_blocked`]
+    pub frame_size: Option<usize>,
+}
+
+impl Codec for WavCodec {
+    type Params = WavParams;
+
+    fn compress(
+        input: &Path,
+        output: &Path,
+        params: WavParams,
+    ) -> Result<CompressionStats, CompressionError> {
+        let WavParams {
+            freq_cutoff,
+            quantization,
+            frame_size,
+        } = params;
+        match frame_size {
+            Some(frame_size) => wav::compress_wav_blocked(
+                input,
+                output,
+                freq_cutoff,
+                quantization,
+                frame_size,
+                |_| {},
+            ),
+            None => wav::compress_wav(input, output, freq_cutoff, quantization, |_| {}),
+        }
+    }
+
+    fn decompress(input: &Path, output: &Path) -> Result<(), CompressionError> {
+        wav::decompress_wav(input, output)
+    }
+}
+
+/// Codec for .bmp files, see [`bmp::compress_bmp_per_channel`].
+pub struct BmpCodec;
+
+/// Parameters for [`BmpCodec`].
+#[derive(Debug, Clone, Copy)]
+pub struct BmpParams {
+    /// Compression level of the red, green and blue channels
+    pub levels: [f32; 3],
+}
+
+impl Codec for BmpCodec {
+    type Params = BmpParams;
+
+    fn compress(
+        input: &Path,
+        output: &Path,
+        params: BmpParams,
+    ) -> Result<CompressionStats, CompressionError> {
+        bmp::compress_bmp_per_channel(input, output, params.levels, |_| {})
+    }
+
+    fn decompress(input: &Path, output: &Path) -> Result<(), CompressionError> {
+        bmp::decompress_bmp(input, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compression_codec_{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Compress and decompress a file, returning the size of the decompressed file.
+    fn round_trip<C: Codec>(original: &Path, params: C::Params) -> u64 {
+        let compressed = original.with_extension("compressed");
+        let decompressed = original.with_extension("decompressed");
+        let stats = C::compress(original, &compressed, params).unwrap();
+        assert_eq!(
+            stats.original_bytes,
+            std::fs::metadata(original).unwrap().len()
+        );
+        C::decompress(&compressed, &decompressed).unwrap();
+        std::fs::metadata(&decompressed).unwrap().len()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn codecs_round_trip() {
+        let dir = test_dir("codecs_round_trip");
+        let wav_file = dir.join("original.wav");
+        let samples: Vec<i16> = (0..4096).map(|x| ((x % 64) * 100) as i16).collect();
+        let header = ::wav::Header::new(::wav::WAV_FORMAT_PCM, 1, 8000, 16);
+        let mut file = std::fs::File::create(&wav_file).unwrap();
+        ::wav::write(header, &::wav::BitDepth::Sixteen(samples), &mut file).unwrap();
+        let bmp_file = dir.join("original.bmp");
+        let mut image = ::bmp::Image::new(20, 10);
+        for (x, y) in image.coordinates() {
+            image.set_pixel(x, y, ::bmp::Pixel::new((x * 10) as u8, (y * 20) as u8, 100));
+        }
+        image.save(&bmp_file).unwrap();
+
+        let wav_params = WavParams {
+            freq_cutoff: 8000,
+            quantization: 1.,
+            frame_size: None,
+        };
+        let bmp_params = BmpParams {
+            levels: [2., 4., 4.],
+        };
+        let wav_size = round_trip::<WavCodec>(&wav_file, wav_params);
+        let bmp_size = round_trip::<BmpCodec>(&bmp_file, bmp_params);
+        assert_eq!(wav_size, std::fs::metadata(&wav_file).unwrap().len());
+        assert_eq!(bmp_size, std::fs::metadata(&bmp_file).unwrap().len());
+    }
+}
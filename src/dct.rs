@@ -0,0 +1,137 @@
+//! # Discrete cosine transform
+//! The 2D DCT-II of 8x8 blocks, as used by JPEG, and the standard JPEG quantization tables.
+//!
+
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+/// Width and height of a block.
+pub const BLOCK_SIZE: usize = 8;
+
+/// An 8x8 block of samples or coefficients, indexed by row then column.
+pub type Block = [[f32; BLOCK_SIZE]; BLOCK_SIZE];
+
+/// Divisors of the coefficients of a block, indexed by row then column.
+pub type QuantizationTable = [[u16; BLOCK_SIZE]; BLOCK_SIZE];
+
+/// Standard JPEG quantization table for luminance (quality 50).
+pub const LUMINANCE_QUANTIZATION: QuantizationTable = [
+    [16, 11, 10, 16, 24, 40, 51, 61],
+    [12, 12, 14, 19, 26, 58, 60, 55],
+    [14, 13, 16, 24, 40, 57, 69, 56],
+    [14, 17, 22, 29, 51, 87, 80, 62],
+    [18, 22, 37, 56, 68, 109, 103, 77],
+    [24, 35, 55, 64, 81, 104, 113, 92],
+    [49, 64, 78, 87, 103, 121, 120, 101],
+    [72, 92, 95, 98, 112, 100, 103, 99],
+];
+
+/// Standard JPEG quantization table for chrominance (quality 50).
+pub const CHROMINANCE_QUANTIZATION: QuantizationTable = [
+    [17, 18, 24, 47, 99, 99, 99, 99],
+    [18, 21, 26, 66, 99, 99, 99, 99],
+    [24, 26, 56, 99, 99, 99, 99, 99],
+    [47, 66, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+    [99, 99, 99, 99, 99, 99, 99, 99],
+];
+
+/// Perform an (orthonormal) 2D DCT-II on a block, undone by [`idct2_8x8`].
+pub fn dct2_8x8(block: &Block) -> Block {
+    let basis = basis();
+    let mut coefficients = [[0.; BLOCK_SIZE]; BLOCK_SIZE];
+    for (v, row) in coefficients.iter_mut().enumerate() {
+        for (u, coefficient) in row.iter_mut().enumerate() {
+            *coefficient = (0..BLOCK_SIZE)
+                .flat_map(|y| (0..BLOCK_SIZE).map(move |x| (y, x)))
+                .map(|(y, x)| block[y][x] * basis[v][y] * basis[u][x])
+                .sum();
+        }
+    }
+    coefficients
+}
+
+/// Perform an (orthonormal) 2D DCT-III on a block, the inverse of [`dct2_8x8`].
+pub fn idct2_8x8(coefficients: &Block) -> Block {
+    let basis = basis();
+    let mut block = [[0.; BLOCK_SIZE]; BLOCK_SIZE];
+    for (y, row) in block.iter_mut().enumerate() {
+        for (x, sample) in row.iter_mut().enumerate() {
+            *sample = (0..BLOCK_SIZE)
+                .flat_map(|v| (0..BLOCK_SIZE).map(move |u| (v, u)))
+                .map(|(v, u)| coefficients[v][u] * basis[v][y] * basis[u][x])
+                .sum();
+        }
+    }
+    block
+}
+
+/// Positions (row, column) of a block in zigzag order, from the lowest frequencies to the
+/// highest, which groups the coefficients that quantize to zero.
+pub fn zigzag() -> [(usize, usize); BLOCK_SIZE * BLOCK_SIZE] {
+    let mut positions = [(0, 0); BLOCK_SIZE * BLOCK_SIZE];
+    for (i, position) in positions.iter_mut().enumerate() {
+        *position = (i / BLOCK_SIZE, i % BLOCK_SIZE);
+    }
+    // Alternate the direction along each anti-diagonal
+    positions.sort_by_key(|(y, x)| {
+        let diagonal = y + x;
+        match diagonal % 2 {
+            0 => (diagonal, *x),
+            _ => (diagonal, *y),
+        }
+    });
+    positions
+}
+
+/// Orthonormal DCT-II basis, indexed by frequency then sample.
+fn basis() -> Block {
+    let mut basis = [[0.; BLOCK_SIZE]; BLOCK_SIZE];
+    let scale = (2. / BLOCK_SIZE as f32).sqrt();
+    for (k, row) in basis.iter_mut().enumerate() {
+        let normalization = match k {
+            0 => FRAC_1_SQRT_2,
+            _ => 1.,
+        };
+        for (n, value) in row.iter_mut().enumerate() {
+            let angle = PI * (2 * n + 1) as f32 * k as f32 / (2 * BLOCK_SIZE) as f32;
+            *value = scale * normalization * angle.cos();
+        }
+    }
+    basis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_round_trip() {
+        let mut block = [[0.; BLOCK_SIZE]; BLOCK_SIZE];
+        for (y, row) in block.iter_mut().enumerate() {
+            for (x, sample) in row.iter_mut().enumerate() {
+                *sample = ((y * 31 + x * 17) % 255) as f32 - 128.;
+            }
+        }
+        let restored = idct2_8x8(&dct2_8x8(&block));
+        for (a, b) in block.iter().flatten().zip(restored.iter().flatten()) {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+        // A uniform block has only a DC coefficient
+        let coefficients = dct2_8x8(&[[10.; BLOCK_SIZE]; BLOCK_SIZE]);
+        assert!((coefficients[0][0] - 80.).abs() < 1e-3);
+        assert!(coefficients
+            .iter()
+            .flatten()
+            .skip(1)
+            .all(|c| c.abs() < 1e-3));
+    }
+
+    #[test]
+    fn zigzag_order() {
+        let order = zigzag();
+        assert_eq!(order[..6], [(0, 0), (0, 1), (1, 0), (2, 0), (1, 1), (0, 2)]);
+        assert_eq!(order[63], (7, 7));
+    }
+}
@@ -0,0 +1,167 @@
+//! # Debug dumps
+//! Intermediate stages of the transforms written to `.npy` files, to inspect with numpy
+//! (`numpy.load`) when a reconstruction looks wrong.
+//!
+//! While a dump directory is set on a thread (see [`set_dump_dir`]), the compressions and
+//! decompressions it runs dump their stages there, one file per stage and channel, like
+//! `wav_frequency_domain_0.npy`. Later dumps of a stage overwrite earlier ones.
+//!
+
+use crate::error::CompressionError;
+use num_complex::Complex32;
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    static DUMP_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Dump the stages of the compressions and decompressions run on this thread to a directory
+/// (created if missing), or stop dumping them with None.
+pub fn set_dump_dir(dir: Option<PathBuf>) {
+    DUMP_DIR.with(|dump_dir| *dump_dir.borrow_mut() = dir);
+}
+
+/// Values of a dumped array.
+pub trait DumpElement: Copy {
+    /// Numpy type of the values, little-endian.
+    const DESCR: &'static str;
+    fn write_le(&self, bytes: &mut Vec<u8>);
+}
+
+impl DumpElement for f32 {
+    const DESCR: &'static str = "<f4";
+    fn write_le(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.to_le_bytes());
+    }
+}
+
+impl DumpElement for Complex32 {
+    const DESCR: &'static str = "<c8";
+    fn write_le(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.re.to_le_bytes());
+        bytes.extend(self.im.to_le_bytes());
+    }
+}
+
+/// A 1D (audio) or 2D (image) array that can be dumped.
+pub trait DumpArray {
+    type Element: DumpElement;
+    /// Dimensions of the array, or None if its rows differ in length.
+    fn shape(&self) -> Option<Vec<usize>>;
+    fn values(&self) -> impl Iterator<Item = &Self::Element>;
+}
+
+impl<T: DumpElement> DumpArray for [T] {
+    type Element = T;
+    fn shape(&self) -> Option<Vec<usize>> {
+        Some(vec![self.len()])
+    }
+    fn values(&self) -> impl Iterator<Item = &T> {
+        self.iter()
+    }
+}
+
+impl<T: DumpElement> DumpArray for [Vec<T>] {
+    type Element = T;
+    fn shape(&self) -> Option<Vec<usize>> {
+        let width = self.first().map_or(0, Vec::len);
+        match self.iter().all(|row| row.len() == width) {
+            true => Some(vec![self.len(), width]),
+            false => None,
+        }
+    }
+    fn values(&self) -> impl Iterator<Item = &T> {
+        self.iter().flatten()
+    }
+}
+
+/// Write an array to `<stage>.npy` in the directory (created if missing), returning the path
+/// of the file.
+pub fn debug_dump<A: DumpArray + ?Sized>(
+    stage: &str,
+    data: &A,
+    dir: &Path,
+) -> Result<PathBuf, CompressionError> {
+    let shape = data.shape().ok_or(CompressionError::InvalidParameter(
+        "dumped rows must have the same length",
+    ))?;
+    let shape = match shape.as_slice() {
+        [length] => format!("({length},)"),
+        dimensions => {
+            let dimensions: Vec<String> = dimensions.iter().map(usize::to_string).collect();
+            format!("({})", dimensions.join(", "))
+        }
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+        A::Element::DESCR
+    );
+    // The magic, version and header length take 10 bytes, and the data starts aligned to 64
+    let padded_size = (10 + header.len() + 1).div_ceil(64) * 64 - 10;
+    header.extend(std::iter::repeat_n(' ', padded_size - header.len() - 1));
+    header.push('\n');
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend((header.len() as u16).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    for value in data.values() {
+        value.write_le(&mut bytes);
+    }
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{stage}.npy"));
+    fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+/// Whether stages are dumped on this thread, for callers to skip preparing them otherwise.
+pub(crate) fn dumping() -> bool {
+    DUMP_DIR.with(|dump_dir| dump_dir.borrow().is_some())
+}
+
+/// Dump a stage with [`debug_dump`] if a dump directory is set on this thread.
+pub(crate) fn dump<A: DumpArray + ?Sized>(stage: &str, data: &A) -> Result<(), CompressionError> {
+    let Some(dir) = DUMP_DIR.with(|dump_dir| dump_dir.borrow().clone()) else {
+        return Ok(());
+    };
+    debug_dump(stage, data, &dir)?;
+    Ok(())
+}
+
+/// Type and shape from the header of a .npy file written by [`debug_dump`].
+#[cfg(test)]
+pub(crate) fn read_header(path: &Path) -> (String, String) {
+    let bytes = fs::read(path).unwrap();
+    assert!(bytes.starts_with(b"\x93NUMPY\x01\x00"));
+    let length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert_eq!((10 + length) % 64, 0);
+    let header = std::str::from_utf8(&bytes[10..10 + length]).unwrap();
+    let field = |name: &str| {
+        let start = header.find(name).unwrap() + name.len();
+        let rest = &header[start..];
+        let end = rest.find(", '").or(rest.find(", }")).unwrap();
+        rest[..end].to_string()
+    };
+    (field("'descr': "), field("'shape': "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npy_format() {
+        let dir = std::env::temp_dir().join("compression_dump_npy_format");
+        let path = debug_dump("line", &[1., 2., 3.][..], &dir).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(path, dir.join("line.npy"));
+        assert_eq!(read_header(&path), ("'<f4'".into(), "(3,)".into()));
+        assert_eq!(bytes[bytes.len() - 4..], 3f32.to_le_bytes());
+        let grid = vec![vec![Complex32::new(1., -1.); 5]; 2];
+        let path = debug_dump("grid", &grid[..], &dir).unwrap();
+        assert_eq!(read_header(&path), ("'<c8'".into(), "(2, 5)".into()));
+        assert_eq!(fs::read(&path).unwrap().len(), 128 + 2 * 5 * 8);
+        let ragged = [vec![1.], vec![1., 2.]];
+        assert!(debug_dump("ragged", &ragged[..], &dir).is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! # Entropy coding
+//! Lossless coding of quantized values: runs of zeros are run-length encoded, and the resulting
+//! symbols are Huffman coded.
+//!
+//! Symbols only record the number of significant bits of a value or run length, which are
+//! followed by the remaining bits uncoded. This keeps the code table small even when most values
+//! are distinct.
+//!
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use thiserror::Error;
+
+/// Returned when entropy coded data cannot be decoded.
+#[derive(Error, Debug)]
+pub enum EntropyError {
+    #[error("entropy coded data is malformed")]
+    Malformed,
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+}
+
+/// Encode quantized values for later decoding using [`decode`].
+pub fn encode(values: &[i16]) -> Result<Vec<u8>, EntropyError> {
+    let tokens = run_length_encode(values);
+    let symbols: Vec<Symbol> = tokens.iter().map(|token| token.symbol).collect();
+    let code_lengths = code_lengths(&symbols);
+    let codes = canonical_codes(&code_lengths);
+    let mut writer = BitWriter::default();
+    for token in &tokens {
+        let (code, length) = codes[&token.symbol];
+        writer.write(code, length);
+        writer.write(token.extra_bits, token.symbol.extra_bit_count());
+    }
+    let encoded = Encoded {
+        code_lengths,
+        symbol_count: symbols.len(),
+        bits: writer.finish(),
+    };
+    Ok(bincode::DefaultOptions::new().serialize(&encoded)?)
+}
+
+/// Encode values that may not fit an i16 for later decoding using [`decode_wide`].
+///
+/// Values that fit are encoded as they are, others as i16::MIN followed by their upper and
+/// lower halves, which suits values that are mostly small (such as residuals).
+pub fn encode_wide(values: &[i32]) -> Result<Vec<u8>, EntropyError> {
+    let narrow: Vec<i16> = values
+        .iter()
+        .flat_map(|&value| match i16::try_from(value) {
+            Ok(value) if value != i16::MIN => vec![value],
+            _ => vec![i16::MIN, (value >> 16) as i16, value as i16],
+        })
+        .collect();
+    encode(&narrow)
+}
+
+/// Decode at most `max_count` values from [`encode_wide`], see [`decode`].
+pub fn decode_wide(bytes: &[u8], max_count: usize) -> Result<Vec<i32>, EntropyError> {
+    // Each value is encoded as up to 3 narrow ones
+    let narrow = decode(bytes, max_count.saturating_mul(3))?;
+    let mut narrow = narrow.iter();
+    let mut values = Vec::with_capacity(narrow.len());
+    while let Some(&value) = narrow.next() {
+        values.push(match value {
+            i16::MIN => {
+                let (Some(&upper), Some(&lower)) = (narrow.next(), narrow.next()) else {
+                    return Err(EntropyError::Malformed);
+                };
+                ((upper as i32) << 16) | lower as u16 as i32
+            }
+            value => value as i32,
+        });
+    }
+    if values.len() > max_count {
+        return Err(EntropyError::Malformed);
+    }
+    Ok(values)
+}
+
+/// Decode values from [`encode`], of which the caller expects at most `max_count`.
+///
+/// Data decoding to more values is malformed, and is rejected before they are allocated: a
+/// single run of zeros may otherwise expand to billions of values.
+pub fn decode(bytes: &[u8], max_count: usize) -> Result<Vec<i16>, EntropyError> {
+    let encoded: Encoded = bincode::DefaultOptions::new().deserialize(bytes)?;
+    validate_code_lengths(&encoded.code_lengths)?;
+    let lookup: HashMap<(u64, u8), Symbol> = canonical_codes(&encoded.code_lengths)
+        .into_iter()
+        .map(|(symbol, code)| (code, symbol))
+        .collect();
+    let mut reader = BitReader::new(&encoded.bits);
+    let mut values = Vec::new();
+    for _ in 0..encoded.symbol_count {
+        let (mut code, mut length) = (0u64, 0u8);
+        let symbol = loop {
+            let bit = reader.read().ok_or(EntropyError::Malformed)?;
+            code = (code << 1) | bit as u64;
+            length += 1;
+            if let Some(symbol) = lookup.get(&(code, length)) {
+                break *symbol;
+            }
+            if length >= MAX_CODE_LENGTH {
+                return Err(EntropyError::Malformed);
+            }
+        };
+        let extra_bits = reader
+            .read_bits(symbol.extra_bit_count())
+            .ok_or(EntropyError::Malformed)?;
+        let remaining = max_count - values.len();
+        match symbol {
+            Symbol::Value(_) if remaining == 0 => return Err(EntropyError::Malformed),
+            Symbol::Value(bit_count) => values.push(decode_value(bit_count, extra_bits)?),
+            Symbol::ZeroRun(bit_count) => {
+                let run = ((1 << (bit_count - 1)) | extra_bits) as usize;
+                if run > remaining {
+                    return Err(EntropyError::Malformed);
+                }
+                values.extend(std::iter::repeat_n(0, run));
+            }
+        }
+    }
+    Ok(values)
+}
+
+const MAX_CODE_LENGTH: u8 = 64;
+
+/// Check a code table read from a file, so that decoding cannot overflow: only symbols that
+/// [`encode`] produces (values of up to 16 bits and run lengths of up to 32 bits), with code
+/// lengths of 1 to [`MAX_CODE_LENGTH`] that satisfy Kraft's inequality, so each canonical code
+/// fits in its length.
+fn validate_code_lengths(code_lengths: &[(Symbol, u8)]) -> Result<(), EntropyError> {
+    // Kraft's sum in units of the longest code
+    let mut kraft_sum = 0u128;
+    for (symbol, length) in code_lengths {
+        let valid_symbol = match symbol {
+            Symbol::Value(bit_count) => (1..=16).contains(bit_count),
+            Symbol::ZeroRun(bit_count) => (1..=32).contains(bit_count),
+        };
+        if !valid_symbol || !(1..=MAX_CODE_LENGTH).contains(length) {
+            return Err(EntropyError::Malformed);
+        }
+        kraft_sum += 1 << (MAX_CODE_LENGTH - length);
+        if kraft_sum > 1 << MAX_CODE_LENGTH {
+            return Err(EntropyError::Malformed);
+        }
+    }
+    Ok(())
+}
+
+/// Huffman coded part of a value or run length: its number of significant bits.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Symbol {
+    Value(u8),
+    ZeroRun(u8),
+}
+
+impl Symbol {
+    /// Number of uncoded bits following the symbol.
+    fn extra_bit_count(&self) -> u8 {
+        match self {
+            Symbol::Value(bit_count) => *bit_count,
+            // The leading bit of a run length is always set
+            Symbol::ZeroRun(bit_count) => bit_count - 1,
+        }
+    }
+}
+
+struct Token {
+    symbol: Symbol,
+    extra_bits: u64,
+}
+
+impl Token {
+    /// Negative values are offset (as in JPEG) so that their leading bit is unset.
+    fn value(value: i16) -> Token {
+        let magnitude = value.unsigned_abs();
+        let bit_count = significant_bits(magnitude as u64);
+        let extra_bits = match value < 0 {
+            true => (value as i64 + (1 << bit_count) - 1) as u64,
+            false => value as u64,
+        };
+        Token {
+            symbol: Symbol::Value(bit_count),
+            extra_bits,
+        }
+    }
+
+    fn zero_run(run: u32) -> Token {
+        let bit_count = significant_bits(run as u64);
+        Token {
+            symbol: Symbol::ZeroRun(bit_count),
+            extra_bits: run as u64 & !(1 << (bit_count - 1)),
+        }
+    }
+}
+
+fn significant_bits(value: u64) -> u8 {
+    (u64::BITS - value.leading_zeros()) as u8
+}
+
+fn decode_value(bit_count: u8, extra_bits: u64) -> Result<i16, EntropyError> {
+    if bit_count == 0 || bit_count > 16 {
+        return Err(EntropyError::Malformed);
+    }
+    let value = match extra_bits >> (bit_count - 1) {
+        1 => extra_bits as i64,
+        _ => extra_bits as i64 - (1 << bit_count) + 1,
+    };
+    i16::try_from(value).map_err(|_| EntropyError::Malformed)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Encoded {
+    /// Huffman code length of each symbol, from which the canonical codes are derived
+    code_lengths: Vec<(Symbol, u8)>,
+    symbol_count: usize,
+    bits: Vec<u8>,
+}
+
+fn run_length_encode(values: &[i16]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut run = 0u32;
+    for value in values {
+        if *value == 0 && run < u32::MAX {
+            run += 1;
+            continue;
+        }
+        if run > 0 {
+            tokens.push(Token::zero_run(run));
+            run = 0;
+        }
+        match value {
+            0 => run = 1,
+            _ => tokens.push(Token::value(*value)),
+        }
+    }
+    if run > 0 {
+        tokens.push(Token::zero_run(run));
+    }
+    tokens
+}
+
+/// Huffman code length of each symbol, sorted by symbol.
+fn code_lengths(symbols: &[Symbol]) -> Vec<(Symbol, u8)> {
+    let mut counts: HashMap<Symbol, usize> = HashMap::new();
+    for symbol in symbols {
+        *counts.entry(*symbol).or_default() += 1;
+    }
+    let mut counts: Vec<(Symbol, usize)> = counts.into_iter().collect();
+    counts.sort();
+    if counts.len() == 1 {
+        return vec![(counts[0].0, 1)];
+    }
+
+    // Merge the two least frequent nodes until a single tree remains
+    let mut parents: Vec<usize> = vec![0; counts.len()];
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = counts
+        .iter()
+        .enumerate()
+        .map(|(node, (_, count))| Reverse((*count, node)))
+        .collect();
+    while heap.len() > 1 {
+        let Reverse((count_a, node_a)) = heap.pop().expect("heap has two nodes");
+        let Reverse((count_b, node_b)) = heap.pop().expect("heap has two nodes");
+        let parent = parents.len();
+        parents.push(parent);
+        parents[node_a] = parent;
+        parents[node_b] = parent;
+        heap.push(Reverse((count_a + count_b, parent)));
+    }
+
+    // The depth of each leaf is its code length
+    counts
+        .iter()
+        .enumerate()
+        .map(|(leaf, (symbol, _))| {
+            let mut node = leaf;
+            let mut depth = 0;
+            while parents[node] != node {
+                node = parents[node];
+                depth += 1;
+            }
+            (*symbol, depth)
+        })
+        .collect()
+}
+
+/// Canonical Huffman code (and its length) of each symbol.
+fn canonical_codes(code_lengths: &[(Symbol, u8)]) -> HashMap<Symbol, (u64, u8)> {
+    let mut sorted = code_lengths.to_vec();
+    sorted.sort_by_key(|(symbol, length)| (*length, *symbol));
+    let mut codes = HashMap::with_capacity(sorted.len());
+    let mut code = 0u64;
+    let mut previous_length = sorted.first().map_or(0, |(_, length)| *length);
+    for (symbol, length) in sorted {
+        code <<= length - previous_length;
+        codes.insert(symbol, (code, length));
+        code += 1;
+        previous_length = length;
+    }
+    codes
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_count: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u64, length: u8) {
+        for i in (0..length).rev() {
+            if self.bit_count.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = ((code >> i) & 1) as u8;
+            *self.bytes.last_mut().expect("byte was pushed") |= bit << (7 - self.bit_count % 8);
+            self.bit_count += 1;
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0 }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        (0..count).try_fold(0u64, |bits, _| Some((bits << 1) | self.read()? as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let values: Vec<i16> = (0..5000)
+            .map(|x: i32| ((x * 7919) % 201 - 100) as i16)
+            .chain([0; 300])
+            .chain([i16::MIN, i16::MAX, 0, 1, -1, -2])
+            .collect();
+        let encoded = encode(&values).unwrap();
+        assert_eq!(decode(&encoded, values.len()).unwrap(), values);
+        let distinct: Vec<i16> = (-3000..3000).collect();
+        let encoded = encode(&distinct).unwrap();
+        assert!(encoded.len() < distinct.len() * 2);
+        assert_eq!(decode(&encoded, usize::MAX).unwrap(), distinct);
+        assert!(decode(&encode(&[]).unwrap(), 0).unwrap().is_empty());
+        assert_eq!(decode(&encode(&[5; 10]).unwrap(), 10).unwrap(), vec![5; 10]);
+    }
+
+    #[test]
+    fn wide_round_trip() {
+        let values: Vec<i32> = (-300..300)
+            .chain([
+                i32::MIN,
+                i32::MAX,
+                i16::MIN as i32,
+                i16::MAX as i32 + 1,
+                -70000,
+            ])
+            .collect();
+        let encoded = encode_wide(&values).unwrap();
+        assert_eq!(decode_wide(&encoded, values.len()).unwrap(), values);
+        assert!(decode_wide(&encoded, values.len() - 1).is_err());
+        let truncated = encode(&[5, i16::MIN, 1]).unwrap();
+        assert!(decode_wide(&truncated, 3).is_err());
+    }
+
+    #[test]
+    fn corrupted_tables() {
+        let values: Vec<i16> = (0..2000)
+            .map(|x| match x % 7 {
+                0 => (x % 300) as i16 - 150,
+                _ => 0,
+            })
+            .collect();
+        let encoded = encode(&values).unwrap();
+        let options = bincode::DefaultOptions::new();
+        let valid: Encoded = options.deserialize(&encoded).unwrap();
+        let with_table = |code_lengths: Vec<(Symbol, u8)>| {
+            let encoded = Encoded {
+                code_lengths,
+                symbol_count: valid.symbol_count,
+                bits: valid.bits.clone(),
+            };
+            decode(&options.serialize(&encoded).unwrap(), values.len())
+        };
+        for code_lengths in [
+            vec![(Symbol::ZeroRun(0), 1), (Symbol::Value(1), 1)],
+            vec![(Symbol::ZeroRun(40), 1), (Symbol::Value(1), 1)],
+            vec![(Symbol::Value(0), 1), (Symbol::Value(17), 1)],
+            vec![(Symbol::Value(1), 0)],
+            vec![(Symbol::Value(1), 65), (Symbol::Value(2), 1)],
+            vec![(Symbol::Value(1), 200), (Symbol::Value(2), 255)],
+            // More codes than fit in their lengths
+            vec![
+                (Symbol::Value(1), 1),
+                (Symbol::Value(2), 1),
+                (Symbol::Value(3), 1),
+            ],
+        ] {
+            let result = with_table(code_lengths.clone());
+            assert!(
+                matches!(result, Err(EntropyError::Malformed)),
+                "{code_lengths:?}: {result:?}"
+            );
+        }
+
+        // Flipped bits of the table or the coded values decode to an error or other values,
+        // without panicking
+        for (i, byte) in encoded.iter().enumerate() {
+            for flip in [0x01, 0x10, 0x80, 0xff] {
+                let mut corrupted = encoded.clone();
+                corrupted[i] = byte ^ flip;
+                let decoded = decode(&corrupted, values.len());
+                assert!(decoded.map_or(true, |decoded| decoded.len() <= values.len()));
+            }
+        }
+        for length in 0..encoded.len() {
+            let _ = decode(&encoded[..length], values.len());
+        }
+    }
+
+    #[test]
+    fn oversized_output() {
+        // A single run of 2^32 - 1 zeros
+        let mut writer = BitWriter::default();
+        writer.write(0, 1);
+        writer.write(u32::MAX as u64 >> 1, 31);
+        let encoded = Encoded {
+            code_lengths: vec![(Symbol::ZeroRun(32), 1)],
+            symbol_count: 1,
+            bits: writer.finish(),
+        };
+        let encoded = bincode::DefaultOptions::new().serialize(&encoded).unwrap();
+        assert!(matches!(
+            decode(&encoded, 1 << 20),
+            Err(EntropyError::Malformed)
+        ));
+        let values = encode(&[0, 0, 7, 0, 0]).unwrap();
+        assert_eq!(decode(&values, 5).unwrap(), vec![0, 0, 7, 0, 0]);
+        assert!(matches!(decode(&values, 4), Err(EntropyError::Malformed)));
+        assert!(matches!(decode(&values, 2), Err(EntropyError::Malformed)));
+    }
+
+    #[test]
+    fn mostly_zeros() {
+        let values: Vec<i16> = (0..100000)
+            .map(|x| match x % 1000 {
+                0 => 1000,
+                1 => -3,
+                _ => 0,
+            })
+            .collect();
+        let encoded = encode(&values).unwrap();
+        let raw = bincode::serialize(&values).unwrap();
+        assert!(
+            encoded.len() * 100 < raw.len(),
+            "raw {} encoded {}",
+            raw.len(),
+            encoded.len()
+        );
+        assert_eq!(decode(&encoded, values.len()).unwrap(), values);
+    }
+}
@@ -0,0 +1,41 @@
+//! # Errors
+//! The error returned by the compression functions, so callers can match on the cause.
+//!
+
+use crate::entropy::EntropyError;
+use crate::fft::FftError;
+use crate::format::FormatError;
+use thiserror::Error;
+
+/// Returned when compressing, decompressing or analyzing fails.
+#[derive(Error, Debug)]
+pub enum CompressionError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Format(#[from] FormatError),
+    #[error(transparent)]
+    Fft(#[from] FftError),
+    #[error(transparent)]
+    Serialization(#[from] bincode::Error),
+    #[error(transparent)]
+    Entropy(#[from] EntropyError),
+    #[error(transparent)]
+    Bmp(#[from] bmp::BmpError),
+    #[cfg(feature = "png")]
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error("input contains no samples")]
+    EmptyInput,
+    #[cfg(feature = "flac")]
+    #[error(transparent)]
+    Flac(#[from] claxon::Error),
+    #[cfg(feature = "mp3")]
+    #[error(transparent)]
+    Mp3Build(#[from] mp3lame_encoder::BuildError),
+    #[cfg(feature = "mp3")]
+    #[error(transparent)]
+    Mp3Encode(#[from] mp3lame_encoder::EncodeError),
+    #[error("{0}")]
+    InvalidParameter(&'static str),
+}
@@ -1,18 +1,69 @@
-use num_complex::Complex32;
-use rustfft::{algorithm::Dft, Fft, FftDirection};
-use std::f32::consts::PI;
+use crate::window::{self, WindowKind};
+use num_complex::{Complex, Complex32, Complex64};
+use rustfft::num_traits::{Float, FromPrimitive};
+pub use rustfft::FftDirection;
+use std::f64::consts::PI;
+use thiserror::Error;
+#[cfg(feature = "simd")]
+use wide::f32x8;
+
+/// Returned when a sample cannot be transformed.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FftError {
+    #[error("sample is empty")]
+    EmptyInput,
+    #[error("sample size {len} does not match the planned size {expected}")]
+    SizeMismatch { expected: usize, len: usize },
+    #[error("row {row} of the 2D sample has {len} values rather than {expected}")]
+    NonRectangular {
+        row: usize,
+        expected: usize,
+        len: usize,
+    },
+    #[error("hop {hop} must be between 1 and the frame size {frame_size}")]
+    InvalidHop { hop: usize, frame_size: usize },
+}
 
 /// Convert a sequence of floats to complex numbers.
 pub fn convert_sample(sample: &[f32]) -> Vec<Complex32> {
-    sample.iter().map(|x| Complex32::from(x.clone())).collect()
+    sample.iter().map(|x| Complex32::from(*x)).collect()
+}
+
+/// Convert a sequence of floats to double precision complex numbers.
+pub fn convert_sample64(sample: &[f64]) -> Vec<Complex64> {
+    sample.iter().map(|x| Complex64::from(*x)).collect()
+}
+
+/// How a sample is extended when its size is rounded up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PadMode {
+    /// Default values (zeros), which leave a discontinuity at the boundary.
+    Zero,
+    /// The sample mirrored about its last value, like 1, 2, 3 → 1, 2, 3, 2.
+    Reflect,
+    /// The last value repeated.
+    Edge,
 }
 
 /// Add default values to round sample size up to 2^n.
 pub fn round_sample_size_up<T: Default + Clone>(sample: &mut Vec<T>) {
+    round_sample_size_up_with(sample, PadMode::Zero);
+}
+
+/// Extend a sample by the pad mode to round sample size up to 2^n.
+pub fn round_sample_size_up_with<T: Default + Clone>(sample: &mut Vec<T>, mode: PadMode) {
     let original_size = sample.len();
     let nearest_power2 = 2f64.powf((original_size as f64).log2().ceil()) as usize;
-    let padding = nearest_power2 - original_size;
-    sample.append(&mut vec![T::default(); padding]);
+    for i in original_size..nearest_power2 {
+        let value = match mode {
+            PadMode::Zero => T::default(),
+            // The padding is shorter than the sample, so a single reflection is enough
+            PadMode::Reflect => sample[2 * (original_size - 1) - i].clone(),
+            PadMode::Edge => sample[original_size - 1].clone(),
+        };
+        sample.push(value);
+    }
 }
 
 /// Removes items to round sample size down to 2^n.
@@ -22,115 +73,694 @@ pub fn round_sample_size_down<T: Default + Clone>(sample: &mut Vec<T>) {
 }
 
 /// Perform a 2D FFT on a 2D sample of complex numbers (horizontal then vertical).
-pub fn fft_2d(samples: &Vec<Vec<Complex32>>) -> Vec<Vec<Complex32>> {
-    fft_2d_vertical(&fft_2d_horizontal(samples))
+///
+/// The 2D transforms compose 1D transforms with the [`FftNorm::Backward`] normalization: each
+/// inverse pass divides by the length of its lines, so [`fft_2d_inverse`] divides by
+/// width * height overall, undoing the unscaled [`fft_2d`].
+pub fn fft_2d(samples: &[Vec<Complex32>]) -> Result<Vec<Vec<Complex32>>, FftError> {
+    fft_2d_vertical(&fft_2d_horizontal(samples)?)
 }
 
 /// Perform an inverse 2D FFT on a 2D sample of complex numbers (vertical then horizontal).
-pub fn fft_2d_inverse(samples: &Vec<Vec<Complex32>>) -> Vec<Vec<Complex32>> {
-    fft_2d_horizontal_inverse(&fft_2d_vertical_inverse(samples))
+pub fn fft_2d_inverse(samples: &[Vec<Complex32>]) -> Result<Vec<Vec<Complex32>>, FftError> {
+    fft_2d_horizontal_inverse(&fft_2d_vertical_inverse(samples)?)
 }
 
-pub fn fft_2d_horizontal(samples: &Vec<Vec<Complex32>>) -> Vec<Vec<Complex32>> {
-    samples.iter().map(|y| fft(y)).collect()
+/// Perform an inverse 2D FFT like [`fft_2d_inverse`], keeping the real part of each value.
+///
+/// Restores a real 2D sample (such as an image channel) from its transform, where the imaginary
+/// parts are only rounding error. Unlike the magnitude, the real part keeps the sign of values
+/// below zero.
+pub fn fft_2d_inverse_real(samples: &[Vec<Complex32>]) -> Result<Vec<Vec<f32>>, FftError> {
+    Ok(fft_2d_inverse(samples)?
+        .iter()
+        .map(|row| row.iter().map(|x| x.re).collect())
+        .collect())
 }
 
-pub fn fft_2d_horizontal_inverse(samples: &Vec<Vec<Complex32>>) -> Vec<Vec<Complex32>> {
-    samples.iter().map(|y| fft_inverse(y)).collect()
+/// Perform a 2D FFT on a 2D sample of double precision complex numbers, like [`fft_2d`].
+pub fn fft_2d64(samples: &[Vec<Complex64>]) -> Result<Vec<Vec<Complex64>>, FftError> {
+    check_rectangular(samples)?;
+    map_columns(
+        &map_lines(samples, FftDirection::Forward)?,
+        FftDirection::Forward,
+    )
 }
 
-pub fn fft_2d_vertical(samples: &Vec<Vec<Complex32>>) -> Vec<Vec<Complex32>> {
-    let (height, width) = (samples.len(), samples[0].len());
-    let transposed: Vec<Vec<Complex32>> = (0..width)
-        .map(|x| fft(&(0..height).map(|y| samples[y][x]).collect()))
-        .collect();
-    (0..height)
-        .map(|y| (0..width).map(|x| transposed[x][y]).collect())
-        .collect()
+/// Perform an inverse 2D FFT on a 2D sample of double precision complex numbers, like
+/// [`fft_2d_inverse`].
+pub fn fft_2d_inverse64(samples: &[Vec<Complex64>]) -> Result<Vec<Vec<Complex64>>, FftError> {
+    check_rectangular(samples)?;
+    map_lines(
+        &map_columns(samples, FftDirection::Inverse)?,
+        FftDirection::Inverse,
+    )
 }
 
-pub fn fft_2d_vertical_inverse(samples: &Vec<Vec<Complex32>>) -> Vec<Vec<Complex32>> {
-    let (height, width) = (samples.len(), samples[0].len());
-    let transposed: Vec<Vec<Complex32>> = (0..width)
-        .map(|x| fft_inverse(&(0..height).map(|y| samples[y][x]).collect()))
-        .collect();
-    (0..height)
-        .map(|y| (0..width).map(|x| transposed[x][y]).collect())
-        .collect()
+pub fn fft_2d_horizontal(samples: &[Vec<Complex32>]) -> Result<Vec<Vec<Complex32>>, FftError> {
+    check_rectangular(samples)?;
+    map_lines(samples, FftDirection::Forward)
+}
+
+pub fn fft_2d_horizontal_inverse(
+    samples: &[Vec<Complex32>],
+) -> Result<Vec<Vec<Complex32>>, FftError> {
+    check_rectangular(samples)?;
+    map_lines(samples, FftDirection::Inverse)
+}
+
+pub fn fft_2d_vertical(samples: &[Vec<Complex32>]) -> Result<Vec<Vec<Complex32>>, FftError> {
+    check_rectangular(samples)?;
+    map_columns(samples, FftDirection::Forward)
+}
+
+pub fn fft_2d_vertical_inverse(
+    samples: &[Vec<Complex32>],
+) -> Result<Vec<Vec<Complex32>>, FftError> {
+    check_rectangular(samples)?;
+    map_columns(samples, FftDirection::Inverse)
+}
+
+/// Returns an error if the 2D sample is empty or its rows differ in length.
+fn check_rectangular<T>(samples: &[Vec<T>]) -> Result<(), FftError> {
+    let expected = samples.first().map_or(0, Vec::len);
+    if expected == 0 {
+        return Err(FftError::EmptyInput);
+    }
+    match samples.iter().position(|row| row.len() != expected) {
+        Some(row) => Err(FftError::NonRectangular {
+            row,
+            expected,
+            len: samples[row].len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Transform each line of a 2D sample (in parallel with the `parallel` feature).
+///
+/// The lines share a single planner, as they are all the same size.
+fn map_lines<T: FftFloat>(
+    lines: &[Vec<Complex<T>>],
+    direction: FftDirection,
+) -> Result<Vec<Vec<Complex<T>>>, FftError> {
+    let Some(first) = lines.first() else {
+        return Ok(Vec::new());
+    };
+    let planner = FftPlanner::new(first.len(), direction)?;
+    let transform = |line: &Vec<Complex<T>>| {
+        let mut buffer = line.clone();
+        planner.process(&mut buffer)?;
+        Ok(buffer)
+    };
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        lines.par_iter().map(transform).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        lines.iter().map(transform).collect()
+    }
+}
+
+/// Number of columns gathered at a time by [`map_columns`].
+const COLUMN_BAND: usize = 64;
+
+/// Transform each column of a (rectangular) 2D sample (in parallel with the `parallel` feature).
+///
+/// Bands of columns are gathered into reusable buffers, transformed and scattered back, so only
+/// the output is allocated at the size of the sample rather than transposed copies.
+fn map_columns<T: FftFloat>(
+    samples: &[Vec<Complex<T>>],
+    direction: FftDirection,
+) -> Result<Vec<Vec<Complex<T>>>, FftError> {
+    let (height, width) = (samples.len(), samples.first().map_or(0, Vec::len));
+    let planner = FftPlanner::new(height, direction)?;
+    let mut output = samples.to_vec();
+    let mut band = vec![vec![Complex::new(T::zero(), T::zero()); height]; COLUMN_BAND.min(width)];
+    for start in (0..width).step_by(COLUMN_BAND) {
+        let columns = &mut band[..COLUMN_BAND.min(width - start)];
+        for (y, row) in output.iter().enumerate() {
+            for (column, value) in columns.iter_mut().zip(&row[start..]) {
+                column[y] = *value;
+            }
+        }
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+            columns
+                .par_iter_mut()
+                .try_for_each(|column| planner.process(column))?;
+        }
+        #[cfg(not(feature = "parallel"))]
+        {
+            columns
+                .iter_mut()
+                .try_for_each(|column| planner.process(column))?;
+        }
+        for (y, row) in output.iter_mut().enumerate() {
+            for (value, column) in row[start..].iter_mut().zip(columns.iter()) {
+                *value = column[y];
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Scaling of the forward and inverse transforms, such that an inverse undoes a forward
+/// transform of the same normalization (except for `None`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FftNorm {
+    /// Neither direction is scaled, so a round trip multiplies by the sample size.
+    None,
+    /// The inverse divides by the sample size.
+    #[default]
+    Backward,
+    /// Both directions divide by the square root of the sample size, preserving energy.
+    Ortho,
+}
+
+impl FftNorm {
+    /// Factor to divide the transform by, for a sample size in the given direction.
+    fn divisor(&self, sample_size: usize, direction: FftDirection) -> Option<f64> {
+        match (self, direction) {
+            (FftNorm::Backward, FftDirection::Inverse) => Some(sample_size as f64),
+            (FftNorm::Ortho, _) => Some((sample_size as f64).sqrt()),
+            _ => None,
+        }
+    }
 }
 
 /// Perform an FFT on a sample of complex numbers.
-pub fn fft(samples: &Vec<Complex32>) -> Vec<Complex32> {
-    assert_sample_size(&samples);
-    fft_recursive(samples.clone(), 1.)
+///
+/// Sample sizes of 2^n use the radix-2 algorithm, other sizes fall back to Bluestein's algorithm.
+pub fn fft(samples: &[Complex32]) -> Result<Vec<Complex32>, FftError> {
+    transform(samples, FftDirection::Forward, FftNorm::Backward)
 }
 
 /// Perform an inverse FFT on a sample of complex numbers.
-pub fn fft_inverse(samples: &Vec<Complex32>) -> Vec<Complex32> {
-    assert_sample_size(&samples);
-    let sample_size = samples.len() as f32;
-    fft_recursive(samples.clone(), -1.)
+pub fn fft_inverse(samples: &[Complex32]) -> Result<Vec<Complex32>, FftError> {
+    transform(samples, FftDirection::Inverse, FftNorm::Backward)
+}
+
+/// Perform an FFT on a sample of complex numbers with the given normalization, see [`fft`].
+pub fn fft_with_norm(samples: &[Complex32], norm: FftNorm) -> Result<Vec<Complex32>, FftError> {
+    transform(samples, FftDirection::Forward, norm)
+}
+
+/// Perform an inverse FFT on a sample of complex numbers with the given normalization.
+pub fn fft_inverse_with_norm(
+    samples: &[Complex32],
+    norm: FftNorm,
+) -> Result<Vec<Complex32>, FftError> {
+    transform(samples, FftDirection::Inverse, norm)
+}
+
+/// Perform an FFT on a sample of double precision complex numbers.
+pub fn fft64(samples: &[Complex64]) -> Result<Vec<Complex64>, FftError> {
+    transform(samples, FftDirection::Forward, FftNorm::Backward)
+}
+
+/// Perform an inverse FFT on a sample of double precision complex numbers.
+pub fn fft_inverse64(samples: &[Complex64]) -> Result<Vec<Complex64>, FftError> {
+    transform(samples, FftDirection::Inverse, FftNorm::Backward)
+}
+
+/// Perform an FFT on a sample of real numbers, returning only the `n/2 + 1` non-redundant
+/// frequency bins (the rest are their complex conjugates). Undone by [`irfft`].
+///
+/// Even sample sizes are transformed as a complex sample of half the size.
+pub fn rfft(samples: &[f32]) -> Result<Vec<Complex32>, FftError> {
+    let sample_size = samples.len();
+    let half = sample_size / 2;
+    if sample_size < 2 || sample_size % 2 == 1 {
+        let mut bins = fft(&convert_sample(samples))?;
+        bins.truncate(half + 1);
+        return Ok(bins);
+    }
+    // Pack even samples as the real part and odd samples as the imaginary part
+    let packed: Vec<Complex32> = samples
+        .chunks_exact(2)
+        .map(|pair| Complex32::new(pair[0], pair[1]))
+        .collect();
+    let packed = fft(&packed)?;
+    Ok((0..=half)
+        .map(|k| {
+            let z = packed[k % half];
+            let mirror = packed[(half - k) % half].conj();
+            let even = (z + mirror) * 0.5;
+            let odd = (z - mirror) * Complex32::new(0., -0.5);
+            even + odd * unit_complex::<f32>(-2. * PI * k as f64 / sample_size as f64)
+        })
+        .collect())
+}
+
+/// Perform an inverse FFT on the non-redundant frequency bins of a real sample of size
+/// `sample_size`, see [`rfft`].
+///
+/// The bins are extended by their complex conjugates, so the result is the real sample of a
+/// Hermitian-symmetric spectrum. The DC and Nyquist bins of such a spectrum are real, so only
+/// their real parts are kept, as when taking the real part of a full inverse.
+pub fn irfft(bins: &[Complex32], sample_size: usize) -> Result<Vec<f32>, FftError> {
+    if sample_size == 0 {
+        return Err(FftError::EmptyInput);
+    }
+    let half = sample_size / 2;
+    if bins.len() != half + 1 {
+        return Err(FftError::SizeMismatch {
+            expected: half + 1,
+            len: bins.len(),
+        });
+    }
+    if sample_size < 2 || sample_size % 2 == 1 {
+        let mut full = bins.to_vec();
+        full.extend((1..sample_size - half).rev().map(|k| bins[k].conj()));
+        full.truncate(sample_size);
+        return Ok(fft_inverse(&full)?.iter().map(|x| x.re).collect());
+    }
+    let bin = |k: usize| match k == 0 || k == half {
+        true => Complex32::from(bins[k].re),
+        false => bins[k],
+    };
+    let packed: Vec<Complex32> = (0..half)
+        .map(|k| {
+            let mirror = bin(half - k).conj();
+            let even = (bin(k) + mirror) * 0.5;
+            let odd = (bin(k) - mirror)
+                * 0.5
+                * unit_complex::<f32>(2. * PI * k as f64 / sample_size as f64);
+            even + odd * Complex32::i()
+        })
+        .collect();
+    Ok(fft_inverse(&packed)?
         .iter()
-        .map(|x| x / sample_size)
-        .collect()
+        .flat_map(|x| [x.re, x.im])
+        .collect())
 }
 
-/// Returns the amplitudes of the discernable frequencies in bins (by the frequency resolution).
+/// Returns the amplitudes of the discernable frequencies in bins (by the frequency resolution),
+/// from the DC offset up to and including the Nyquist frequency.
+///
+/// Bin `k` of a transform of `n` samples is at `k * sample_rate / n` Hz. The energy of each
+/// frequency is split between its bin and the mirrored bin, so those amplitudes are doubled,
+/// while the DC bin and (for even sizes) the Nyquist bin have no mirror.
 pub fn frequency_bins(sample: &[Complex32]) -> Vec<f32> {
-    let sample_size = sample.len() as f32;
-    let alias_index = (sample_size / 2.) as usize;
-    sample[0..alias_index]
+    let sample_size = sample.len();
+    if sample_size == 0 {
+        return Vec::new();
+    }
+    let nyquist = sample_size / 2;
+    sample[..=nyquist]
         .iter()
-        .map(|x| x.norm() * 2. / sample_size)
+        .enumerate()
+        .map(|(k, x)| {
+            let mirrored = k != 0 && 2 * k != sample_size;
+            let scale = if mirrored { 2. } else { 1. };
+            x.norm() * scale / sample_size as f32
+        })
         .collect()
 }
 
-fn fft_recursive(sample: Vec<Complex32>, coeff: f32) -> Vec<Complex32> {
-    // WARNING: will fail if sample size is not 2^n
-    let sample_size = sample.len();
-    if sample_size == 1 {
-        return sample;
-    }
-    let half_size = sample_size / 2;
-
-    // Collect transforms of even and odd samples (recursive)
-    let mut evens = Vec::with_capacity(half_size);
-    let mut odds = Vec::with_capacity(half_size);
-    for i in 0..half_size {
-        evens.push(sample[2 * i]);
-        odds.push(sample[2 * i + 1]);
-    }
-    let freq_evens = fft_recursive(evens, coeff);
-    let freq_odds = fft_recursive(odds, coeff);
-
-    // Calculate frequency bins
-    let mut freq_bins = vec![Complex32::default(); sample_size];
-    let coeff_const = Complex32::new(0., coeff * -2. * PI / sample_size as f32);
-    for k in 0..half_size {
-        let k2 = k + half_size;
-        let ek1 = coeff_const * k as f32;
-        let ek2 = coeff_const * k2 as f32;
-        freq_bins[k] = freq_evens[k] + ek1.exp() * freq_odds[k];
-        freq_bins[k2] = freq_evens[k] + ek2.exp() * freq_odds[k];
-    }
+/// Shift the zero frequency to the center of the sample, undone by [`ifftshift`].
+///
+/// For odd sizes the zero frequency lands exactly in the center, with one more negative
+/// frequency before it than positive frequencies after it.
+pub fn fftshift<T>(sample: &mut [T]) {
+    let half = sample.len() / 2;
+    sample.rotate_right(half);
+}
+
+/// Shift the zero frequency from the center back to the start of the sample.
+pub fn ifftshift<T>(sample: &mut [T]) {
+    let half = sample.len() / 2;
+    sample.rotate_left(half);
+}
+
+/// Shift the zero frequency to the center of both dimensions, undone by [`ifftshift_2d`].
+pub fn fftshift_2d<T>(samples: &mut [Vec<T>]) {
+    samples.iter_mut().for_each(|row| fftshift(row));
+    fftshift(samples);
+}
+
+/// Shift the zero frequency from the center of both dimensions back to the start.
+pub fn ifftshift_2d<T>(samples: &mut [Vec<T>]) {
+    samples.iter_mut().for_each(|row| ifftshift(row));
+    ifftshift(samples);
+}
+
+/// Pairs the frequency bins (see [`frequency_bins`]) of a transform of `sample_size` samples
+/// with their frequencies in Hz, as (frequency, amplitude).
+pub fn labeled_bins(
+    freq_bins: &[f32],
+    sample_rate: usize,
+    sample_size: usize,
+) -> impl Iterator<Item = (f32, f32)> + '_ {
+    let resolution = sample_rate as f32 / sample_size as f32;
     freq_bins
+        .iter()
+        .enumerate()
+        .map(move |(k, amplitude)| (k as f32 * resolution, *amplitude))
+}
+
+/// Returns the strongest `n` frequencies as (frequency in Hz, amplitude) pairs, strongest first,
+/// from the bins of an even-sized transform (see [`frequency_bins`]).
+///
+/// Only local maxima are considered, so the bins next to a peak that its energy leaks into are
+/// not counted as frequencies of their own. The DC offset is excluded.
+pub fn dominant_frequencies(freq_bins: &[f32], sample_rate: usize, n: usize) -> Vec<(f32, f32)> {
+    if freq_bins.len() < 2 {
+        return Vec::new();
+    }
+    // The last bin is the Nyquist frequency
+    let sample_size = 2 * (freq_bins.len() - 1);
+    let mut peaks: Vec<(f32, f32)> = labeled_bins(freq_bins, sample_rate, sample_size)
+        .enumerate()
+        .skip(1)
+        .filter(|(k, (_, amplitude))| {
+            *amplitude > freq_bins[k - 1]
+                && freq_bins.get(k + 1).is_none_or(|next| amplitude >= next)
+        })
+        .map(|(_, peak)| peak)
+        .collect();
+    peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+    peaks.truncate(n);
+    peaks
+}
+
+/// Short-time Fourier transform: the frequency bins (see [`frequency_bins`]) of each windowed
+/// frame of `frame_size` samples, with frames starting every `hop` samples.
+///
+/// Returns one column per frame, in order of time. Samples after the last complete frame are
+/// ignored. Returns an error if the hop is zero or larger than the frame size, which would skip
+/// samples. Frames that should also be overlap-added back can be checked with
+/// [`window::cola_gain`].
+pub fn stft(
+    samples: &[f32],
+    frame_size: usize,
+    hop: usize,
+    window: WindowKind,
+) -> Result<Vec<Vec<f32>>, FftError> {
+    if hop == 0 || hop > frame_size {
+        return Err(FftError::InvalidHop { hop, frame_size });
+    }
+    let planner = FftPlanner::new(frame_size, FftDirection::Forward)?;
+    let frame_count = match samples.len().checked_sub(frame_size) {
+        Some(remainder) => remainder / hop + 1,
+        None => 0,
+    };
+    (0..frame_count)
+        .map(|f| {
+            let mut frame = samples[f * hop..f * hop + frame_size].to_vec();
+            window::apply_window(&mut frame, window);
+            let mut buffer = convert_sample(&frame);
+            planner.process(&mut buffer)?;
+            Ok(frequency_bins(&buffer))
+        })
+        .collect()
+}
+
+/// Precomputed twiddle factors for transforming samples of a fixed size.
+///
+/// Reusing a planner avoids recomputing the twiddle factors when transforming many samples of
+/// the same size. By default inverse planners normalize the output (like [`fft_inverse`]).
+pub struct FftPlanner<T = f32> {
+    sample_size: usize,
+    direction: FftDirection,
+    norm: FftNorm,
+    algorithm: Algorithm<T>,
+}
+
+impl<T: FftFloat> FftPlanner<T> {
+    /// Plan a transform for the sample size in the given direction.
+    pub fn new(sample_size: usize, direction: FftDirection) -> Result<Self, FftError> {
+        Self::with_norm(sample_size, direction, FftNorm::Backward)
+    }
+
+    /// Plan a transform for the sample size in the given direction, with the normalization.
+    pub fn with_norm(
+        sample_size: usize,
+        direction: FftDirection,
+        norm: FftNorm,
+    ) -> Result<Self, FftError> {
+        if sample_size == 0 {
+            return Err(FftError::EmptyInput);
+        }
+        let coeff = match direction {
+            FftDirection::Forward => 1.,
+            FftDirection::Inverse => -1.,
+        };
+        let algorithm = match sample_size.is_power_of_two() {
+            true => Algorithm::Radix2(Radix2::new(sample_size, coeff)),
+            false => Algorithm::Bluestein(Box::new(Bluestein::new(sample_size, coeff))),
+        };
+        Ok(FftPlanner {
+            sample_size,
+            direction,
+            norm,
+            algorithm,
+        })
+    }
+
+    /// Transform the samples in place.
+    pub fn process(&self, buffer: &mut [Complex<T>]) -> Result<(), FftError> {
+        if buffer.len() != self.sample_size {
+            return Err(FftError::SizeMismatch {
+                expected: self.sample_size,
+                len: buffer.len(),
+            });
+        }
+        match &self.algorithm {
+            Algorithm::Radix2(radix2) => radix2.process(buffer),
+            Algorithm::Bluestein(bluestein) => bluestein.process(buffer),
+        }
+        if let Some(divisor) = self.norm.divisor(self.sample_size, self.direction) {
+            let divisor = T::from_f64(divisor).expect("float conversion");
+            buffer.iter_mut().for_each(|x| *x = *x / divisor);
+        }
+        Ok(())
+    }
 }
 
-fn assert_sample_size(samples: &Vec<Complex32>) {
-    let sample_log = f32::log2(samples.len() as f32);
-    assert_eq!(
-        sample_log,
-        (sample_log as i32 as f32),
-        "Sample size is not a power of 2: {}",
-        samples.len()
-    );
+fn transform<T: FftFloat>(
+    samples: &[Complex<T>],
+    direction: FftDirection,
+    norm: FftNorm,
+) -> Result<Vec<Complex<T>>, FftError> {
+    let mut buffer = samples.to_vec();
+    FftPlanner::with_norm(samples.len(), direction, norm)?.process(&mut buffer)?;
+    Ok(buffer)
+}
+
+enum Algorithm<T> {
+    Radix2(Radix2<T>),
+    Bluestein(Box<Bluestein<T>>),
+}
+
+/// Iterative radix-2 Cooley-Tukey FFT, performed in place. Requires a sample size of 2^n.
+struct Radix2<T> {
+    twiddles: Vec<Complex<T>>,
+}
+
+impl<T: FftFloat> Radix2<T> {
+    fn new(sample_size: usize, coeff: f64) -> Self {
+        // Twiddle factors for the full size (computed in f64 to avoid accumulating error)
+        let twiddles = (0..sample_size / 2)
+            .map(|k| unit_complex(coeff * -2. * PI * k as f64 / sample_size as f64))
+            .collect();
+        Radix2 { twiddles }
+    }
+
+    fn process(&self, buffer: &mut [Complex<T>]) {
+        self.process_with(buffer, T::butterflies);
+    }
+
+    /// Transform the samples in place, with the function for each butterfly pass.
+    fn process_with(
+        &self,
+        buffer: &mut [Complex<T>],
+        butterflies: impl Fn(&mut [Complex<T>], &[Complex<T>], usize),
+    ) {
+        let sample_size = buffer.len();
+        if sample_size <= 1 {
+            return;
+        }
+
+        // Reorder samples by bit-reversed index so the butterflies can run in place
+        let bits = sample_size.trailing_zeros();
+        for i in 0..sample_size {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                buffer.swap(i, j);
+            }
+        }
+
+        // Butterfly passes, doubling the transform size each pass
+        let mut size = 2;
+        while size <= sample_size {
+            butterflies(buffer, &self.twiddles, size);
+            size *= 2;
+        }
+    }
+}
+
+/// Floating point types that can be transformed, `f32` and `f64`.
+///
+/// With the `simd` feature, `f32` butterflies are computed several at a time.
+pub trait FftFloat: Float + FromPrimitive + Send + Sync {
+    /// Combine the pairs of transforms of half the size into transforms of the size, in place.
+    fn butterflies(buffer: &mut [Complex<Self>], twiddles: &[Complex<Self>], size: usize) {
+        scalar_butterflies(buffer, twiddles, size);
+    }
+}
+
+impl FftFloat for f64 {}
+
+#[cfg(not(feature = "simd"))]
+impl FftFloat for f32 {}
+
+#[cfg(feature = "simd")]
+impl FftFloat for f32 {
+    fn butterflies(buffer: &mut [Complex32], twiddles: &[Complex32], size: usize) {
+        simd_butterflies(buffer, twiddles, size);
+    }
+}
+
+/// A butterfly pass of the radix-2 transform, see [`FftFloat::butterflies`].
+fn scalar_butterflies<T: Float>(buffer: &mut [Complex<T>], twiddles: &[Complex<T>], size: usize) {
+    let half = size / 2;
+    let stride = buffer.len() / size;
+    for start in (0..buffer.len()).step_by(size) {
+        for k in 0..half {
+            let even = buffer[start + k];
+            let odd = twiddles[k * stride] * buffer[start + k + half];
+            buffer[start + k] = even + odd;
+            buffer[start + k + half] = even - odd;
+        }
+    }
+}
+
+/// A butterfly pass of the radix-2 transform, computing eight butterflies at a time (the first
+/// passes are too small and fall back to [`scalar_butterflies`]).
+#[cfg(feature = "simd")]
+fn simd_butterflies(buffer: &mut [Complex32], twiddles: &[Complex32], size: usize) {
+    const LANES: usize = 8;
+    let half = size / 2;
+    if half < LANES {
+        return scalar_butterflies(buffer, twiddles, size);
+    }
+    let stride = buffer.len() / size;
+    // Split the complex values of consecutive butterflies into their real and imaginary parts
+    let split = |values: &mut dyn Iterator<Item = Complex32>| {
+        let (mut re, mut im) = ([0.; LANES], [0.; LANES]);
+        for (i, value) in values.enumerate() {
+            (re[i], im[i]) = (value.re, value.im);
+        }
+        (f32x8::from(re), f32x8::from(im))
+    };
+    for transform in buffer.chunks_exact_mut(size) {
+        let (evens, odds) = transform.split_at_mut(half);
+        for k in (0..half).step_by(LANES) {
+            let (even, odd) = (&mut evens[k..k + LANES], &mut odds[k..k + LANES]);
+            let (twiddle_re, twiddle_im) = split(&mut (k..k + LANES).map(|k| twiddles[k * stride]));
+            let (even_re, even_im) = split(&mut even.iter().copied());
+            let (odd_re, odd_im) = split(&mut odd.iter().copied());
+            let product_re = odd_re * twiddle_re - odd_im * twiddle_im;
+            let product_im = odd_re * twiddle_im + odd_im * twiddle_re;
+            let sums = (
+                (even_re + product_re).to_array(),
+                (even_im + product_im).to_array(),
+            );
+            let differences = (
+                (even_re - product_re).to_array(),
+                (even_im - product_im).to_array(),
+            );
+            for i in 0..LANES {
+                even[i] = Complex32::new(sums.0[i], sums.1[i]);
+                odd[i] = Complex32::new(differences.0[i], differences.1[i]);
+            }
+        }
+    }
+}
+
+/// Bluestein's algorithm: expresses a transform of any size as a convolution, which is computed
+/// with radix-2 transforms of the next power of 2 that fits it.
+struct Bluestein<T> {
+    chirp: Vec<Complex<T>>,
+    /// Transformed (and normalized) conjugate chirp to convolve with
+    kernel: Vec<Complex<T>>,
+    forward: Radix2<T>,
+    inverse: Radix2<T>,
+}
+
+impl<T: FftFloat> Bluestein<T> {
+    fn new(sample_size: usize, coeff: f64) -> Self {
+        let padded_size = (2 * sample_size - 1).next_power_of_two();
+        let forward = Radix2::new(padded_size, 1.);
+        let inverse = Radix2::new(padded_size, -1.);
+
+        // Chirp factors (k^2 is reduced modulo 2n to keep the angle precise)
+        let chirp: Vec<Complex<T>> = (0..sample_size)
+            .map(|k| {
+                let k_squared = (k * k) % (2 * sample_size);
+                unit_complex(coeff * -PI * k_squared as f64 / sample_size as f64)
+            })
+            .collect();
+
+        let mut kernel = vec![Complex::new(T::zero(), T::zero()); padded_size];
+        kernel[0] = chirp[0].conj();
+        for k in 1..sample_size {
+            kernel[k] = chirp[k].conj();
+            kernel[padded_size - k] = chirp[k].conj();
+        }
+        forward.process(&mut kernel);
+        let scale = from_usize::<T>(padded_size);
+        kernel.iter_mut().for_each(|x| *x = *x / scale);
+
+        Bluestein {
+            chirp,
+            kernel,
+            forward,
+            inverse,
+        }
+    }
+
+    fn process(&self, buffer: &mut [Complex<T>]) {
+        // Convolve the chirped samples with the conjugate chirp
+        let mut samples = vec![Complex::new(T::zero(), T::zero()); self.kernel.len()];
+        for (k, sample) in buffer.iter().enumerate() {
+            samples[k] = *sample * self.chirp[k];
+        }
+        self.forward.process(&mut samples);
+        for (sample, kernel) in samples.iter_mut().zip(self.kernel.iter()) {
+            *sample = *sample * kernel;
+        }
+        self.inverse.process(&mut samples);
+
+        for (k, sample) in buffer.iter_mut().enumerate() {
+            *sample = samples[k] * self.chirp[k];
+        }
+    }
+}
+
+/// Complex number of magnitude 1 at the given angle.
+fn unit_complex<T: Float + FromPrimitive>(angle: f64) -> Complex<T> {
+    Complex::new(from_f64(angle.cos()), from_f64(angle.sin()))
+}
+
+fn from_f64<T: FromPrimitive>(value: f64) -> T {
+    T::from_f64(value).expect("float conversion")
+}
+
+fn from_usize<T: FromPrimitive>(value: usize) -> T {
+    T::from_usize(value).expect("float conversion")
 }
 
 #[allow(dead_code)] // For testing
 fn basefft(samples: &[Complex32]) -> Vec<Complex32> {
     // Computes a forward FFT
     let mut result = samples.to_vec();
-    let fft = Dft::new(result.len(), FftDirection::Forward);
+    let fft = rustfft::FftPlanner::new().plan_fft(result.len(), FftDirection::Forward);
     fft.process(&mut result);
     result
 }
@@ -142,7 +772,7 @@ mod tests {
     #[test]
     fn compare_builtin() {
         let sample = convert_sample(&[0., 1., 0., -1.]);
-        let result = fft(&sample);
+        let result = fft(&sample).unwrap();
         let expected = basefft(&sample);
         let epsilon = 10f32.powi(-5);
         println!("result {:?}\nexpected {:?}", result, expected);
@@ -155,7 +785,7 @@ mod tests {
     #[test]
     fn inversion() {
         let sample = convert_sample(&[1., 2., 3., 4., 5., 6., 7., 8.]);
-        let result = fft(&fft_inverse(&sample));
+        let result = fft(&fft_inverse(&sample).unwrap()).unwrap();
         let epsilon = 10f32.powi(-5);
         println!("result {:?}\nexpected {:?}", sample, result);
         for i in 0..result.len() {
@@ -163,4 +793,489 @@ mod tests {
             assert!(f32::abs(diff) < epsilon);
         }
     }
+
+    #[test]
+    fn ortho_symmetry() {
+        for sample_size in [8, 12] {
+            let sample: Vec<Complex32> = (0..sample_size)
+                .map(|x| Complex32::new((x * 7 % 5) as f32, (x * 3 % 4) as f32 - 1.))
+                .collect();
+            let conjugate = |sample: &[Complex32]| -> Vec<Complex32> {
+                sample.iter().map(|x| x.conj()).collect()
+            };
+            let forward = fft_with_norm(&sample, FftNorm::Ortho).unwrap();
+            // The inverse is the forward transform with conjugated input and output
+            let inverse = fft_inverse_with_norm(&conjugate(&sample), FftNorm::Ortho).unwrap();
+            for (a, b) in forward.iter().zip(conjugate(&inverse).iter()) {
+                assert!((a - b).norm() < 1e-4, "{a} != {b}");
+            }
+            // Energy is preserved, and the inverse undoes the forward transform
+            let energy = |sample: &[Complex32]| sample.iter().map(|x| x.norm_sqr()).sum::<f32>();
+            assert!((energy(&forward) - energy(&sample)).abs() < 1e-3);
+            let restored = fft_inverse_with_norm(&forward, FftNorm::Ortho).unwrap();
+            for (a, b) in sample.iter().zip(restored.iter()) {
+                assert!((a - b).norm() < 1e-4, "{a} != {b}");
+            }
+            // Without normalization a round trip scales by the sample size
+            let unscaled = fft_inverse_with_norm(
+                &fft_with_norm(&sample, FftNorm::None).unwrap(),
+                FftNorm::None,
+            )
+            .unwrap();
+            for (a, b) in sample.iter().zip(unscaled.iter()) {
+                assert!((a * sample_size as f32 - b).norm() < 1e-3, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn pad_modes() {
+        let padded = |mode| {
+            let mut sample = vec![1., 2., 3., 4., 5.];
+            round_sample_size_up_with(&mut sample, mode);
+            sample
+        };
+        assert_eq!(padded(PadMode::Zero), [1., 2., 3., 4., 5., 0., 0., 0.]);
+        assert_eq!(padded(PadMode::Reflect), [1., 2., 3., 4., 5., 4., 3., 2.]);
+        assert_eq!(padded(PadMode::Edge), [1., 2., 3., 4., 5., 5., 5., 5.]);
+        // The longest padding reflects all but the first value
+        let mut sample: Vec<f32> = (1..=9).map(|x| x as f32).collect();
+        round_sample_size_up_with(&mut sample, PadMode::Reflect);
+        assert_eq!(sample[9..], [8., 7., 6., 5., 4., 3., 2.]);
+        // Sizes that are already a power of 2 are unchanged
+        let mut sample = vec![7.];
+        round_sample_size_up_with(&mut sample, PadMode::Reflect);
+        assert_eq!(sample, [7.]);
+    }
+
+    #[test]
+    fn arbitrary_sizes() {
+        for sample_size in [6, 100, 1000] {
+            let sample: Vec<f32> = (0..sample_size)
+                .map(|x| (x as f32 * 0.3).sin() + (x % 7) as f32 * 0.1)
+                .collect();
+            let sample = convert_sample(&sample);
+            let result = fft(&sample).unwrap();
+            let expected = basefft(&sample);
+            let scale = expected.iter().map(|x| x.norm()).fold(0., f32::max);
+            let epsilon = 10f32.powi(-4);
+            for i in 0..expected.len() {
+                let diff = (result[i] - expected[i]).norm() / scale;
+                assert!(
+                    diff < epsilon,
+                    "size {sample_size} bin {i} differs by {diff}"
+                );
+            }
+            let inverted = fft_inverse(&result).unwrap();
+            for i in 0..sample.len() {
+                let diff = (inverted[i] - sample[i]).norm();
+                assert!(
+                    diff < epsilon,
+                    "size {sample_size} sample {i} differs by {diff}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn real_sample() {
+        for sample_size in [1, 2, 7, 8, 100, 1000] {
+            let sample: Vec<f32> = (0..sample_size)
+                .map(|x| (x as f32 * 0.3).sin() + (x % 7) as f32 * 0.1)
+                .collect();
+            let bins = rfft(&sample).unwrap();
+            assert_eq!(bins.len(), sample_size / 2 + 1);
+            let expected = fft(&convert_sample(&sample)).unwrap();
+            for (bin, expected) in bins.iter().zip(&expected) {
+                assert!((bin - expected).norm() < 1e-3, "size {sample_size}");
+            }
+            let inverted = irfft(&bins, sample_size).unwrap();
+            assert_eq!(inverted.len(), sample_size);
+            for (x, y) in sample.iter().zip(&inverted) {
+                assert!((x - y).abs() < 1e-4, "size {sample_size}: {x} != {y}");
+            }
+        }
+        assert!(irfft(&[Complex32::default(); 3], 8).is_err());
+    }
+
+    #[test]
+    fn hermitian_inverse() {
+        for sample_size in [2, 7, 8, 64] {
+            // Imaginary parts in every bin, including DC and Nyquist
+            let bins: Vec<Complex32> = (0..=sample_size / 2)
+                .map(|k| Complex32::new((k as f32 * 0.7).cos(), (k as f32 * 1.3).sin() + 0.5))
+                .collect();
+            let mut full = bins.clone();
+            full.extend(
+                (1..sample_size - sample_size / 2)
+                    .rev()
+                    .map(|k| bins[k].conj()),
+            );
+            full.truncate(sample_size);
+            let expected = fft_inverse(&full).unwrap();
+            let inverted = irfft(&bins, sample_size).unwrap();
+            for (x, y) in inverted.iter().zip(&expected) {
+                assert!((x - y.re).abs() < 1e-5, "size {sample_size}: {x} != {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_sample() {
+        assert_eq!(fft(&[]), Err(FftError::EmptyInput));
+        assert_eq!(fft_inverse(&[]), Err(FftError::EmptyInput));
+        assert_eq!(rfft(&[]), Err(FftError::EmptyInput));
+        assert_eq!(irfft(&[Complex32::default()], 0), Err(FftError::EmptyInput));
+        assert_eq!(fft_2d(&[]), Err(FftError::EmptyInput));
+        assert_eq!(fft_2d_inverse(&[vec![], vec![]]), Err(FftError::EmptyInput));
+    }
+
+    #[test]
+    fn double_precision_inversion() {
+        let sample: Vec<f64> = (0..1 << 16)
+            .map(|x| (x as f64 * 0.01).sin() * 100. + (x % 13) as f64)
+            .collect();
+        let sample = convert_sample64(&sample);
+        let result = fft_inverse64(&fft64(&sample).unwrap()).unwrap();
+        let epsilon = 10f64.powi(-10);
+        for i in 0..sample.len() {
+            let diff = (result[i] - sample[i]).norm();
+            assert!(diff < epsilon, "sample {i} differs by {diff}");
+        }
+    }
+
+    #[test]
+    fn planner_reuse() {
+        let blocks: Vec<Vec<Complex32>> = (0..1000)
+            .map(|block| {
+                let sample: Vec<f32> = (0..200)
+                    .map(|x| ((block * 200 + x) as f32 * 0.05).sin())
+                    .collect();
+                convert_sample(&sample)
+            })
+            .collect();
+        let one_shot: Vec<Vec<Complex32>> = blocks.iter().map(|b| fft(b).unwrap()).collect();
+        let planner = FftPlanner::new(200, FftDirection::Forward).unwrap();
+        let planned: Vec<Vec<Complex32>> = blocks
+            .iter()
+            .map(|b| {
+                let mut buffer = b.clone();
+                planner.process(&mut buffer).unwrap();
+                buffer
+            })
+            .collect();
+        assert_eq!(one_shot, planned);
+        let inverse_planner = FftPlanner::new(200, FftDirection::Inverse).unwrap();
+        let mut buffer = planned[0].clone();
+        inverse_planner.process(&mut buffer).unwrap();
+        assert_eq!(buffer, fft_inverse(&planned[0]).unwrap());
+        assert_eq!(
+            planner.process(&mut vec![Complex32::default(); 100]),
+            Err(FftError::SizeMismatch {
+                expected: 200,
+                len: 100
+            })
+        );
+    }
+
+    #[test]
+    fn ragged_2d() {
+        let mut image = vec![vec![Complex32::from(1.); 8]; 4];
+        image[3].truncate(5);
+        let expected = Err(FftError::NonRectangular {
+            row: 3,
+            expected: 8,
+            len: 5,
+        });
+        assert_eq!(fft_2d(&image), expected);
+        assert_eq!(fft_2d_vertical(&image), expected);
+        assert_eq!(fft_2d_inverse(&image), expected);
+        assert_eq!(fft_2d_horizontal_inverse(&image), expected);
+    }
+
+    #[test]
+    fn real_2d_inversion() {
+        // Values on both sides of zero
+        let image: Vec<Vec<f32>> = (0..9)
+            .map(|y| {
+                (0..14)
+                    .map(|x| ((x * 5 + y * 3) % 11) as f32 - 5.)
+                    .collect()
+            })
+            .collect();
+        let complex: Vec<Vec<Complex32>> = image.iter().map(|row| convert_sample(row)).collect();
+        let restored = fft_2d_inverse_real(&fft_2d(&complex).unwrap()).unwrap();
+        for (a, b) in image.iter().flatten().zip(restored.iter().flatten()) {
+            assert!((a - b).abs() < 1e-4, "{a} != {b}");
+        }
+    }
+
+    fn transpose(samples: &[Vec<Complex32>]) -> Vec<Vec<Complex32>> {
+        let (height, width) = (samples.len(), samples.first().map_or(0, Vec::len));
+        (0..width)
+            .map(|x| (0..height).map(|y| samples[y][x]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn vertical_in_place() {
+        // Wider than a band of columns, in both kinds of algorithm
+        for (width, height) in [(150, 16), (70, 12)] {
+            let image: Vec<Vec<Complex32>> = (0..height)
+                .map(|y| {
+                    (0..width)
+                        .map(|x| Complex32::new((x * y % 13) as f32, (x + 2 * y) as f32 * 0.1))
+                        .collect()
+                })
+                .collect();
+            for direction in [FftDirection::Forward, FftDirection::Inverse] {
+                let transposed = map_lines(&transpose(&image), direction).unwrap();
+                assert_eq!(
+                    map_columns(&image, direction).unwrap(),
+                    transpose(&transposed)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn planner_2d() {
+        // Neither dimension is a power of 2
+        let image: Vec<Vec<Complex32>> = (0..12)
+            .map(|y| {
+                (0..20)
+                    .map(|x| Complex32::new((x * y % 7) as f32, (x + y) as f32 * 0.1))
+                    .collect()
+            })
+            .collect();
+        let per_line =
+            |sample: &[Vec<Complex32>],
+             transform: fn(&[Complex32]) -> Result<Vec<Complex32>, FftError>| {
+                let rows: Vec<Vec<Complex32>> =
+                    sample.iter().map(|y| transform(y).unwrap()).collect();
+                let columns: Vec<Vec<Complex32>> = transpose(&rows)
+                    .iter()
+                    .map(|x| transform(x).unwrap())
+                    .collect();
+                transpose(&columns)
+            };
+        assert_eq!(fft_2d(&image).unwrap(), per_line(&image, fft));
+        // The inverse transforms columns first, which is equivalent up to rounding
+        let inverse = fft_2d_inverse(&image).unwrap();
+        for (a, b) in inverse
+            .iter()
+            .flatten()
+            .zip(per_line(&image, fft_inverse).iter().flatten())
+        {
+            assert!((a - b).norm() < 1e-5, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn parallel_2d() {
+        // Pseudo-random image from a linear congruential generator
+        let mut seed = 12345u32;
+        let image: Vec<Vec<Complex32>> = (0..256)
+            .map(|_| {
+                (0..256)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                        Complex32::from((seed >> 16) as f32 / 65536.)
+                    })
+                    .collect()
+            })
+            .collect();
+        let horizontal: Vec<Vec<Complex32>> = image.iter().map(|y| fft(y).unwrap()).collect();
+        let columns: Vec<Vec<Complex32>> = transpose(&horizontal)
+            .iter()
+            .map(|x| fft(x).unwrap())
+            .collect();
+        let serial = transpose(&columns);
+        assert_eq!(fft_2d(&image).unwrap(), serial);
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_butterflies() {
+        let mut seed = 12345u32;
+        for sample_size in [8, 16, 64, 1 << 12] {
+            let sample: Vec<Complex32> = (0..sample_size)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    Complex32::new(
+                        (seed >> 16) as f32 / 65536.,
+                        (seed & 0xffff) as f32 / 65536.,
+                    )
+                })
+                .collect();
+            let radix2 = Radix2::new(sample_size, 1.);
+            let (mut simd, mut scalar) = (sample.clone(), sample);
+            radix2.process(&mut simd);
+            radix2.process_with(&mut scalar, scalar_butterflies);
+            let scale = scalar.iter().map(|x| x.norm()).fold(0., f32::max);
+            for (a, b) in simd.iter().zip(&scalar) {
+                assert!((a - b).norm() / scale < 1e-4, "{a} != {b}");
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn thread_counts() {
+        let image: Vec<Vec<Complex32>> = (0..100)
+            .map(|y| {
+                (0..60)
+                    .map(|x| Complex32::from((x * y % 17) as f32))
+                    .collect()
+            })
+            .collect();
+        let transform = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| (fft_2d(&image).unwrap(), fft_2d_inverse(&image).unwrap()))
+        };
+        assert_eq!(transform(1), transform(4));
+    }
+
+    #[test]
+    fn large_sample() {
+        let sample_size = 1 << 20;
+        let sample: Vec<f32> = (0..sample_size)
+            .map(|x| (x as f32 * 0.01).sin() + (x as f32 * 0.37).cos() * 0.5)
+            .collect();
+        let sample = convert_sample(&sample);
+        let result = fft(&sample).unwrap();
+        let expected = basefft(&sample);
+        let scale = expected.iter().map(|x| x.norm()).fold(0., f32::max);
+        let epsilon = 10f32.powi(-4);
+        for i in 0..expected.len() {
+            let diff = (result[i] - expected[i]).norm() / scale;
+            assert!(diff < epsilon, "bin {i} differs by {diff}");
+        }
+    }
+
+    #[test]
+    fn shift_odd_length() {
+        let mut sample = vec![0, 1, 2, -2, -1];
+        fftshift(&mut sample);
+        assert_eq!(sample, vec![-2, -1, 0, 1, 2]);
+        ifftshift(&mut sample);
+        assert_eq!(sample, vec![0, 1, 2, -2, -1]);
+        let mut samples: Vec<Vec<usize>> = (0..3)
+            .map(|y| (0..5).map(|x| y * 5 + x).collect())
+            .collect();
+        let original = samples.clone();
+        fftshift_2d(&mut samples);
+        assert_eq!(samples[1][2], 0);
+        ifftshift_2d(&mut samples);
+        assert_eq!(samples, original);
+    }
+
+    #[test]
+    fn bin_amplitudes() {
+        // A DC offset, a tone at bin 5 and a cosine at the Nyquist frequency
+        let sample: Vec<f32> = (0..64)
+            .map(|x| {
+                let phase = 2. * std::f32::consts::PI * 5. * x as f32 / 64.;
+                let nyquist = if x % 2 == 0 { 0.5 } else { -0.5 };
+                3. + 2. * phase.sin() + nyquist
+            })
+            .collect();
+        let bins = frequency_bins(&fft(&convert_sample(&sample)).unwrap());
+        assert_eq!(bins.len(), 33);
+        assert!((bins[0] - 3.).abs() < 1e-4, "{}", bins[0]);
+        assert!((bins[5] - 2.).abs() < 1e-4, "{}", bins[5]);
+        assert!((bins[32] - 0.5).abs() < 1e-4, "{}", bins[32]);
+        assert!(bins
+            .iter()
+            .enumerate()
+            .all(|(k, x)| [0, 5, 32].contains(&k) || x.abs() < 1e-4));
+        // Odd sizes have no Nyquist bin, the last bin is mirrored
+        let odd = frequency_bins(&fft(&convert_sample(&sample[..63])).unwrap());
+        assert_eq!(odd.len(), 32);
+    }
+
+    #[test]
+    fn bin_labels() {
+        let bins = [4., 3., 2., 1., 0.];
+        let labeled: Vec<(f32, f32)> = labeled_bins(&bins, 8000, 8).collect();
+        assert_eq!(labeled.len(), 5);
+        for (k, (frequency, amplitude)) in labeled.iter().enumerate() {
+            assert_eq!(*frequency, k as f32 * 1000.);
+            assert_eq!(*amplitude, bins[k]);
+        }
+        // Labels by the resolution, whether or not it divides the sample rate evenly
+        let resolution = 44100. / 1024.;
+        let labels: Vec<f32> = labeled_bins(&[0.; 4], 44100, 1024)
+            .map(|(hz, _)| hz)
+            .collect();
+        assert_eq!(labels, [0., resolution, 2. * resolution, 3. * resolution]);
+    }
+
+    #[test]
+    fn dominant_frequencies_of_two_tones() {
+        let bins = [0., 1., 5., 4., 1., 0., 3., 2.];
+        assert_eq!(dominant_frequencies(&bins, 14, 3), [(2., 5.), (6., 3.)]);
+        assert_eq!(dominant_frequencies(&bins, 14, 1), [(2., 5.)]);
+        // Tones between bins, which leak into their neighbours
+        let (sample_rate, sample_size) = (8000, 8192);
+        let sample: Vec<f32> = (0..sample_size)
+            .map(|x| {
+                let t = x as f32 / sample_rate as f32;
+                let tau = 2. * std::f32::consts::PI;
+                (tau * 440.3 * t).sin() + 0.5 * (tau * 1234.5 * t).sin()
+            })
+            .collect();
+        let bins = frequency_bins(&fft(&convert_sample(&sample)).unwrap());
+        let peaks = dominant_frequencies(&bins, sample_rate, 2);
+        let resolution = sample_rate as f32 / sample_size as f32;
+        assert_eq!(peaks.len(), 2);
+        for ((frequency, amplitude), (expected, expected_amplitude)) in
+            peaks.iter().zip([(440.3, 1.), (1234.5, 0.5)])
+        {
+            assert!((frequency - expected).abs() <= resolution, "{frequency} Hz");
+            assert!((amplitude - expected_amplitude).abs() < 0.4, "{amplitude}");
+        }
+    }
+
+    #[test]
+    fn chirp_spectrogram() {
+        // Frequency rising linearly from 200 Hz to 3000 Hz over one second
+        let sample_rate = 8000.;
+        let chirp: Vec<f32> = (0..8000)
+            .map(|x| {
+                let t = x as f32 / sample_rate;
+                (2. * std::f32::consts::PI * (200. * t + 1400. * t * t)).sin()
+            })
+            .collect();
+        let spectrogram = stft(&chirp, 256, 128, WindowKind::Hann).unwrap();
+        assert_eq!(spectrogram.len(), (8000 - 256) / 128 + 1);
+        assert!(spectrogram.iter().all(|column| column.len() == 129));
+        let ridge: Vec<usize> = spectrogram
+            .iter()
+            .map(|column| {
+                (0..column.len())
+                    .max_by(|a, b| column[*a].total_cmp(&column[*b]))
+                    .unwrap()
+            })
+            .collect();
+        assert!(ridge.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Bins are 31.25 Hz apart
+        assert!(ridge[0] < 10 && ridge[ridge.len() - 1] > 85);
+        for hop in [0, 257] {
+            let result = stft(&chirp, 256, hop, WindowKind::Hann);
+            assert!(
+                matches!(
+                    result,
+                    Err(FftError::InvalidHop {
+                        frame_size: 256,
+                        ..
+                    })
+                ),
+                "{result:?}"
+            );
+        }
+    }
 }
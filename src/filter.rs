@@ -0,0 +1,166 @@
+//! # Filters
+//! Frequency filters for audio, applied by zeroing frequency bins of the transformed waveform,
+//! and resampling by truncating or extending the frequency bins.
+//!
+
+use crate::fft::{self, FftError};
+use num_complex::Complex32;
+use std::ops::RangeInclusive;
+
+/// Frequencies (in Hz) to remove from a waveform.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterSpec {
+    /// Remove frequencies above the cutoff.
+    LowPass(f32),
+    /// Remove frequencies below the cutoff.
+    HighPass(f32),
+    /// Remove frequencies within the range.
+    BandStop(RangeInclusive<f32>),
+}
+
+impl FilterSpec {
+    /// Whether the filter keeps the frequency.
+    pub fn passes(&self, frequency: f32) -> bool {
+        match self {
+            FilterSpec::LowPass(cutoff) => frequency <= *cutoff,
+            FilterSpec::HighPass(cutoff) => frequency >= *cutoff,
+            FilterSpec::BandStop(range) => !range.contains(&frequency),
+        }
+    }
+}
+
+/// Remove frequencies from a waveform, see [`FilterSpec`].
+///
+/// Each frequency is removed together with its mirror above the Nyquist frequency, so the
+/// spectrum remains conjugate-symmetric and the filtered waveform remains real.
+///
+/// The kept frequencies are left unchanged (a real gain of 1), so the filter has zero phase: it
+/// delays no frequency and the filtered waveform stays aligned with the original, unlike a
+/// causal filter that would have to be run forward and backward to cancel its phase.
+///
+/// There is therefore no zero-phase option: running this filter forward and backward would
+/// apply the same mask twice, which gives the same waveform as applying it once.
+pub fn apply_filter(
+    waveform: &[f32],
+    sample_rate: usize,
+    filter: FilterSpec,
+) -> Result<Vec<f32>, FftError> {
+    let sample_size = waveform.len();
+    if sample_size == 0 {
+        return Ok(Vec::new());
+    }
+    let mut freq_domain = fft::fft(&fft::convert_sample(waveform))?;
+    let freq_resolution = sample_rate as f32 / sample_size as f32;
+    for (bin, value) in freq_domain.iter_mut().enumerate() {
+        let frequency = bin.min(sample_size - bin) as f32 * freq_resolution;
+        if !filter.passes(frequency) {
+            *value = Default::default();
+        }
+    }
+    Ok(fft::fft_inverse(&freq_domain)?
+        .iter()
+        .map(|value| value.re)
+        .collect())
+}
+
+/// Convert a waveform from one sample rate to another, keeping its duration.
+///
+/// The frequency bins are truncated (removing frequencies above the new Nyquist frequency) or
+/// extended with zeros, and transformed back at the new size. The waveform is treated as
+/// periodic, like every transform. Returns an empty waveform if either rate is zero.
+pub fn resample(waveform: &[f32], from_rate: usize, to_rate: usize) -> Result<Vec<f32>, FftError> {
+    let sample_size = waveform.len();
+    if sample_size == 0 || from_rate == 0 || to_rate == 0 {
+        return Ok(Vec::new());
+    }
+    let new_size = (sample_size as f64 * to_rate as f64 / from_rate as f64).round() as usize;
+    if new_size == 0 {
+        return Ok(Vec::new());
+    }
+    let mut bins = fft::rfft(waveform)?;
+    // A Nyquist bin stands for both its positive and negative frequency, so it is split when
+    // the frequency is no longer the highest, and merged when it becomes the highest
+    if new_size > sample_size && sample_size.is_multiple_of(2) {
+        bins[sample_size / 2] *= 0.5;
+    }
+    if new_size < sample_size && new_size.is_multiple_of(2) {
+        bins[new_size / 2] = Complex32::from(bins[new_size / 2].re * 2.);
+    }
+    bins.resize(new_size / 2 + 1, Complex32::default());
+    // The inverse is normalized by the new size rather than the original
+    let scale = new_size as f32 / sample_size as f32;
+    Ok(fft::irfft(&bins, new_size)?
+        .iter()
+        .map(|x| x * scale)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn tone(sample_size: usize, sample_rate: usize, frequency: f32) -> Vec<f32> {
+        (0..sample_size)
+            .map(|x| (2. * PI * frequency * x as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn low_pass_removes_high_tone() {
+        // An odd number of samples, so no bin falls exactly on the Nyquist frequency
+        let (sample_size, sample_rate) = (4001, 8000);
+        let low = tone(sample_size, sample_rate, 300. * 4000. / 4001.);
+        let high = tone(sample_size, sample_rate, 3000. * 4000. / 4001.);
+        let mixed: Vec<f32> = low.iter().zip(&high).map(|(a, b)| a + b).collect();
+        let filtered = apply_filter(&mixed, sample_rate, FilterSpec::LowPass(1000.)).unwrap();
+        assert_close(&filtered, &low);
+        let filtered = apply_filter(&mixed, sample_rate, FilterSpec::HighPass(1000.)).unwrap();
+        assert_close(&filtered, &high);
+        let band_stop = FilterSpec::BandStop(2000.0..=3500.);
+        let filtered = apply_filter(&mixed, sample_rate, band_stop).unwrap();
+        assert_close(&filtered, &low);
+        assert!(apply_filter(&[], sample_rate, FilterSpec::LowPass(1.))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn resample_keeps_frequency() {
+        let original = tone(4800, 48000, 1000.);
+        for to_rate in [44100, 96000, 8000] {
+            let resampled = resample(&original, 48000, to_rate).unwrap();
+            assert_eq!(resampled.len(), to_rate / 10);
+            let bins = fft::frequency_bins(&fft::fft(&fft::convert_sample(&resampled)).unwrap());
+            let peaks = fft::dominant_frequencies(&bins, to_rate, 1);
+            assert_eq!(peaks[0].0, 1000., "{to_rate}");
+            assert_close(&resampled, &tone(to_rate / 10, to_rate, 1000.));
+        }
+        assert!(resample(&original, 48000, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn zero_phase() {
+        let mut impulse = vec![0.; 1001];
+        impulse[500] = 1.;
+        let response = apply_filter(&impulse, 8000, FilterSpec::LowPass(1000.)).unwrap();
+        let peak = (0..response.len())
+            .max_by(|a, b| response[*a].total_cmp(&response[*b]))
+            .unwrap();
+        assert_eq!(peak, 500);
+        // Symmetric about the impulse, so no frequency is delayed
+        for offset in 1..=500 {
+            let (before, after) = (response[500 - offset], response[500 + offset]);
+            assert!(
+                (before - after).abs() < 1e-5,
+                "{offset}: {before} != {after}"
+            );
+        }
+    }
+}
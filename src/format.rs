@@ -0,0 +1,136 @@
+//! # File formats
+//! Recognizes the supported file formats by their content, falling back to their extension.
+//! Defines the error returned for unsupported or malformed files, whatever their format.
+//!
+
+use crate::header::{BMP_DCT_MAGIC, BMP_MAGIC, BMP_WAVELET_MAGIC, WAV_MAGIC};
+use thiserror::Error;
+
+/// Returned when file formats are not supported.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    #[error("multiple channels not supported")]
+    UnsupportedChannels,
+    #[error("unrecognized format not supported")]
+    UnsupportedFormat,
+    #[error("file is too short to contain a header")]
+    Truncated,
+    #[error("file does not start with the expected magic number")]
+    BadMagic,
+    #[error("version {0} of the compressed format not supported")]
+    UnsupportedVersion(u8),
+    #[error("compressed data does not match its checksum, the file is corrupted")]
+    Corrupted,
+}
+
+/// Magic number of .png files.
+pub(crate) const PNG_MAGIC: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+/// Magic number of .flac files.
+pub(crate) const FLAC_MAGIC: &[u8; 4] = b"fLaC";
+
+/// Kind of file the compression functions accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Wav,
+    Bmp,
+    /// Compressed like [`FileKind::Bmp`], requires the `png` feature
+    Png,
+    /// Compressed like [`FileKind::Wav`], requires the `flac` feature
+    Flac,
+    CompressedWav,
+    CompressedBmp,
+}
+
+impl FileKind {
+    /// The kind of file conventionally named with this extension.
+    pub fn from_extension(extension: &str) -> Option<FileKind> {
+        match extension.to_lowercase().as_str() {
+            "wav" => Some(FileKind::Wav),
+            "bmp" => Some(FileKind::Bmp),
+            "png" => Some(FileKind::Png),
+            "flac" => Some(FileKind::Flac),
+            "cwv" => Some(FileKind::CompressedWav),
+            "cbm" => Some(FileKind::CompressedBmp),
+            _ => None,
+        }
+    }
+}
+
+/// Recognize a file by the magic bytes at its start.
+pub fn detect_format(bytes: &[u8]) -> Option<FileKind> {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+        Some(FileKind::Wav)
+    } else if bytes.starts_with(b"BM") {
+        Some(FileKind::Bmp)
+    } else if bytes.starts_with(PNG_MAGIC) {
+        Some(FileKind::Png)
+    } else if bytes.starts_with(FLAC_MAGIC) {
+        Some(FileKind::Flac)
+    } else if bytes.starts_with(WAV_MAGIC) {
+        Some(FileKind::CompressedWav)
+    } else if [BMP_MAGIC, BMP_DCT_MAGIC, BMP_WAVELET_MAGIC]
+        .iter()
+        .any(|magic| bytes.starts_with(*magic))
+    {
+        Some(FileKind::CompressedBmp)
+    } else {
+        None
+    }
+}
+
+/// Recognize a file by its content, or by its extension if the content is not recognized.
+pub fn resolve_format(bytes: &[u8], extension: Option<&str>) -> Result<FileKind, FormatError> {
+    detect_format(bytes)
+        .or_else(|| extension.and_then(FileKind::from_extension))
+        .ok_or(FormatError::UnsupportedFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(deprecated)]
+    fn wav_bytes() -> Vec<u8> {
+        let header = wav::Header::new(wav::WAV_FORMAT_PCM, 1, 8000, 16);
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        wav::write(header, &wav::BitDepth::Sixteen(vec![0; 100]), &mut bytes).unwrap();
+        bytes.into_inner()
+    }
+
+    fn bmp_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bmp::Image::new(4, 4).to_writer(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn content_overrides_extension() {
+        assert_eq!(resolve_format(&bmp_bytes(), Some("wav")), Ok(FileKind::Bmp));
+        assert_eq!(resolve_format(&wav_bytes(), Some("bmp")), Ok(FileKind::Wav));
+        assert_eq!(resolve_format(b"fLaC\0", Some("wav")), Ok(FileKind::Flac));
+        assert_eq!(
+            resolve_format(b"CWV1\x01", Some("cbm")),
+            Ok(FileKind::CompressedWav)
+        );
+        assert_eq!(
+            resolve_format(b"CBD1\x01", Some("wav")),
+            Ok(FileKind::CompressedBmp)
+        );
+        assert_eq!(
+            resolve_format(b"CBW1\x01", Some("wav")),
+            Ok(FileKind::CompressedBmp)
+        );
+        assert_eq!(
+            resolve_format(b"", Some("cbm")),
+            Ok(FileKind::CompressedBmp)
+        );
+        assert_eq!(
+            resolve_format(b"text", Some("txt")),
+            Err(FormatError::UnsupportedFormat)
+        );
+        assert_eq!(
+            resolve_format(b"text", None),
+            Err(FormatError::UnsupportedFormat)
+        );
+    }
+}
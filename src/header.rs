@@ -0,0 +1,65 @@
+//! # File headers
+//! Identifies the compressed file formats: a 4-byte magic number followed by a version byte.
+//! The encoded data is followed by a footer, the CRC32 of everything before it, so corrupted or
+//! truncated files are detected before they are decoded.
+//!
+
+use crate::format::FormatError;
+
+/// Magic number of compressed .wav files.
+pub(crate) const WAV_MAGIC: &[u8; 4] = b"CWV1";
+/// Magic number of compressed .bmp files.
+pub(crate) const BMP_MAGIC: &[u8; 4] = b"CBM1";
+/// Magic number of .bmp files compressed in blocks of the discrete cosine transform.
+pub(crate) const BMP_DCT_MAGIC: &[u8; 4] = b"CBD1";
+/// Magic number of .bmp files compressed by the Haar wavelet transform.
+pub(crate) const BMP_WAVELET_MAGIC: &[u8; 4] = b"CBW1";
+/// Version of the compressed .wav data following the header.
+pub(crate) const WAV_VERSION: u8 = 10;
+/// Version of the compressed .bmp data following the header.
+pub(crate) const BMP_VERSION: u8 = 8;
+/// Version of the DCT compressed .bmp data following the header.
+pub(crate) const BMP_DCT_VERSION: u8 = 4;
+/// Version of the wavelet compressed .bmp data following the header.
+pub(crate) const BMP_WAVELET_VERSION: u8 = 1;
+
+/// Size of the checksum footer.
+const FOOTER_SIZE: usize = 4;
+
+/// Prepend the header to the encoded data, and append the footer.
+pub(crate) fn with_header(magic: &[u8; 4], version: u8, encoded: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(magic.len() + 1 + encoded.len() + FOOTER_SIZE);
+    bytes.extend_from_slice(magic);
+    bytes.push(version);
+    bytes.extend_from_slice(encoded);
+    let checksum = crc32fast::hash(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Validate and strip the header and footer, returning the encoded data.
+pub(crate) fn strip_header<'a>(
+    magic: &[u8; 4],
+    version: u8,
+    bytes: &'a [u8],
+) -> Result<&'a [u8], FormatError> {
+    let header_size = magic.len() + 1;
+    if bytes.len() < header_size {
+        return Err(FormatError::Truncated);
+    }
+    if &bytes[..magic.len()] != magic {
+        return Err(FormatError::BadMagic);
+    }
+    match bytes[magic.len()] {
+        v if v == version => (),
+        v => return Err(FormatError::UnsupportedVersion(v)),
+    }
+    if bytes.len() < header_size + FOOTER_SIZE {
+        return Err(FormatError::Truncated);
+    }
+    let (checked, footer) = bytes.split_at(bytes.len() - FOOTER_SIZE);
+    if crc32fast::hash(checked).to_le_bytes() != footer {
+        return Err(FormatError::Corrupted);
+    }
+    Ok(&checked[header_size..])
+}
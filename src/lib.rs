@@ -1,7 +1,27 @@
 //! # Compression
 //! Proof of concept for compressing and decompressing media files.
 //!
+//! The library never prints: progress is reported through callbacks, and results are returned
+//! for the caller to present.
+//!
 
 pub mod bmp;
+pub mod codec;
+pub mod dct;
+pub mod dump;
+pub mod entropy;
+pub mod error;
 pub mod fft;
+pub mod filter;
+pub mod format;
+mod header;
+pub mod plotting;
+pub mod quality;
+pub mod stats;
+pub mod transform;
 pub mod wav;
+pub mod wavelet;
+pub mod window;
+
+pub use error::CompressionError;
+pub use wav::{compress_wav, decompress_wav};
@@ -1,9 +1,17 @@
 /// Proof of concept for compressing and decompressing media files.
-use clap::Parser;
-use compression::{bmp, wav};
+use clap::{Parser, Subcommand};
+use compression::bmp::BmpFormat;
+use compression::fft::PadMode;
+use compression::format::{self, FileKind};
+use compression::plotting::PlotConfig;
+use compression::stats::CompressionStats;
+use compression::transform::{PartialFftTransform, SpectrumPart};
+use compression::{bmp, quality, wav, window::WindowKind};
 use std::cmp::Ordering;
 use std::error::Error;
-use std::path::PathBuf;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 type BoxedError = Box<dyn std::error::Error>;
@@ -11,83 +19,779 @@ type BoxedError = Box<dyn std::error::Error>;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Input file (.wav or .bmp)
+    #[command(subcommand)]
+    mode: Mode,
+    /// Number of threads for parallel transforms, 1 for serial transforms [default: all cores]
+    #[cfg(feature = "parallel")]
+    #[arg(long, global = true, display_order = 100)]
+    threads: Option<usize>,
+    /// Print nothing but errors
+    #[arg(
+        long,
+        global = true,
+        display_order = 100,
+        default_value_t = false,
+        conflicts_with = "verbose"
+    )]
+    quiet: bool,
+    /// Print what is being done in more detail
+    #[arg(long, global = true, display_order = 100, default_value_t = false)]
+    verbose: bool,
+    /// Write the intermediate stages of the transforms to this directory, as numpy (.npy) files
+    #[arg(long, global = true, display_order = 100)]
+    dump_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Compress an audio or image file, or each of them in a directory
+    Compress(CompressArgs),
+    /// Decompress a compressed file, or each of them in a directory
+    Decompress(DecompressArgs),
+    /// Plot the frequencies of an audio or image file, or of each of them in a directory
+    Analyze(AnalyzeArgs),
+    /// Print the properties of a compressed file, without decompressing it
+    Info(InfoArgs),
+    /// Decompress a compressed file in memory and compare it with the original, failing if it
+    /// decompresses with a larger error than the maximum
+    Verify(VerifyArgs),
+    /// Plot the difference between the retained frequencies of two compressed audio files
+    Diff(DiffArgs),
+    /// Concatenate compressed audio files (compressed with --frame-size) into one, without
+    /// decompressing them
+    Concat(ConcatArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct CompressArgs {
+    /// Input file (.wav, .flac, .bmp or .png), or a directory of input files
     #[arg()]
     file: String,
     /// Compression level (higher: smaller file size, lower: better quality)
     #[arg(short = 'c', long, default_value_t = 10.)]
     compression: f32,
-    /// Analyze frequencies
+    /// Compress images in blocks of the discrete cosine transform (like JPEG)
     #[arg(short, long, default_value_t = false)]
-    analyze: bool,
-    /// Log factor (when analyzing)
+    dct: bool,
+    /// Quantization table for --dct instead of the JPEG tables: a file of 64 integers, row by row
+    #[arg(long, requires = "dct")]
+    quantization_table: Option<PathBuf>,
+    /// Compress images in independent square tiles of this many pixels, for huge images
+    #[arg(long, conflicts_with = "dct")]
+    tile_size: Option<usize>,
+    /// Extend image tiles into their neighbours by this many pixels, crossfading them to hide
+    /// the seams
+    #[arg(long, default_value_t = 0, requires = "tile_size")]
+    tile_overlap: usize,
+    /// Also store what the lossy compression loses, to decompress the exact original
+    #[arg(long, default_value_t = false, conflicts_with_all = ["dct", "tile_size", "frame_size"])]
+    lossless: bool,
+    /// Compress images by the Haar wavelet transform, which blurs sharp edges rather than
+    /// ringing around them
+    #[arg(long, default_value_t = false, conflicts_with_all = ["dct", "tile_size", "lossless"])]
+    wavelet: bool,
+    /// Transform images in double precision and store 32-bit coefficients, for high dynamic
+    /// range images
+    #[cfg(feature = "high-precision")]
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["dct", "tile_size", "lossless", "wavelet"]
+    )]
+    high_precision: bool,
+    /// Quantization of audio frequencies (higher: smaller file size, lower: better quality)
+    #[arg(short, long, default_value_t = 1.)]
+    quantization: f32,
+    /// Quantize audio frequencies more coarsely along a curve of frequency:strength points, like
+    /// "0:8, 100:1, 12000:1, 16000:8" (strengths from 1 to 2047 multiply the quantization)
+    #[arg(long, conflicts_with_all = ["frame_size", "lossless", "progressive"])]
+    quantization_curve: Option<String>,
+    /// Compress audio in overlapping frames of this many samples
+    #[arg(short, long)]
+    frame_size: Option<usize>,
+    /// Store audio frequencies by descending magnitude, so that decompress --partial can
+    /// decompress them
+    #[arg(long, default_value_t = false, conflicts_with_all = ["frame_size", "lossless"])]
+    progressive: bool,
+    /// Keep only the magnitudes or only the phases of the audio frequencies, to hear what the
+    /// other part carries
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["quantization_curve", "frame_size", "lossless", "progressive"]
+    )]
+    keep_only: Option<SpectrumPart>,
+    /// Downmix audio to mono before compressing it
+    #[arg(long, default_value_t = false)]
+    downmix: bool,
+    /// Resample audio to this sample rate (in Hz) before compressing it
+    #[arg(long)]
+    resample: Option<usize>,
+    /// Run the compression without writing the compressed files, printing their size instead
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+    /// Output directory
+    #[arg(short, long, default_value_t = String::from("data"))]
+    output_dir: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct DecompressArgs {
+    /// Compressed file (.cwv or .cbm), or a directory of compressed files
+    #[arg()]
+    file: String,
+    /// Dither decompressed audio, with noise reproducible from this seed
+    #[arg(long)]
+    dither: Option<u64>,
+    /// Decompress audio to .mp3 rather than .wav
+    #[cfg(feature = "mp3")]
+    #[arg(long, default_value_t = false, conflicts_with_all = ["dither", "partial"])]
+    mp3: bool,
+    /// Decompress audio from only this fraction (0 to 1) of its most significant frequencies,
+    /// if compressed with --progressive
+    #[arg(long, conflicts_with = "dither")]
+    partial: Option<f32>,
+    /// Pixel format of decompressed images
+    #[arg(long, value_enum, default_value_t = BmpFormat::Rgb24)]
+    bmp_format: BmpFormat,
+    /// Original image to compare with after decompressing, printing the PSNR and SSIM
+    #[arg(long)]
+    original: Option<String>,
+    /// Output directory
+    #[arg(short, long, default_value_t = String::from("data"))]
+    output_dir: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct AnalyzeArgs {
+    /// Input file (.wav, .flac, .bmp or .png), or a directory of input files
+    #[arg()]
+    file: String,
+    /// Log factor of image frequencies
     #[arg(short = 'l', long, default_value_t = 2.5)]
     log_factor: f32,
+    /// Window function of audio
+    #[arg(short, long, value_enum, default_value_t = WindowKind::Rectangular)]
+    window: WindowKind,
+    /// Padding of audio up to the transform size
+    #[arg(long, value_enum, default_value_t = PadMode::Reflect)]
+    pad: PadMode,
+    /// Compressed audio file to overlay the decompressed spectrum of on the spectrum of the input
+    /// file
+    #[arg(long)]
+    overlay: Option<String>,
+    #[command(flatten)]
+    plot: PlotArgs,
+    /// Open the analysis file in the browser, instead of only printing its path
+    #[arg(long, default_value_t = false)]
+    open: bool,
+    /// Output directory
+    #[arg(short, long, default_value_t = String::from("data"))]
+    output_dir: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct InfoArgs {
+    /// Compressed file (.cwv or .cbm)
+    #[arg()]
+    file: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    /// Original file (.wav, .flac, .bmp or .png)
+    #[arg()]
+    original: String,
+    /// Compressed file to verify against the original
+    #[arg()]
+    compressed: String,
+    /// Largest absolute sample or pixel error to accept
+    #[arg(long, default_value_t = 100.)]
+    max_error: f32,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Compressed audio file (.cwv)
+    #[arg()]
+    file: String,
+    /// Compressed audio file to compare the retained frequencies of the first file with
+    #[arg()]
+    other: String,
+    #[command(flatten)]
+    plot: PlotArgs,
     /// Output directory
     #[arg(short, long, default_value_t = String::from("data"))]
     output_dir: String,
 }
 
+#[derive(clap::Args, Debug)]
+struct ConcatArgs {
+    /// Compressed audio files (.cwv) in the order to concatenate them
+    #[arg(num_args = 2.., required = true)]
+    files: Vec<String>,
+    /// Output directory, of the concatenated file named after the first file
+    #[arg(short, long, default_value_t = String::from("data"))]
+    output_dir: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct PlotArgs {
+    /// Width of the plots in pixels
+    #[arg(long)]
+    plot_width: Option<usize>,
+    /// Height of the plots in pixels
+    #[arg(long)]
+    plot_height: Option<usize>,
+    /// Plot with a dark theme
+    #[arg(long, default_value_t = false)]
+    dark_mode: bool,
+}
+
+impl Args {
+    /// Print a message, unless quiet.
+    fn info(&self, message: &str) {
+        if !self.quiet {
+            println!("{message}");
+        }
+    }
+
+    /// Print a message, only if verbose.
+    fn detail(&self, message: &str) {
+        if self.verbose {
+            println!("{message}");
+        }
+    }
+}
+
+impl PlotArgs {
+    fn config(&self) -> PlotConfig {
+        PlotConfig {
+            width: self.plot_width,
+            height: self.plot_height,
+            dark_mode: self.dark_mode,
+        }
+    }
+}
+
+/// Kinds of files that are compressed and analyzed.
+const ORIGINAL_KINDS: [FileKind; 4] = [FileKind::Wav, FileKind::Flac, FileKind::Bmp, FileKind::Png];
+/// Kinds of files that are decompressed.
+const COMPRESSED_KINDS: [FileKind; 2] = [FileKind::CompressedWav, FileKind::CompressedBmp];
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
-    let file = PathBuf::from(args.file);
+    #[cfg(feature = "parallel")]
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    compression::dump::set_dump_dir(args.dump_dir.clone());
+    match &args.mode {
+        Mode::Compress(options) => {
+            process_path(&options.file, &ORIGINAL_KINDS, &args, |file, kind| {
+                compress(file, kind, options, &args)
+            })
+        }
+        Mode::Decompress(options) => {
+            process_path(&options.file, &COMPRESSED_KINDS, &args, |file, kind| {
+                decompress(file, kind, options, &args)
+            })
+        }
+        Mode::Analyze(options) => {
+            process_path(&options.file, &ORIGINAL_KINDS, &args, |file, kind| {
+                analyze(file, kind, options, &args)
+            })
+        }
+        Mode::Info(options) => {
+            let file = PathBuf::from(&options.file);
+            print_info(&file, recognize(&file)?, &args)
+        }
+        Mode::Verify(options) => {
+            let original = PathBuf::from(&options.original);
+            let compressed = PathBuf::from(&options.compressed);
+            verify(
+                &original,
+                recognize(&original)?,
+                &compressed,
+                options,
+                &args,
+            )
+        }
+        Mode::Diff(options) => diff(options, &args),
+        Mode::Concat(options) => concat(options, &args),
+    }
+}
+
+/// Process a file, or each file of the kinds in a directory.
+fn process_path(
+    path: &str,
+    kinds: &[FileKind],
+    args: &Args,
+    process: impl Fn(&Path, FileKind) -> Result<Option<CompressionStats>, BoxedError>,
+) -> Result<(), BoxedError> {
+    let file = PathBuf::from(path);
+    if file.is_dir() {
+        return process_directory(&file, kinds, args, process);
+    }
     if !file.is_file() {
-        return Err(BoxedError::from("Not a file."));
+        return Err(BoxedError::from("Not a file or directory."));
+    }
+    let kind = recognize(&file)?;
+    args.detail(&format!("Processing {file:?} as {kind:?}"));
+    process(&file, kind)?;
+    Ok(())
+}
+
+/// Decompress a file in memory and compare it with the original, failing if the largest error
+/// exceeds the maximum.
+fn verify(
+    original: &Path,
+    kind: FileKind,
+    compressed: &Path,
+    options: &VerifyArgs,
+    args: &Args,
+) -> Result<(), BoxedError> {
+    let max_error = options.max_error;
+    let compressed = fs::read(compressed)?;
+    let error = match kind {
+        FileKind::Wav | FileKind::Flac => {
+            let decompressed = wav::decompress_wav_bytes(&compressed)?;
+            wav::max_sample_error(&fs::read(original)?, &decompressed)?
+        }
+        FileKind::Bmp | FileKind::Png => {
+            let decompressed = bmp::decompress_bmp_bytes(&compressed)?;
+            let decompressed = bmp::ComplexImage::from_bitmap_bytes(&decompressed)?;
+            let original = bmp::ComplexImage::from_image(original)?;
+            if original.size() != decompressed.size() {
+                return Err(BoxedError::from("original image differs in size"));
+            }
+            quality::max_error(&original, &decompressed)
+        }
+        _ => return Err(BoxedError::from("only original files can be verified")),
+    };
+    args.info(&format!("Max error: {error:.2}"));
+    match error.partial_cmp(&max_error) {
+        Some(Ordering::Greater) | None => Err(BoxedError::from(format!(
+            "error exceeds the maximum of {max_error:.2}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Print the properties of a compressed file, without decompressing it.
+fn print_info(file: &Path, kind: FileKind, args: &Args) -> Result<(), BoxedError> {
+    match kind {
+        FileKind::CompressedWav => {
+            let info = wav::read_wav_metadata(file)?;
+            args.info(&format!("Sample rate: {} Hz", info.sample_rate));
+            args.info(&format!("Bit depth: {}", info.bit_rate));
+            args.info(&format!("Channels: {}", info.channel_count));
+            args.info(&format!("Original size: {} samples", info.original_size));
+            if let Some(frame_size) = info.frame_size {
+                args.info(&format!("Frame size: {frame_size} samples"));
+            }
+            args.info(&format!("Coefficients: {}", info.coefficient_count));
+            args.info(&format!("Lossless: {}", info.lossless));
+        }
+        FileKind::CompressedBmp => {
+            let info = bmp::read_bmp_metadata(file)?;
+            args.info(&format!("Original size: {}x{}", info.width, info.height));
+            args.info(&format!("Channels: {}", info.channel_count));
+            args.info(&format!("Alpha: {}", info.alpha));
+            let transform = match (info.dct, info.wavelet) {
+                (true, _) => "DCT",
+                (_, true) => "Haar wavelet",
+                _ => "FFT",
+            };
+            args.info(&format!("Transform: {transform}"));
+            if let Some(tile_size) = info.tile_size {
+                args.info(&format!("Tile size: {tile_size} pixels"));
+                args.info(&format!("Tile overlap: {} pixels", info.tile_overlap));
+            }
+            args.info(&format!("Coefficients: {}", info.coefficient_count));
+            args.info(&format!("Lossless: {}", info.lossless));
+        }
+        _ => return Err(BoxedError::from("only compressed files have info")),
+    }
+    Ok(())
+}
+
+/// Plot the difference between the retained frequencies of two compressed audio files.
+fn diff(options: &DiffArgs, args: &Args) -> Result<(), BoxedError> {
+    let file = PathBuf::from(&options.file);
+    if recognize(&file)? != FileKind::CompressedWav {
+        return Err(BoxedError::from(
+            "only compressed audio files can be compared",
+        ));
+    }
+    let output_dir = create_output_dir(&options.output_dir)?;
+    let path = wav::plot_spectral_diff(
+        &file,
+        &PathBuf::from(&options.other),
+        &output_dir,
+        options.plot.config(),
+    )?;
+    args.info(&format!("Spectral difference: {path:?}"));
+    Ok(())
+}
+
+fn concat(options: &ConcatArgs, args: &Args) -> Result<(), BoxedError> {
+    let files: Vec<PathBuf> = options.files.iter().map(PathBuf::from).collect();
+    for file in &files {
+        if recognize(file)? != FileKind::CompressedWav {
+            return Err(BoxedError::from(
+                "only compressed audio files can be concatenated",
+            ));
+        }
+    }
+    let output_dir = create_output_dir(&options.output_dir)?;
+    let stem = files[0].file_stem().unwrap_or_default().to_string_lossy();
+    let output = output_dir.join(format!("{stem}_concat.cwv"));
+    let inputs: Vec<&Path> = files.iter().map(PathBuf::as_path).collect();
+    wav::concat_compressed(&inputs, &output)?;
+    args.info(&format!("Concatenated {} files: {output:?}", files.len()));
+    Ok(())
+}
+
+/// Process each file of the kinds in a directory with the same settings, reporting failures
+/// without stopping.
+fn process_directory(
+    dir: &Path,
+    kinds: &[FileKind],
+    args: &Args,
+    process: impl Fn(&Path, FileKind) -> Result<Option<CompressionStats>, BoxedError>,
+) -> Result<(), BoxedError> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    files.retain(|file| file.is_file());
+    files.sort();
+    let (mut processed, mut failed) = (0, 0);
+    let (mut original_bytes, mut compressed_bytes) = (0, 0);
+    for file in &files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let Ok(kind) = recognize(file) else {
+            continue;
+        };
+        if !kinds.contains(&kind) {
+            continue;
+        }
+        args.detail(&format!("Processing {file:?} as {kind:?}"));
+        match process(file, kind) {
+            Ok(Some(stats)) => {
+                args.info(&format!(
+                    "{name}: {} -> {} bytes (ratio {:.2})",
+                    stats.original_bytes, stats.compressed_bytes, stats.ratio
+                ));
+                original_bytes += stats.original_bytes;
+                compressed_bytes += stats.compressed_bytes;
+            }
+            Ok(None) => args.info(&format!("{name}: done")),
+            Err(error) => {
+                eprintln!("{name}: {error}");
+                failed += 1;
+                continue;
+            }
+        }
+        processed += 1;
+    }
+    let total = CompressionStats::new(original_bytes, compressed_bytes);
+    args.info(&format!(
+        "Processed {processed} files ({failed} failed): {} -> {} bytes (ratio {:.2})",
+        total.original_bytes, total.compressed_bytes, total.ratio
+    ));
+    match failed {
+        0 => Ok(()),
+        _ => Err(BoxedError::from(format!("{failed} files failed"))),
     }
-    let stem = file
-        .file_stem()
+}
+
+/// Recognize a file by its content, the extension may be misleading.
+fn recognize(file: &Path) -> Result<FileKind, BoxedError> {
+    let extension = file.extension().map(|x| x.to_string_lossy().to_string());
+    Ok(format::resolve_format(
+        &fs::read(file)?,
+        extension.as_deref(),
+    )?)
+}
+
+/// Create an output directory and its missing parents, if it does not exist yet.
+fn create_output_dir(dir: &str) -> Result<PathBuf, BoxedError> {
+    let path = PathBuf::from(dir);
+    fs::create_dir_all(&path).map_err(|error| {
+        BoxedError::from(format!("cannot create output directory {path:?}: {error}"))
+    })?;
+    Ok(path)
+}
+
+/// Name of a file without its extension, to name the outputs after.
+fn file_stem(file: &Path) -> String {
+    file.file_stem()
         .expect("cannot get file stem")
         .to_string_lossy()
-        .to_string();
-    let suffix = file
-        .extension()
-        .expect("cannot get file suffix")
-        .to_string_lossy()
-        .to_string();
-    let output_dir = PathBuf::from(args.output_dir);
-    let wav_freq_cutoff = match args.compression.partial_cmp(&1.) {
-        Some(Ordering::Greater) => (22050. / args.compression).ceil() as usize,
-        _ => 22050,
+        .to_string()
+}
+
+/// Compress an audio or image file, returning the statistics of the compression.
+fn compress(
+    file: &Path,
+    kind: FileKind,
+    options: &CompressArgs,
+    args: &Args,
+) -> Result<Option<CompressionStats>, BoxedError> {
+    let stem = file_stem(file);
+    let output_dir = PathBuf::from(&options.output_dir);
+    // NaN and negative levels give a cutoff of 0, which the library rejects
+    let wav_freq_cutoff = (22050. / options.compression).ceil() as usize;
+    let progress = |fraction| {
+        if !args.quiet {
+            print_progress(fraction);
+        }
     };
-    let bmp_compression_level = match args.compression.partial_cmp(&0.) {
-        Some(Ordering::Greater) => args.compression,
-        _ => 0.01,
+    let (compressed_output, original_bytes, compressed) = match kind {
+        FileKind::Wav | FileKind::Flac => {
+            let input = fs::read(file)?;
+            let original_bytes = input.len() as u64;
+            let input = match options.downmix {
+                true => wav::downmix_wav_bytes(&input)?,
+                false => input,
+            };
+            let input = match options.resample {
+                Some(sample_rate) => wav::resample_wav_bytes(&input, sample_rate)?,
+                None => input,
+            };
+            let compressed = match options.frame_size {
+                None if options.progressive => wav::compress_wav_progressive_bytes(
+                    &input,
+                    wav_freq_cutoff,
+                    options.quantization,
+                    progress,
+                )?,
+                None if options.lossless => wav::compress_wav_lossless_bytes(
+                    &input,
+                    wav_freq_cutoff,
+                    options.quantization,
+                    progress,
+                )?,
+                Some(frame_size) => wav::compress_wav_blocked_bytes(
+                    &input,
+                    wav_freq_cutoff,
+                    options.quantization,
+                    frame_size,
+                    progress,
+                )?,
+                None => match (&options.quantization_curve, options.keep_only) {
+                    (Some(curve), _) => wav::compress_wav_weighted_bytes(
+                        &input,
+                        wav_freq_cutoff,
+                        options.quantization,
+                        &wav::parse_quantization_curve(curve)?,
+                        progress,
+                    )?,
+                    (None, Some(part)) => wav::compress_wav_with_transform_bytes(
+                        &input,
+                        wav_freq_cutoff,
+                        options.quantization,
+                        &PartialFftTransform(part),
+                        progress,
+                    )?,
+                    (None, None) => wav::compress_wav_bytes(
+                        &input,
+                        wav_freq_cutoff,
+                        options.quantization,
+                        progress,
+                    )?,
+                },
+            };
+            (
+                output_dir.join(format!("{stem}.cwv")),
+                original_bytes,
+                compressed,
+            )
+        }
+        FileKind::Bmp | FileKind::Png => {
+            let input = fs::read(file)?;
+            let level = options.compression;
+            let compressed = match (options.dct, options.tile_size) {
+                (false, None) if options.lossless => {
+                    bmp::compress_bmp_lossless_bytes(&input, level, progress)?
+                }
+                (false, None) if options.wavelet => {
+                    bmp::compress_bmp_wavelet_bytes(&input, level, progress)?
+                }
+                #[cfg(feature = "high-precision")]
+                (false, None) if options.high_precision => {
+                    bmp::compress_bmp_hp_bytes(&input, level, progress)?
+                }
+                (true, _) => match &options.quantization_table {
+                    Some(table) => bmp::compress_bmp_dct_with_table_bytes(
+                        &input,
+                        level,
+                        &bmp::load_quantization_table(table)?,
+                        progress,
+                    )?,
+                    None => bmp::compress_bmp_dct_bytes(&input, level, progress)?,
+                },
+                (false, Some(tile_size)) => bmp::compress_bmp_tiled_bytes(
+                    &input,
+                    level,
+                    tile_size,
+                    options.tile_overlap,
+                    progress,
+                )?,
+                (false, None) => bmp::compress_bmp_bytes(&input, level, progress)?,
+            };
+            let original_bytes = input.len() as u64;
+            (
+                output_dir.join(format!("{stem}.cbm")),
+                original_bytes,
+                compressed,
+            )
+        }
+        _ => return Err(BoxedError::from("compressed files cannot be compressed")),
     };
-    match (suffix.as_str(), args.analyze) {
-        // Compress
-        ("wav", false) => {
-            let compressed_output = output_dir.join(format!("{stem}.cwv"));
-            wav::compress_wav(&file, &compressed_output, wav_freq_cutoff)?;
-            println!("Compressed to: {compressed_output:?}");
-        }
-        ("bmp", false) => {
-            let compressed_output = output_dir.join(format!("{stem}.cbm"));
-            bmp::compress_bmp(&file, &compressed_output, bmp_compression_level)?;
-            println!("Compressed to: {compressed_output:?}");
-        }
-        // Decompress
-        ("cwv", false) => {
+    let stats = CompressionStats::new(original_bytes, compressed.len() as u64);
+    match options.dry_run {
+        true => args.info(&format!(
+            "Would compress to: {compressed_output:?} ({} bytes)",
+            compressed.len()
+        )),
+        false => {
+            create_output_dir(&options.output_dir)?;
+            fs::write(&compressed_output, &compressed)?;
+            args.info(&format!("Compressed to: {compressed_output:?}"));
+        }
+    }
+    args.info(&format!("Compression ratio: {:.2}", stats.ratio));
+    Ok(Some(stats))
+}
+
+/// Decompress a compressed audio or image file.
+fn decompress(
+    file: &Path,
+    kind: FileKind,
+    options: &DecompressArgs,
+    args: &Args,
+) -> Result<Option<CompressionStats>, BoxedError> {
+    let stem = file_stem(file);
+    let output_dir = create_output_dir(&options.output_dir)?;
+    match kind {
+        #[cfg(feature = "mp3")]
+        FileKind::CompressedWav if options.mp3 => {
+            let decompressed_output = output_dir.join(format!("{stem}_decompressed.mp3"));
+            wav::decompress_mp3(file, &decompressed_output)?;
+            args.info(&format!("Decompressed to: {decompressed_output:?}"));
+        }
+        FileKind::CompressedWav => {
             let decompressed_output = output_dir.join(format!("{stem}_decompressed.wav"));
-            wav::decompress_wav(&file, &decompressed_output)?;
-            println!("Decompressed to: {decompressed_output:?}");
+            match (options.dither, options.partial) {
+                (Some(seed), _) => wav::decompress_wav_dithered(file, &decompressed_output, seed)?,
+                (None, Some(fraction)) => {
+                    wav::decompress_wav_partial(file, &decompressed_output, fraction)?
+                }
+                (None, None) => wav::decompress_wav_streaming(file, &decompressed_output)?,
+            }
+            args.info(&format!("Decompressed to: {decompressed_output:?}"));
         }
-        ("cbm", false) => {
+        FileKind::CompressedBmp => {
             let decompressed_output = output_dir.join(format!("{stem}_decompressed.bmp"));
-            bmp::decompress_bmp(&file, &decompressed_output)?;
-            println!("Decompressed to: {decompressed_output:?}");
+            bmp::decompress_bmp_with(file, &decompressed_output, options.bmp_format)?;
+            args.info(&format!("Decompressed to: {decompressed_output:?}"));
+            if let Some(original) = &options.original {
+                let original = bmp::ComplexImage::from_image(&PathBuf::from(original))?;
+                let decompressed = bmp::ComplexImage::from_bitmap(&decompressed_output)?;
+                if original.size() != decompressed.size() {
+                    return Err(BoxedError::from("original image differs in size"));
+                }
+                args.info(&format!(
+                    "PSNR: {:.2} dB",
+                    quality::psnr(&original, &decompressed)
+                ));
+                args.info(&format!(
+                    "SSIM: {:.4}",
+                    quality::ssim(&original, &decompressed)
+                ));
+            }
         }
-        // Analyze
-        ("wav", true) => {
-            let analysis = wav::analyze_waveform(&file, &output_dir)?;
-            println!("Analysis file: {analysis:?}");
-            Command::new("xdg-open").arg(analysis).spawn()?;
+        _ => {
+            return Err(BoxedError::from(
+                "only compressed files can be decompressed",
+            ))
         }
-        ("bmp", true) => {
-            let log_factor = 1. / args.log_factor;
-            let analysis = bmp::analyze_image(&file, log_factor, &output_dir)?;
-            Command::new("xdg-open").arg(analysis).spawn()?;
+    }
+    Ok(None)
+}
+
+/// Plot the frequencies of an audio or image file.
+fn analyze(
+    file: &Path,
+    kind: FileKind,
+    options: &AnalyzeArgs,
+    args: &Args,
+) -> Result<Option<CompressionStats>, BoxedError> {
+    let output_dir = create_output_dir(&options.output_dir)?;
+    let plot_config = options.plot.config();
+    args.detail(&format!("Analyzing {file:?}..."));
+    let analysis = match kind {
+        FileKind::Wav | FileKind::Flac => {
+            let (window, pad) = (options.window, options.pad);
+            let analysis = match &options.overlay {
+                Some(compressed) => wav::analyze_waveform_overlay(
+                    file,
+                    &PathBuf::from(compressed),
+                    &output_dir,
+                    window,
+                    pad,
+                    plot_config,
+                )?,
+                None => wav::analyze_waveform(file, &output_dir, window, pad, plot_config)?,
+            };
+            args.info(&format!("Analysis file: {:?}", analysis.path));
+            args.info("Dominant frequencies:");
+            for (frequency, amplitude) in &analysis.dominant_frequencies {
+                args.info(&format!("  {frequency:.1} Hz (amplitude {amplitude:.2})"));
+            }
+            analysis.path
+        }
+        FileKind::Bmp | FileKind::Png => {
+            let log_factor = 1. / options.log_factor;
+            let analysis = bmp::analyze_image(file, log_factor, &output_dir, plot_config)?;
+            args.info(&format!("Analysis file: {analysis:?}"));
+            analysis
         }
-        _ => return Err(BoxedError::from("file suffix unrecognized")),
+        _ => return Err(BoxedError::from("compressed files cannot be analyzed")),
+    };
+    if options.open {
+        open_in_browser(&analysis)?;
     }
+    Ok(None)
+}
+
+/// Open a file with the default application of the platform, without waiting for it.
+fn open_in_browser(file: &Path) -> Result<(), BoxedError> {
+    let mut command = match std::env::consts::OS {
+        "macos" => Command::new("open"),
+        "windows" => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        _ => Command::new("xdg-open"),
+    };
+    command
+        .arg(file)
+        .spawn()
+        .map_err(|e| format!("failed to open {file:?} ({e}), open it manually"))?;
     Ok(())
 }
+
+/// Print the progress as a percentage, overwriting the previous line.
+fn print_progress(fraction: f32) {
+    print!("\rCompressing... {:.0}%", fraction * 100.);
+    if fraction >= 1. {
+        println!();
+    }
+    std::io::stdout().flush().ok();
+}
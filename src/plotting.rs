@@ -0,0 +1,35 @@
+//! # Plotting
+//! Size and theme of the html pages written by the analyze functions.
+//!
+
+use plotly::layout::themes::PLOTLY_DARK;
+use plotly::Layout;
+use std::path::{Path, PathBuf};
+
+/// Appearance of an analysis plot. Unset dimensions fall back to the default of each plot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlotConfig {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub dark_mode: bool,
+}
+
+impl PlotConfig {
+    /// Size a layout (by default to the given dimensions) and apply the dark template if set.
+    pub(crate) fn apply(&self, layout: Layout, width: usize, height: usize) -> Layout {
+        let layout = layout
+            .width(self.width.unwrap_or(width))
+            .height(self.height.unwrap_or(height));
+        match self.dark_mode {
+            true => layout.template(&*PLOTLY_DARK),
+            false => layout,
+        }
+    }
+}
+
+/// Path of the analysis of an input file, named after its stem (like `song_analysis.html`) so
+/// analyses of different files do not overwrite each other.
+pub(crate) fn analysis_path(input: &Path, output_dir: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    output_dir.join(format!("{stem}_analysis.html"))
+}
@@ -0,0 +1,131 @@
+//! # Quality metrics
+//! Objective measures of how closely a decompressed image matches the original.
+//!
+
+use crate::bmp::{ComplexChannel, ComplexImage};
+
+/// Largest color value.
+const PEAK: f32 = 255.;
+/// Width and height of the windows over which the structural similarity is averaged.
+const SSIM_WINDOW: usize = 8;
+
+/// Peak signal-to-noise ratio (in decibels) of each channel, averaged over the channels.
+///
+/// Identical images have an infinite ratio. Panics if the images differ in size.
+pub fn psnr(a: &ComplexImage, b: &ComplexImage) -> f32 {
+    assert_eq!(a.size(), b.size(), "images differ in size");
+    let ratios = a.channels().into_iter().zip(b.channels()).map(|(a, b)| {
+        let errors: Vec<f32> = a
+            .iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .map(|(a, b)| (a.re - b.re).powi(2))
+            .collect();
+        let mse = errors.iter().sum::<f32>() / errors.len().max(1) as f32;
+        10. * (PEAK * PEAK / mse).log10()
+    });
+    ratios.sum::<f32>() / 3.
+}
+
+/// Largest absolute difference between the values of any channel of the images.
+///
+/// Panics if the images differ in size.
+pub fn max_error(a: &ComplexImage, b: &ComplexImage) -> f32 {
+    assert_eq!(a.size(), b.size(), "images differ in size");
+    a.channels()
+        .into_iter()
+        .zip(b.channels())
+        .flat_map(|(a, b)| a.iter().flatten().zip(b.iter().flatten()))
+        .map(|(a, b)| (a.re - b.re).abs())
+        .fold(0., f32::max)
+}
+
+/// Structural similarity of each channel, averaged over non-overlapping 8x8 windows and then
+/// over the channels. Ranges up to 1 for identical images.
+///
+/// Panics if the images differ in size.
+pub fn ssim(a: &ComplexImage, b: &ComplexImage) -> f32 {
+    assert_eq!(a.size(), b.size(), "images differ in size");
+    let (width, height) = a.size();
+    let similarities = a.channels().into_iter().zip(b.channels()).map(|(a, b)| {
+        let windows: Vec<f32> = (0..height)
+            .step_by(SSIM_WINDOW)
+            .flat_map(|y| (0..width).step_by(SSIM_WINDOW).map(move |x| (x, y)))
+            .map(|(x, y)| window_ssim(a, b, x, y))
+            .collect();
+        windows.iter().sum::<f32>() / windows.len().max(1) as f32
+    });
+    similarities.sum::<f32>() / 3.
+}
+
+/// Structural similarity of the window starting at (x, y), truncated at the edges.
+fn window_ssim(a: &ComplexChannel, b: &ComplexChannel, x: usize, y: usize) -> f32 {
+    // Stabilize the division for windows with low mean or variance
+    let c1 = (0.01 * PEAK).powi(2);
+    let c2 = (0.03 * PEAK).powi(2);
+    let pairs: Vec<(f32, f32)> = a[y..]
+        .iter()
+        .zip(&b[y..])
+        .take(SSIM_WINDOW)
+        .flat_map(|(a, b)| a[x..].iter().zip(&b[x..]).take(SSIM_WINDOW))
+        .map(|(a, b)| (a.re, b.re))
+        .collect();
+    let count = pairs.len() as f32;
+    let mean_a = pairs.iter().map(|(a, _)| a).sum::<f32>() / count;
+    let mean_b = pairs.iter().map(|(_, b)| b).sum::<f32>() / count;
+    let (mut variance_a, mut variance_b, mut covariance) = (0., 0., 0.);
+    for (a, b) in &pairs {
+        variance_a += (a - mean_a).powi(2) / count;
+        variance_b += (b - mean_b).powi(2) / count;
+        covariance += (a - mean_a) * (b - mean_b) / count;
+    }
+    ((2. * mean_a * mean_b + c1) * (2. * covariance + c2))
+        / ((mean_a.powi(2) + mean_b.powi(2) + c1) * (variance_a + variance_b + c2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex32;
+
+    fn image(width: usize, height: usize, value: impl Fn(usize, usize) -> f32) -> ComplexImage {
+        let channel: ComplexChannel = (0..height)
+            .map(|y| (0..width).map(|x| Complex32::from(value(x, y))).collect())
+            .collect();
+        ComplexImage::new(channel.clone(), channel.clone(), channel)
+    }
+
+    #[test]
+    fn psnr_of_perturbation() {
+        let original = image(20, 12, |x, y| ((x * 13 + y * 7) % 200) as f32);
+        assert_eq!(psnr(&original, &original), f32::INFINITY);
+        // Every value off by 2 is a mean squared error of 4
+        let perturbed = image(20, 12, |x, y| ((x * 13 + y * 7) % 200) as f32 + 2.);
+        let expected = 10. * (255f32.powi(2) / 4.).log10();
+        assert!((psnr(&original, &perturbed) - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn max_error_of_single_pixel() {
+        let original = image(20, 12, |x, y| ((x * 13 + y * 7) % 200) as f32);
+        assert_eq!(max_error(&original, &original), 0.);
+        let perturbed = image(20, 12, |x, y| match (x, y) {
+            (3, 5) => ((x * 13 + y * 7) % 200) as f32 - 9.,
+            _ => ((x * 13 + y * 7) % 200) as f32 + 1.,
+        });
+        assert_eq!(max_error(&original, &perturbed), 9.);
+    }
+
+    #[test]
+    fn ssim_of_noise() {
+        let original = image(20, 12, |x, y| ((x * 13 + y * 7) % 200) as f32);
+        assert!((ssim(&original, &original) - 1.).abs() < 1e-5);
+        let noisy = image(20, 12, |x, y| {
+            ((x * 13 + y * 7) % 200) as f32 + ((x * 7919 + y * 104729) % 41) as f32 - 20.
+        });
+        let noise_ssim = ssim(&original, &noisy);
+        assert!(noise_ssim < 0.99, "{noise_ssim}");
+        let uniform = image(20, 12, |_, _| 100.);
+        assert!(ssim(&original, &uniform) < noise_ssim);
+    }
+}
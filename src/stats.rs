@@ -0,0 +1,47 @@
+//! # Compression statistics
+//! Sizes reported by the compression functions.
+//!
+
+/// Sizes of a compressed file relative to its original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionStats {
+    /// Size of the input file
+    pub original_bytes: u64,
+    /// Size of the serialized output
+    pub compressed_bytes: u64,
+    /// Original size divided by compressed size: higher = better compression
+    pub ratio: f64,
+    /// Frequency cutoff chosen to meet a size budget, see [`crate::wav::compress_wav_to_size`]
+    pub freq_cutoff: Option<usize>,
+    /// Energy retained by the frequency cutoff relative to the energy it discards (in dB), see
+    /// [`crate::wav::cutoff_snr`]
+    pub cutoff_snr: Option<f32>,
+}
+
+impl CompressionStats {
+    pub fn new(original_bytes: u64, compressed_bytes: u64) -> CompressionStats {
+        CompressionStats {
+            original_bytes,
+            compressed_bytes,
+            ratio: original_bytes as f64 / compressed_bytes as f64,
+            freq_cutoff: None,
+            cutoff_snr: None,
+        }
+    }
+
+    /// Record the frequency cutoff that was chosen.
+    pub fn with_freq_cutoff(self, freq_cutoff: usize) -> CompressionStats {
+        CompressionStats {
+            freq_cutoff: Some(freq_cutoff),
+            ..self
+        }
+    }
+
+    /// Record the energy lost to the frequency cutoff.
+    pub fn with_cutoff_snr(self, cutoff_snr: f32) -> CompressionStats {
+        CompressionStats {
+            cutoff_snr: Some(cutoff_snr),
+            ..self
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! # Transforms
+//! The transforms that audio can be compressed with, interchangeable through the [`Transform`]
+//! trait, see [`crate::wav::compress_wav_with_transform`].
+//!
+
+use crate::fft::{self, FftError};
+use num_complex::Complex32;
+use std::f32::consts::PI;
+
+/// A transform of a real signal into coefficients ordered from the lowest frequency to the
+/// highest (the Nyquist frequency), so that dropping the last ones drops the highest
+/// frequencies.
+pub trait Transform {
+    /// Coefficients of a signal (whose imaginary parts are ignored).
+    fn forward(&self, signal: &[Complex32]) -> Result<Vec<Complex32>, FftError>;
+    /// Restore a signal of `sample_size` samples from all of its coefficients.
+    fn inverse(
+        &self,
+        coefficients: &[Complex32],
+        sample_size: usize,
+    ) -> Result<Vec<Complex32>, FftError>;
+    /// Width (in Hz) of the frequency band of each coefficient of a signal of `sample_size`
+    /// samples.
+    fn bin_width(&self, sample_rate: usize, sample_size: usize) -> f32;
+}
+
+/// The FFT, of which only the non-redundant half of the spectrum is kept: `n / 2 + 1` complex
+/// coefficients of `n` samples, see [`fft::rfft`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FftTransform;
+
+impl Transform for FftTransform {
+    fn forward(&self, signal: &[Complex32]) -> Result<Vec<Complex32>, FftError> {
+        let samples: Vec<f32> = signal.iter().map(|x| x.re).collect();
+        fft::rfft(&samples)
+    }
+
+    fn inverse(
+        &self,
+        coefficients: &[Complex32],
+        sample_size: usize,
+    ) -> Result<Vec<Complex32>, FftError> {
+        let samples = fft::irfft(coefficients, sample_size)?;
+        Ok(samples.into_iter().map(Complex32::from).collect())
+    }
+
+    fn bin_width(&self, sample_rate: usize, sample_size: usize) -> f32 {
+        sample_rate as f32 / sample_size as f32
+    }
+}
+
+/// The discrete cosine transform (DCT-II): `n` real coefficients of `n` samples, at half the
+/// bin width of the FFT. Without the discontinuity of the FFT wrapping around the ends of the
+/// signal, its energy is more compact in the lowest coefficients.
+///
+/// Computed by the FFT of the signal extended by its mirror image.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DctTransform;
+
+impl Transform for DctTransform {
+    fn forward(&self, signal: &[Complex32]) -> Result<Vec<Complex32>, FftError> {
+        let sample_size = signal.len();
+        let mirrored: Vec<f32> = signal
+            .iter()
+            .chain(signal.iter().rev())
+            .map(|x| x.re)
+            .collect();
+        let bins = fft::rfft(&mirrored)?;
+        Ok(bins
+            .iter()
+            .take(sample_size)
+            .enumerate()
+            .map(|(k, bin)| Complex32::from((bin * half_shift(k, sample_size).conj()).re / 2.))
+            .collect())
+    }
+
+    fn inverse(
+        &self,
+        coefficients: &[Complex32],
+        sample_size: usize,
+    ) -> Result<Vec<Complex32>, FftError> {
+        if coefficients.len() != sample_size {
+            return Err(FftError::SizeMismatch {
+                expected: sample_size,
+                len: coefficients.len(),
+            });
+        }
+        // The spectrum of the mirrored signal, whose Nyquist bin is zero
+        let bins: Vec<Complex32> = coefficients
+            .iter()
+            .enumerate()
+            .map(|(k, x)| half_shift(k, sample_size) * x.re * 2.)
+            .chain([Complex32::default()])
+            .collect();
+        let mirrored = fft::irfft(&bins, sample_size * 2)?;
+        Ok(mirrored[..sample_size]
+            .iter()
+            .map(|x| Complex32::from(*x))
+            .collect())
+    }
+
+    fn bin_width(&self, sample_rate: usize, sample_size: usize) -> f32 {
+        sample_rate as f32 / (2 * sample_size) as f32
+    }
+}
+
+/// Part of the spectrum kept by [`PartialFftTransform`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SpectrumPart {
+    /// The magnitudes, with every phase zeroed
+    Magnitude,
+    /// The phases, with every magnitude set to the same (root mean square) magnitude
+    Phase,
+}
+
+/// The FFT of [`FftTransform`] keeping only part of the spectrum, to hear what the other part
+/// carries: zeroing the phases scatters the timing of a sound (it restores as symmetric about
+/// the start of the signal), while the phases alone keep much of it.
+///
+/// The coefficients are FFT coefficients, so the compressed files decompress like any other.
+#[derive(Debug, Clone, Copy)]
+pub struct PartialFftTransform(pub SpectrumPart);
+
+impl Transform for PartialFftTransform {
+    fn forward(&self, signal: &[Complex32]) -> Result<Vec<Complex32>, FftError> {
+        let bins = FftTransform.forward(signal)?;
+        Ok(match self.0 {
+            SpectrumPart::Magnitude => bins.iter().map(|x| Complex32::from(x.norm())).collect(),
+            SpectrumPart::Phase => {
+                let mean_energy =
+                    bins.iter().map(Complex32::norm_sqr).sum::<f32>() / bins.len().max(1) as f32;
+                let magnitude = mean_energy.sqrt();
+                bins.iter()
+                    .map(|x| match x.norm() > 0. {
+                        true => x / x.norm() * magnitude,
+                        false => Complex32::default(),
+                    })
+                    .collect()
+            }
+        })
+    }
+
+    fn inverse(
+        &self,
+        coefficients: &[Complex32],
+        sample_size: usize,
+    ) -> Result<Vec<Complex32>, FftError> {
+        FftTransform.inverse(coefficients, sample_size)
+    }
+
+    fn bin_width(&self, sample_rate: usize, sample_size: usize) -> f32 {
+        FftTransform.bin_width(sample_rate, sample_size)
+    }
+}
+
+/// Phase shift of half a sample at bin k of the mirrored signal.
+fn half_shift(k: usize, sample_size: usize) -> Complex32 {
+    Complex32::from_polar(1., PI * k as f32 / (2 * sample_size) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(sample_size: usize) -> Vec<Complex32> {
+        (0..sample_size)
+            .map(|x| Complex32::from((x as f32 * 0.3).sin() * 100. + x as f32))
+            .collect()
+    }
+
+    #[test]
+    fn dct_matches_definition() {
+        let signal = signal(12);
+        let coefficients = DctTransform.forward(&signal).unwrap();
+        assert_eq!(coefficients.len(), 12);
+        for (k, coefficient) in coefficients.iter().enumerate() {
+            let expected: f32 = signal
+                .iter()
+                .enumerate()
+                .map(|(n, x)| x.re * (PI / 12. * (n as f32 + 0.5) * k as f32).cos())
+                .sum();
+            assert!((coefficient.re - expected).abs() < 0.05, "{k}");
+            assert_eq!(coefficient.im, 0.);
+        }
+    }
+
+    #[test]
+    fn partial_spectrum() {
+        let signal = signal(16);
+        let bins = FftTransform.forward(&signal).unwrap();
+        let magnitudes = PartialFftTransform(SpectrumPart::Magnitude)
+            .forward(&signal)
+            .unwrap();
+        let phases = PartialFftTransform(SpectrumPart::Phase)
+            .forward(&signal)
+            .unwrap();
+        let magnitude = phases[0].norm();
+        for ((bin, kept_magnitude), kept_phase) in bins.iter().zip(&magnitudes).zip(&phases) {
+            assert!((kept_magnitude.re - bin.norm()).abs() < 1e-3);
+            assert_eq!(kept_magnitude.im, 0.);
+            assert!((kept_phase.norm() - magnitude).abs() < 1e-3);
+            assert!((kept_phase.arg() - bin.arg()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn inverse_restores() {
+        let transforms: [&dyn Transform; 2] = [&FftTransform, &DctTransform];
+        for transform in transforms {
+            for sample_size in [7, 64, 100] {
+                let signal = signal(sample_size);
+                let coefficients = transform.forward(&signal).unwrap();
+                let restored = transform.inverse(&coefficients, sample_size).unwrap();
+                assert_eq!(restored.len(), sample_size);
+                for (x, y) in signal.iter().zip(&restored) {
+                    assert!((x.re - y.re).abs() < 0.01, "{sample_size}: {x} != {y}");
+                }
+            }
+        }
+    }
+}
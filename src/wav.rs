@@ -1,195 +1,2466 @@
+// The wav crate is deprecated in favor of hound, but remains sufficient for our needs
+#![allow(deprecated)]
+
+use crate::dump;
+use crate::entropy::{self, EntropyError};
+use crate::error::CompressionError;
 use crate::fft;
+use crate::filter;
+use crate::format::FLAC_MAGIC;
+use crate::header::{self, WAV_MAGIC, WAV_VERSION};
+use crate::plotting::{self, PlotConfig};
+use crate::stats::CompressionStats;
+use crate::transform::{FftTransform, Transform};
+use crate::window::{self, WindowKind};
+use bincode::Options;
 use num_complex::Complex32;
 use plotly::{
     color::NamedColor,
     common::{Line, Mode, Title},
     layout::{Axis, GridPattern, LayoutGrid, RowOrder},
-    Layout, Plot, Scatter,
+    HeatMap, Layout, Plot, Scatter,
 };
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-use std::{error::Error, path::PathBuf};
-use thiserror::Error;
-use wav::{BitDepth, Header};
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use wav::{BitDepth, Header, WAV_FORMAT_IEEE_FLOAT, WAV_FORMAT_PCM};
 
-/// Returned when file formats are not supported.
-#[derive(Error, Debug)]
-pub enum FormatError {
-    #[error("multiple channels not supported")]
-    UnsupportedChannels,
-    #[error("unrecognized format not supported")]
-    UnsupportedFormat,
-}
+pub use crate::format::FormatError;
 
-/// Compress a .wav file for later decompression using [`decompress_wav`].
+/// Compress a .wav file (or a .flac file with the `flac` feature) for later decompression using
+/// [`decompress_wav`].
 ///
 /// The frequency cutoff is the highest frequency to maintain: lower = smaller compressed size,
-/// higher = better quality.
+/// higher = better quality. Each channel is compressed independently.
+///
+/// The retained frequencies are quantized to integers, the quantization (no smaller than 1)
+/// determines how coarsely: higher = smaller compressed size, lower = better quality. Steps are
+/// relative to the largest frequency component, so quiet recordings are quantized as finely as
+/// loud ones.
+///
+/// The statistics report how much of the signal the cutoff discards, see [`cutoff_snr`].
+///
+/// Progress is reported as a fraction as each channel is transformed, and 1.0 when done.
 pub fn compress_wav(
-    wav_file: &PathBuf,
-    output_file: &PathBuf,
+    wav_file: &Path,
+    output_file: &Path,
     freq_cutoff: usize,
-) -> Result<(), Box<dyn Error>> {
-    let (metadata, mut waveform) = load_wav_file(&wav_file)?;
-    let original_size = waveform.len();
-    fft::round_sample_size_up(&mut waveform);
-    let time_domain = fft::convert_sample(&waveform);
-    let mut freq_domain = fft::fft(&time_domain);
-    let freq_resolution = metadata.freq_resolution(waveform.len());
-    let highest_bin = f32::ceil(freq_cutoff as f32 / freq_resolution) as usize;
-    let highest_bin = highest_bin.min(freq_domain.len()).max(0);
-    let cutoff_zeros = freq_domain.len() - highest_bin;
-    freq_domain.drain(highest_bin..);
-    let frequencies: Vec<(f32, f32)> = freq_domain.iter().map(|c| (c.re, c.im)).collect();
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let options = CompressOptions::new()
+        .freq_cutoff(freq_cutoff)
+        .quantization(quantization);
+    compress_wav_with(wav_file, output_file, &options, progress)
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav`].
+pub fn compress_wav_bytes(
+    input: &[u8],
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let options = CompressOptions::new()
+        .freq_cutoff(freq_cutoff)
+        .quantization(quantization);
+    compress_wav_with_bytes(input, &options, progress)
+}
+
+/// Options of [`compress_wav_with`], each set by a method over the defaults: every frequency
+/// kept, quantized by 1, in a single frame.
+///
+/// There is no window option: frames are always tapered by the square root of the Hann window,
+/// because the decoder tapers them again by the same window (which the compressed format does
+/// not record) for the overlapping frames to add up to the original, see
+/// [`compress_wav_blocked`]. A single frame is not windowed, as the window could not be undone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressOptions {
+    freq_cutoff: usize,
+    quantization: f32,
+    quantization_curve: Vec<(f32, f32)>,
+    frame_size: Option<usize>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            freq_cutoff: usize::MAX,
+            quantization: 1.,
+            quantization_curve: Vec::new(),
+            frame_size: None,
+        }
+    }
+}
+
+impl CompressOptions {
+    pub fn new() -> CompressOptions {
+        CompressOptions::default()
+    }
+
+    /// Highest frequency (in Hz) to keep, see [`compress_wav`].
+    pub fn freq_cutoff(self, freq_cutoff: usize) -> CompressOptions {
+        CompressOptions {
+            freq_cutoff,
+            ..self
+        }
+    }
+
+    /// Quantization of the frequencies, see [`compress_wav`].
+    pub fn quantization(self, quantization: f32) -> CompressOptions {
+        CompressOptions {
+            quantization,
+            ..self
+        }
+    }
+
+    /// Quantize some frequencies more coarsely than others, see [`compress_wav_weighted`]. Not
+    /// supported in frames.
+    pub fn quantization_curve(self, quantization_curve: Vec<(f32, f32)>) -> CompressOptions {
+        CompressOptions {
+            quantization_curve,
+            ..self
+        }
+    }
+
+    /// Compress in overlapping frames of this many samples, see [`compress_wav_blocked`].
+    pub fn frame_size(self, frame_size: usize) -> CompressOptions {
+        CompressOptions {
+            frame_size: Some(frame_size),
+            ..self
+        }
+    }
+}
+
+/// Compress a .wav file for later decompression using [`decompress_wav`], with the options
+/// set by [`CompressOptions`].
+///
+/// The statistics report the [`cutoff_snr`], unless compressed in frames.
+///
+/// Progress is reported as a fraction as each channel (or frame) is transformed, and 1.0 when
+/// done.
+pub fn compress_wav_with(
+    wav_file: &Path,
+    output_file: &Path,
+    options: &CompressOptions,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let (compressed, snr) = compress_with_options(&input, options, progress)?;
+    fs::write(output_file, &compressed)?;
+    let stats = CompressionStats::new(input.len() as u64, compressed.len() as u64);
+    Ok(match snr {
+        Some(snr) => stats.with_cutoff_snr(snr),
+        None => stats,
+    })
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_with`].
+pub fn compress_wav_with_bytes(
+    input: &[u8],
+    options: &CompressOptions,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let (compressed, _) = compress_with_options(input, options, progress)?;
+    Ok(compressed)
+}
+
+/// Compress with [`CompressOptions`], returning the [`cutoff_snr`] of single frames.
+fn compress_with_options(
+    input: &[u8],
+    options: &CompressOptions,
+    progress: impl FnMut(f32),
+) -> Result<(Vec<u8>, Option<f32>), CompressionError> {
+    if !options.quantization_curve.is_empty() {
+        validate_curve(&options.quantization_curve)?;
+    }
+    let (metadata, channels) = read_wav(input)?;
+    match options.frame_size {
+        Some(_) if !options.quantization_curve.is_empty() => Err(
+            CompressionError::InvalidParameter("a quantization curve is not supported in frames"),
+        ),
+        Some(frame_size) => {
+            let compressed = compress_frames(
+                &metadata,
+                &channels,
+                options.freq_cutoff,
+                options.quantization,
+                frame_size,
+                progress,
+            )?;
+            Ok((compressed, None))
+        }
+        None => {
+            let (compressed, snr) = compress_channels_measured(
+                &metadata,
+                channels,
+                options.freq_cutoff,
+                options.quantization,
+                &options.quantization_curve,
+                &FftTransform,
+                progress,
+            )?;
+            Ok((compressed, Some(snr)))
+        }
+    }
+}
+
+/// Compress a .wav file like [`compress_wav`], by another transform than the FFT (see
+/// [`crate::transform`]), for later decompression using [`decompress_wav_with_transform`] with
+/// the same transform.
+///
+/// The frequency cutoff keeps the coefficients of the transform up to the cutoff.
+pub fn compress_wav_with_transform(
+    wav_file: &Path,
+    output_file: &Path,
+    freq_cutoff: usize,
+    quantization: f32,
+    transform: &dyn Transform,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let compressed =
+        compress_wav_with_transform_bytes(&input, freq_cutoff, quantization, transform, progress)?;
+    fs::write(output_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_with_transform`].
+pub fn compress_wav_with_transform_bytes(
+    input: &[u8],
+    freq_cutoff: usize,
+    quantization: f32,
+    transform: &dyn Transform,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = read_wav(input)?;
+    compress_channels(
+        &metadata,
+        channels,
+        freq_cutoff,
+        quantization,
+        &[],
+        transform,
+        progress,
+    )
+}
+
+/// Compress a .wav file like [`compress_wav`], quantizing some frequencies more coarsely than
+/// others, such as those at the extremes of hearing.
+///
+/// The curve is a list of (frequency in Hz, strength) points in ascending order of frequency,
+/// see [`parse_quantization_curve`]. The strength of each frequency bin is interpolated
+/// linearly between the points, and is that of the nearest point past either end. The
+/// quantization step of a bin is multiplied by its strength, from 1 (no coarser than
+/// [`compress_wav`]) to 2047, rounded to sixteenths which are stored with the frequencies.
+pub fn compress_wav_weighted(
+    wav_file: &Path,
+    output_file: &Path,
+    freq_cutoff: usize,
+    quantization: f32,
+    curve: &[(f32, f32)],
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let compressed =
+        compress_wav_weighted_bytes(&input, freq_cutoff, quantization, curve, progress)?;
+    fs::write(output_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_weighted`].
+pub fn compress_wav_weighted_bytes(
+    input: &[u8],
+    freq_cutoff: usize,
+    quantization: f32,
+    curve: &[(f32, f32)],
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    validate_curve(curve)?;
+    let (metadata, channels) = read_wav(input)?;
+    compress_channels(
+        &metadata,
+        channels,
+        freq_cutoff,
+        quantization,
+        curve,
+        &FftTransform,
+        progress,
+    )
+}
+
+/// Parse a quantization curve for [`compress_wav_weighted`] from `frequency:strength` points,
+/// separated by whitespace or commas, like `0:8, 100:1, 12000:1, 16000:8`.
+pub fn parse_quantization_curve(text: &str) -> Result<Vec<(f32, f32)>, CompressionError> {
+    let curve = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|point| !point.is_empty())
+        .map(|point| {
+            let (frequency, strength) = point.split_once(':')?;
+            Some((frequency.parse().ok()?, strength.parse().ok()?))
+        })
+        .collect::<Option<Vec<(f32, f32)>>>()
+        .ok_or(CompressionError::InvalidParameter(
+            "quantization curve must be frequency:strength points",
+        ))?;
+    validate_curve(&curve)?;
+    Ok(curve)
+}
+
+/// Weights are stored in sixteenths of the quantization step.
+const WEIGHT_UNIT: f32 = 16.;
+
+fn validate_curve(curve: &[(f32, f32)]) -> Result<(), CompressionError> {
+    let descending = |pair: &[(f32, f32)]| {
+        matches!(
+            pair[0].0.partial_cmp(&pair[1].0),
+            Some(Ordering::Greater) | None
+        )
+    };
+    if curve.is_empty() || curve.windows(2).any(descending) {
+        return Err(CompressionError::InvalidParameter(
+            "quantization curve must have points in ascending order of frequency",
+        ));
+    }
+    let strongest = i16::MAX as f32 / WEIGHT_UNIT;
+    if curve
+        .iter()
+        .any(|(_, strength)| !(1.0..=strongest).contains(strength))
+    {
+        return Err(CompressionError::InvalidParameter(
+            "quantization strengths must be between 1 and 2047",
+        ));
+    }
+    Ok(())
+}
+
+/// Weight of each of the first bins of a transform, interpolated from the strengths of the
+/// curve, in units of [`WEIGHT_UNIT`].
+fn bin_weights(curve: &[(f32, f32)], freq_resolution: f32, bin_count: usize) -> Vec<i16> {
+    (0..bin_count)
+        .map(|bin| {
+            let frequency = bin as f32 * freq_resolution;
+            // First point at or past the frequency
+            let next = curve.partition_point(|(point, _)| *point < frequency);
+            let strength = match next {
+                0 => curve[0].1,
+                n if n == curve.len() => curve[n - 1].1,
+                n => {
+                    let ((low, low_strength), (high, high_strength)) = (curve[n - 1], curve[n]);
+                    let t = (frequency - low) / (high - low);
+                    low_strength + t * (high_strength - low_strength)
+                }
+            };
+            (strength * WEIGHT_UNIT).round() as i16
+        })
+        .collect()
+}
+
+/// Compress a .wav file like [`compress_wav`], also storing the residual between the
+/// decompressed and the original samples, so that [`decompress_wav`] restores the samples
+/// exactly.
+///
+/// The compressed file is larger than the lossy one but smaller than the original, the more
+/// the lossy compression keeps the smaller the residual.
+pub fn compress_wav_lossless(
+    wav_file: &Path,
+    output_file: &Path,
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let compressed = compress_wav_lossless_bytes(&input, freq_cutoff, quantization, progress)?;
+    fs::write(output_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_lossless`].
+pub fn compress_wav_lossless_bytes(
+    input: &[u8],
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = read_wav(input)?;
+    let original = sample_values(interleave(&channels), metadata.bit_rate)?;
+    let lossy = compress_channels(
+        &metadata,
+        channels,
+        freq_cutoff,
+        quantization,
+        &[],
+        &FftTransform,
+        progress,
+    )?;
+    let (_, restored) = decompress_channels(&lossy, &FftTransform)?;
+    let restored = sample_values(interleave(&restored), metadata.bit_rate)?;
+    let residual: Vec<i32> = original
+        .iter()
+        .zip(&restored)
+        .map(|(original, restored)| original.wrapping_sub(*restored))
+        .collect();
+    let mut compressed = deserialize_compressed(&lossy)?;
+    compressed.residual = Some(entropy::encode_wide(&residual)?);
+    serialize_compressed(&compressed)
+}
+
+/// Compress a .wav file like [`compress_wav`], storing the retained frequencies by descending
+/// magnitude with their positions, so that [`decompress_wav_partial`] can restore a rough
+/// version from the most significant of them.
+///
+/// [`decompress_wav`] restores the same waveform as from [`compress_wav`]. The positions make
+/// the compressed file larger.
+pub fn compress_wav_progressive(
+    wav_file: &Path,
+    compressed_file: &Path,
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let compressed = compress_wav_progressive_bytes(&input, freq_cutoff, quantization, progress)?;
+    fs::write(compressed_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_progressive`].
+pub fn compress_wav_progressive_bytes(
+    input: &[u8],
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let mut compressed = deserialize_compressed(&compress_wav_bytes(
+        input,
+        freq_cutoff,
+        quantization,
+        progress,
+    )?)?;
+    let mut frequencies: Vec<(usize, (i16, i16))> = compressed
+        .channels()?
+        .iter()
+        .flat_map(|frames| frames[0].clone())
+        .enumerate()
+        .collect();
+    let magnitude = |(re, im): (i16, i16)| (re as i64).pow(2) + (im as i64).pow(2);
+    // Stable, so bins of the same magnitude stay in order
+    frequencies.sort_by_key(|(_, value)| std::cmp::Reverse(magnitude(*value)));
+    let positions: Vec<i32> = frequencies.iter().map(|(p, _)| *p as i32).collect();
+    let values: Vec<i16> = frequencies
+        .iter()
+        .flat_map(|(_, (re, im))| [*re, *im])
+        .collect();
+    compressed.frequencies = entropy::encode(&values)?;
+    compressed.order = Some(entropy::encode_wide(&positions)?);
+    serialize_compressed(&compressed)
+}
+
+/// Decompress a file from [`compress_wav_progressive`] like [`decompress_wav`], from only the
+/// first fraction (0 to 1) of its frequencies, the most significant.
+///
+/// The whole file is still read, as its checksum covers all of it.
+pub fn decompress_wav_partial(
+    compressed_file: &Path,
+    output_file: &Path,
+    fraction: f32,
+) -> Result<(), CompressionError> {
+    let decompressed = decompress_wav_partial_bytes(&fs::read(compressed_file)?, fraction)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .wav file in memory, see
+/// [`decompress_wav_partial`].
+pub fn decompress_wav_partial_bytes(
+    compressed: &[u8],
+    fraction: f32,
+) -> Result<Vec<u8>, CompressionError> {
+    if !(0.0..=1.).contains(&fraction) {
+        return Err(CompressionError::InvalidParameter(
+            "fraction must be between 0 and 1",
+        ));
+    }
+    let decoded = deserialize_compressed(compressed)?;
+    let Some(order) = &decoded.order else {
+        return Err(CompressionError::InvalidParameter(
+            "only progressive files can be decompressed partially",
+        ));
+    };
+    let count = (decoded.coefficient_count()? as f32 * fraction).round() as usize;
+    let frequencies = decoded.ordered_channels(order, count)?;
+    let (metadata, channels) = restore_channels(&decoded, &frequencies, &FftTransform)?;
+    write_wav(channels, &metadata)
+}
+
+/// Layout of raw (headerless) PCM audio: interleaved little-endian samples of the bit depth.
+///
+/// 8-bit samples are unsigned, 16 and 24-bit samples are signed integers and 32-bit samples are
+/// floats, as in .wav files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: usize,
+    pub bit_depth: usize,
+    pub channel_count: usize,
+}
+
+/// Compress a raw PCM file of the given format like [`compress_wav`], for later decompression
+/// using [`decompress_raw_pcm`] (or [`decompress_wav`]).
+pub fn compress_raw_pcm(
+    pcm_file: &Path,
+    output_file: &Path,
+    format: PcmFormat,
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(pcm_file)?;
+    let compressed = compress_raw_pcm_bytes(&input, format, freq_cutoff, quantization, progress)?;
+    fs::write(output_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress raw PCM audio in memory, see [`compress_raw_pcm`].
+pub fn compress_raw_pcm_bytes(
+    input: &[u8],
+    format: PcmFormat,
+    freq_cutoff: usize,
+    quantization: f32,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = read_raw_pcm(input, format)?;
+    compress_channels(
+        &metadata,
+        channels,
+        freq_cutoff,
+        quantization,
+        &[],
+        &FftTransform,
+        progress,
+    )
+}
+
+/// Compress the waveform of each channel as a single frame, weighting the quantization by the
+/// curve unless it is empty.
+fn compress_channels(
+    metadata: &WaveformMetadata,
+    channels: Channels,
+    freq_cutoff: usize,
+    quantization: f32,
+    curve: &[(f32, f32)],
+    transform: &dyn Transform,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let (encoded, _) = compress_channels_measured(
+        metadata,
+        channels,
+        freq_cutoff,
+        quantization,
+        curve,
+        transform,
+        progress,
+    )?;
+    Ok(encoded)
+}
+
+/// Compress channels like [`compress_channels`], also returning the [`cutoff_snr`] of all of
+/// the channels together.
+fn compress_channels_measured(
+    metadata: &WaveformMetadata,
+    channels: Channels,
+    freq_cutoff: usize,
+    quantization: f32,
+    curve: &[(f32, f32)],
+    transform: &dyn Transform,
+    mut progress: impl FnMut(f32),
+) -> Result<(Vec<u8>, f32), CompressionError> {
+    validate_quantization(quantization)?;
+    validate_freq_cutoff(freq_cutoff)?;
+    let original_size = channels[0].len();
+    let padded_size = original_size.next_power_of_two();
+    let bin_width = transform.bin_width(metadata.sample_rate, padded_size);
+    let mut bin_count = 0;
+    let (mut retained_energy, mut discarded_energy) = (0., 0.);
+    // Leave room for the final encoding step
+    let total = channels.len() as f32 + 1.;
+    let channels = channels
+        .into_iter()
+        .enumerate()
+        .map(|(c, mut waveform)| {
+            fft::round_sample_size_up(&mut waveform);
+            dump::dump(&format!("wav_time_domain_{c}"), &waveform[..])?;
+            let signal: Vec<Complex32> = waveform.into_iter().map(Complex32::from).collect();
+            let mut frame = transform.forward(&signal)?;
+            dump::dump(&format!("wav_frequency_domain_{c}"), &frame[..])?;
+            // Like WaveformMetadata::highest_bin, for the bins of the transform
+            bin_count = frame.len();
+            let highest_bin = f32::ceil(freq_cutoff as f32 / bin_width) as usize;
+            let (retained, discarded) = cutoff_energy(&frame, highest_bin);
+            retained_energy += retained;
+            discarded_energy += discarded;
+            frame.truncate(highest_bin);
+            dump::dump(&format!("wav_retained_{c}"), &frame[..])?;
+            progress((c + 1) as f32 / total);
+            Ok(vec![frame])
+        })
+        .collect::<Result<Vec<_>, CompressionError>>()?;
+    let encoded = serialize_single_frame(
+        metadata,
+        original_size,
+        &channels,
+        bin_count,
+        quantization,
+        curve,
+    )?;
+    progress(1.);
+    Ok((encoded, decibels(retained_energy, discarded_energy)))
+}
+
+/// Ratio (in dB) of the energy of the frequencies retained below a cutoff bin to the energy of
+/// those discarded above it, from the magnitudes of the frequencies: how much of the signal
+/// the cutoff alone loses, before quantization. Infinite if nothing is discarded.
+pub fn cutoff_snr(freq_domain: &[Complex32], highest_bin: usize) -> f32 {
+    let (retained, discarded) = cutoff_energy(freq_domain, highest_bin);
+    decibels(retained, discarded)
+}
+
+/// Energy of the frequencies below and above a cutoff bin.
+fn cutoff_energy(freq_domain: &[Complex32], highest_bin: usize) -> (f32, f32) {
+    let (retained, discarded) = freq_domain.split_at(highest_bin.min(freq_domain.len()));
+    let energy = |bins: &[Complex32]| bins.iter().map(Complex32::norm_sqr).sum::<f32>();
+    (energy(retained), energy(discarded))
+}
+
+fn decibels(signal: f32, noise: f32) -> f32 {
+    match noise > 0. {
+        true => 10. * (signal / noise).log10(),
+        false => f32::INFINITY,
+    }
+}
+
+/// Compress a .wav file like [`compress_wav`], choosing the highest frequency cutoff for which
+/// the compressed file fits in the target size.
+///
+/// The cutoff is found by a binary search up to the Nyquist frequency, so each channel is
+/// transformed once and at most log2(sample rate) cutoffs are serialized. The chosen cutoff is
+/// reported in the statistics. Returns an error if even the lowest cutoff does not fit.
+///
+/// Progress is reported as a fraction after each cutoff is tried, and 1.0 when done.
+pub fn compress_wav_to_size(
+    wav_file: &Path,
+    output_file: &Path,
+    target_bytes: u64,
+    mut progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let (metadata, channels) = read_wav(&input)?;
+    let original_size = channels[0].len();
+    let padded_size = original_size.next_power_of_two();
+    let spectra = channels
+        .into_iter()
+        .map(|mut waveform| {
+            fft::round_sample_size_up(&mut waveform);
+            Ok(vec![fft::rfft(&waveform)?])
+        })
+        .collect::<Result<Vec<_>, fft::FftError>>()?;
+    let serialize = |freq_cutoff: usize| {
+        let highest_bin = metadata.highest_bin(freq_cutoff, padded_size);
+        let retained: Vec<Vec<Vec<Complex32>>> = spectra
+            .iter()
+            .map(|frames| {
+                frames
+                    .iter()
+                    .map(|frame| frame[..highest_bin].to_vec())
+                    .collect()
+            })
+            .collect();
+        serialize_single_frame(
+            &metadata,
+            original_size,
+            &retained,
+            padded_size / 2 + 1,
+            1.,
+            &[],
+        )
+    };
+    let (mut low, mut high) = (1, metadata.sample_rate / 2);
+    let attempts = (usize::BITS - high.leading_zeros()) as f32;
+    let mut attempt = 0;
+    let mut best = None;
+    while low <= high {
+        let freq_cutoff = low + (high - low) / 2;
+        let encoded = serialize(freq_cutoff)?;
+        match encoded.len() as u64 <= target_bytes {
+            true => {
+                best = Some((freq_cutoff, encoded));
+                low = freq_cutoff + 1;
+            }
+            false => high = freq_cutoff - 1,
+        }
+        attempt += 1;
+        progress((attempt as f32 / attempts).min(0.99));
+    }
+    let Some((freq_cutoff, encoded)) = best else {
+        return Err(CompressionError::InvalidParameter(
+            "target size is too small for any frequency cutoff",
+        ));
+    };
+    fs::write(output_file, &encoded)?;
+    progress(1.);
+    Ok(
+        CompressionStats::new(input.len() as u64, encoded.len() as u64)
+            .with_freq_cutoff(freq_cutoff),
+    )
+}
+
+/// Compress a .wav file for later decompression using [`decompress_wav`], keeping the largest
+/// frequencies regardless of their position.
+///
+/// The fraction (greater than 0, up to 1) of frequencies with the largest magnitude are kept in
+/// each channel, along with their positions. Unlike a frequency cutoff, this keeps strong high
+/// frequencies and drops weak low frequencies.
+///
+/// Progress is reported as a fraction as each channel is transformed, and 1.0 when done.
+pub fn compress_wav_topk(
+    wav_file: &Path,
+    output_file: &Path,
+    fraction: f32,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let compressed = compress_wav_topk_bytes(&input, fraction, progress)?;
+    fs::write(output_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_topk`].
+pub fn compress_wav_topk_bytes(
+    input: &[u8],
+    fraction: f32,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    if !(fraction > 0. && fraction <= 1.) {
+        return Err(CompressionError::InvalidParameter(
+            "fraction must be greater than 0 and no greater than 1",
+        ));
+    }
+    let (metadata, channels) = read_wav(input)?;
+    let original_size = channels[0].len();
+    let bin_count = original_size.next_power_of_two() / 2 + 1;
+    let kept_count = ((bin_count as f32 * fraction).ceil() as usize).min(bin_count);
+    // Leave room for the final encoding step
+    let total = channels.len() as f32 + 1.;
+    let mut masks = Vec::with_capacity(channels.len() * bin_count);
+    let channels = channels
+        .into_iter()
+        .enumerate()
+        .map(|(c, mut waveform)| {
+            fft::round_sample_size_up(&mut waveform);
+            let freq_domain = fft::rfft(&waveform)?;
+            let mut by_magnitude: Vec<usize> = (0..bin_count).collect();
+            by_magnitude.sort_by(|a, b| freq_domain[*b].norm().total_cmp(&freq_domain[*a].norm()));
+            let mut mask = vec![0; bin_count];
+            by_magnitude[..kept_count]
+                .iter()
+                .for_each(|bin| mask[*bin] = 1);
+            let kept = (0..bin_count)
+                .filter(|bin| mask[*bin] == 1)
+                .map(|bin| freq_domain[bin])
+                .collect();
+            masks.extend(mask);
+            progress((c + 1) as f32 / total);
+            Ok(vec![kept])
+        })
+        .collect::<Result<Vec<_>, fft::FftError>>()?;
+    let (channels, quantization_step) = quantize(&channels, 1.);
+    let mut compressed = CompressedData::new(
+        metadata.sample_rate,
+        original_size,
+        metadata.bit_rate,
+        None,
+        &channels,
+        0,
+        quantization_step,
+    )?;
+    compressed.kept_bins = Some(entropy::encode(&masks)?);
+    let encoded = serialize_compressed(&compressed)?;
+    progress(1.);
+    Ok(encoded)
+}
+
+/// Compress a .wav file in overlapping frames for later decompression using [`decompress_wav`].
+///
+/// Each frame of `frame_size` samples overlaps the previous by half and is transformed
+/// separately, so the transform size is bounded by the frame size rather than the waveform.
+/// The frames are tapered by a window before the transform and again after the inverse, and
+/// the windows overlap-add to a constant, so the frame boundaries leave no seams.
+///
+/// Progress is reported as a fraction as each frame is transformed, and 1.0 when done.
+pub fn compress_wav_blocked(
+    wav_file: &Path,
+    output_file: &Path,
+    freq_cutoff: usize,
+    quantization: f32,
+    frame_size: usize,
+    progress: impl FnMut(f32),
+) -> Result<CompressionStats, CompressionError> {
+    let input = fs::read(wav_file)?;
+    let compressed =
+        compress_wav_blocked_bytes(&input, freq_cutoff, quantization, frame_size, progress)?;
+    fs::write(output_file, &compressed)?;
+    Ok(CompressionStats::new(
+        input.len() as u64,
+        compressed.len() as u64,
+    ))
+}
+
+/// Compress the contents of a .wav file in memory, see [`compress_wav_blocked`].
+pub fn compress_wav_blocked_bytes(
+    input: &[u8],
+    freq_cutoff: usize,
+    quantization: f32,
+    frame_size: usize,
+    progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = read_wav(input)?;
+    compress_frames(
+        &metadata,
+        &channels,
+        freq_cutoff,
+        quantization,
+        frame_size,
+        progress,
+    )
+}
+
+/// Compress channels in overlapping frames, see [`compress_wav_blocked`].
+fn compress_frames(
+    metadata: &WaveformMetadata,
+    channels: &Channels,
+    freq_cutoff: usize,
+    quantization: f32,
+    frame_size: usize,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<u8>, CompressionError> {
+    validate_quantization(quantization)?;
+    validate_freq_cutoff(freq_cutoff)?;
+    if frame_size < 2 {
+        return Err(CompressionError::InvalidParameter(
+            "frame size must be at least 2",
+        ));
+    }
+    let original_size = channels[0].len();
+    let highest_bin = metadata.highest_bin(freq_cutoff, frame_size);
+    let window = frame_window(frame_size);
+    // Leave room for the final encoding step
+    let total = (channels.len() * frame_starts(original_size, frame_size).count()) as f32 + 1.;
+    let mut done = 0;
+    let channels = channels
+        .iter()
+        .map(|waveform| {
+            // Pad by half a frame so the first samples are covered by two frames as well
+            let mut padded = vec![0.; frame_size / 2];
+            padded.extend_from_slice(waveform);
+            frame_starts(waveform.len(), frame_size)
+                .map(|start| {
+                    let end = (start + frame_size).min(padded.len());
+                    let mut frame = padded[start..end].to_vec();
+                    frame.resize(frame_size, 0.);
+                    frame.iter_mut().zip(&window).for_each(|(x, w)| *x *= w);
+                    let frame = compress_frame(&frame, highest_bin);
+                    done += 1;
+                    progress(done as f32 / total);
+                    frame
+                })
+                .collect()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (channels, quantization_step) = quantize(&channels, quantization);
     let compressed = CompressedData::new(
         metadata.sample_rate,
         original_size,
         metadata.bit_rate,
-        frequencies,
-        cutoff_zeros,
-    );
-    let encoded = bincode::serialize(&compressed)?;
-    let mut file = File::create(output_file)?;
-    file.write_all(&encoded)?;
+        Some(frame_size),
+        &channels,
+        frame_size / 2 + 1 - highest_bin,
+        quantization_step,
+    )?;
+    let encoded = serialize_compressed(&compressed)?;
+    progress(1.);
+    Ok(encoded)
+}
+
+/// Concatenate .wav files compressed by [`compress_wav_blocked`] into one compressed file,
+/// without decompressing them: it decompresses to their waveforms end to end.
+///
+/// The frames of each file are kept as they are, along with the quantization step and length
+/// of its waveform, so each decompresses exactly as it would alone. Returns an error unless
+/// the files have the same sample rate, bit depth, channel count, frame size and frequency
+/// cutoff, or if any was compressed otherwise than in frames.
+pub fn concat_compressed(inputs: &[&Path], output: &Path) -> Result<(), CompressionError> {
+    let inputs = inputs.iter().map(fs::read).collect::<Result<Vec<_>, _>>()?;
+    let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+    fs::write(output, concat_compressed_bytes(&inputs)?)?;
+    Ok(())
+}
+
+/// Concatenate the contents of compressed .wav files in memory, see [`concat_compressed`].
+pub fn concat_compressed_bytes(inputs: &[&[u8]]) -> Result<Vec<u8>, CompressionError> {
+    let decoded = inputs
+        .iter()
+        .map(|input| deserialize_compressed(input))
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = decoded.first() else {
+        return Err(CompressionError::EmptyInput);
+    };
+    let mut segments = Vec::new();
+    let mut channels = vec![Vec::new(); first.channel_count];
+    for other in &decoded {
+        if (other.sample_rate, other.bit_rate) != (first.sample_rate, first.bit_rate) {
+            return Err(CompressionError::InvalidParameter(
+                "concatenated files must have the same sample rate and bit depth",
+            ));
+        }
+        if other.channel_count != first.channel_count {
+            return Err(CompressionError::InvalidParameter(
+                "concatenated files must have the same number of channels",
+            ));
+        }
+        let blocked = other.frame_size.is_some()
+            && other.kept_bins.is_none()
+            && other.residual.is_none()
+            && other.order.is_none()
+            && other.weights.is_none();
+        if !blocked {
+            return Err(CompressionError::InvalidParameter(
+                "only files compressed in frames can be concatenated",
+            ));
+        }
+        if (other.frame_size, other.cutoff_zeros) != (first.frame_size, first.cutoff_zeros) {
+            return Err(CompressionError::InvalidParameter(
+                "concatenated files must have the same frame size and frequency cutoff",
+            ));
+        }
+        segments.extend(
+            other
+                .segments()
+                .into_iter()
+                .map(|(original_size, scale)| Segment {
+                    original_size,
+                    quantization_step: other.quantization_step * scale,
+                }),
+        );
+        for (frames, other_frames) in channels.iter_mut().zip(other.channels()?) {
+            frames.extend(other_frames);
+        }
+    }
+    let mut compressed = CompressedData::new(
+        first.sample_rate,
+        segments.iter().map(|segment| segment.original_size).sum(),
+        first.bit_rate,
+        first.frame_size,
+        &channels,
+        first.cutoff_zeros,
+        first.quantization_step,
+    )?;
+    compressed.segments = Some(segments);
+    serialize_compressed(&compressed)
+}
+
+/// Decompress a .wav file from [`compress_wav`] or [`compress_wav_blocked`].
+pub fn decompress_wav(compressed_file: &Path, output_file: &Path) -> Result<(), CompressionError> {
+    let decompressed = decompress_wav_bytes(&fs::read(compressed_file)?)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .wav file in memory, see [`decompress_wav`].
+pub fn decompress_wav_bytes(compressed: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = decompress_channels(compressed, &FftTransform)?;
+    write_wav(channels, &metadata)
+}
+
+/// Decompress a .wav file from [`compress_wav_with_transform`], by the inverse of the transform
+/// it was compressed with. Returns an error if the file has a different number of coefficients
+/// than the transform, though not every other transform is detected.
+pub fn decompress_wav_with_transform(
+    compressed_file: &Path,
+    output_file: &Path,
+    transform: &dyn Transform,
+) -> Result<(), CompressionError> {
+    let decompressed = decompress_wav_with_transform_bytes(&fs::read(compressed_file)?, transform)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .wav file in memory, see
+/// [`decompress_wav_with_transform`].
+pub fn decompress_wav_with_transform_bytes(
+    compressed: &[u8],
+    transform: &dyn Transform,
+) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = decompress_channels(compressed, transform)?;
+    write_wav(channels, &metadata)
+}
+
+/// Decompress a .wav file like [`decompress_wav`], writing it as each frame is restored rather
+/// than restoring the whole waveform first.
+///
+/// Besides the compressed data, memory is bounded by a frame of each channel, so long
+/// recordings compressed by [`compress_wav_blocked`] can be decompressed without holding their
+/// waveform. Files transformed as a single frame are restored whole. The output is identical
+/// to [`decompress_wav`].
+pub fn decompress_wav_streaming(
+    compressed_file: &Path,
+    output_file: &Path,
+) -> Result<(), CompressionError> {
+    let compressed = fs::read(compressed_file)?;
+    let mut writer = BufWriter::new(File::create(output_file)?);
+    decompress_wav_to_writer(&compressed, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .wav file to a writer, see
+/// [`decompress_wav_streaming`].
+pub fn decompress_wav_to_writer(
+    compressed: &[u8],
+    writer: &mut impl Write,
+) -> Result<(), CompressionError> {
+    let decoded = deserialize_compressed(compressed)?;
+    let (Some(frame_size), None, None, None) = (
+        decoded.frame_size,
+        &decoded.kept_bins,
+        &decoded.residual,
+        &decoded.segments,
+    ) else {
+        writer.write_all(&decompress_wav_bytes(compressed)?)?;
+        return Ok(());
+    };
+    let metadata = WaveformMetadata::new(decoded.sample_rate, decoded.bit_rate);
+    let data_size = decoded.original_size * decoded.channel_count * decoded.bit_rate / 8;
+    write_wav_header(writer, &metadata, decoded.channel_count, data_size)?;
+    let hop = frame_size / 2;
+    let channels = decoded.channels()?;
+    let steps = decoded.bin_steps()?;
+    let mut overlap_adds: Vec<OverlapAdd> = channels
+        .iter()
+        .map(|_| OverlapAdd::new(frame_size))
+        .collect();
+    for (f, start) in frame_starts(decoded.original_size, frame_size).enumerate() {
+        // Positions of the finished samples in the original (unpadded) waveform
+        let first = start.max(hop) - hop;
+        let last = (start + hop).min(decoded.original_size + hop) - hop;
+        let chunk = channels
+            .iter()
+            .zip(overlap_adds.iter_mut())
+            .map(|(frames, overlap_add)| {
+                let finished = overlap_add.push(&decompress_frame(
+                    &frames[f],
+                    &steps,
+                    &decoded,
+                    &FftTransform,
+                )?);
+                let offset = first + hop - start;
+                Ok(finished[offset..offset + last - first].to_vec())
+            })
+            .collect::<Result<Channels, fft::FftError>>()?;
+        writer.write_all(&write_raw_pcm(chunk, &metadata)?)?;
+    }
+    // Chunks of an odd size are padded to an even size
+    if data_size % 2 == 1 {
+        writer.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+/// Decompress a file from [`compress_raw_pcm`] (or any compressed .wav file) to raw PCM audio,
+/// in the format of the original.
+pub fn decompress_raw_pcm(
+    compressed_file: &Path,
+    output_file: &Path,
+) -> Result<(), CompressionError> {
+    let decompressed = decompress_raw_pcm_bytes(&fs::read(compressed_file)?)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress to raw PCM audio in memory, see [`decompress_raw_pcm`].
+pub fn decompress_raw_pcm_bytes(compressed: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = decompress_channels(compressed, &FftTransform)?;
+    write_raw_pcm(channels, &metadata)
+}
+
+/// Decompress a .wav file like [`decompress_wav`], adding triangular dither before the samples
+/// are rounded to the bit depth.
+///
+/// Dither turns the distortion of a low bit depth into uncorrelated noise, which is less
+/// audible on quiet passages. The noise is reproducible for the same seed.
+pub fn decompress_wav_dithered(
+    compressed_file: &Path,
+    output_file: &Path,
+    seed: u64,
+) -> Result<(), CompressionError> {
+    let decompressed = decompress_wav_dithered_bytes(&fs::read(compressed_file)?, seed)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .wav file in memory, see
+/// [`decompress_wav_dithered`].
+pub fn decompress_wav_dithered_bytes(
+    compressed: &[u8],
+    seed: u64,
+) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = decompress_channels(compressed, &FftTransform)?;
+    write_wav_dithered(channels, &metadata, Some(seed))
+}
+
+/// Decompress a .wav file like [`decompress_wav`] to an .mp3 file (with the `mp3` feature), at
+/// a constant bit rate of 192 kbps.
+///
+/// Only mono and stereo audio can be encoded.
+#[cfg(feature = "mp3")]
+pub fn decompress_mp3(compressed_file: &Path, output_file: &Path) -> Result<(), CompressionError> {
+    let decompressed = decompress_mp3_bytes(&fs::read(compressed_file)?)?;
+    fs::write(output_file, decompressed)?;
+    Ok(())
+}
+
+/// Decompress the contents of a compressed .wav file to an .mp3 file in memory, see
+/// [`decompress_mp3`].
+#[cfg(feature = "mp3")]
+pub fn decompress_mp3_bytes(compressed: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, channels) = decompress_channels(compressed, &FftTransform)?;
+    write_mp3(channels, &metadata)
+}
+
+/// Downmix a .wav file to mono by averaging its channels, keeping the sample rate and bit
+/// depth. A mono file is rewritten unchanged.
+pub fn downmix_wav(wav_file: &Path, output_file: &Path) -> Result<(), CompressionError> {
+    let (metadata, waveform) = load_wav_file_mono(wav_file)?;
+    fs::write(output_file, write_wav(vec![waveform], &metadata)?)?;
     Ok(())
 }
 
-/// Decompress a .wav file from [`compress_wav`].
-pub fn decompress_wav(
-    compressed_file: &PathBuf,
-    output_file: &PathBuf,
-) -> Result<(), Box<dyn Error>> {
-    let mut encoded: Vec<u8> = Vec::new();
-    let mut file = File::open(compressed_file)?;
-    file.read_to_end(&mut encoded)?;
-    let decoded: CompressedData = bincode::deserialize(&encoded)?;
-    let mut freq_domain: Vec<Complex32> = decoded
-        .frequencies
-        .iter()
-        .map(|(r, i)| Complex32::new(r.clone(), i.clone()))
+/// Downmix the contents of a .wav file in memory, see [`downmix_wav`].
+pub fn downmix_wav_bytes(input: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let (metadata, waveform) = read_wav_mono(input)?;
+    write_wav(vec![waveform], &metadata)
+}
+
+/// Resample a .wav file to another sample rate (in Hz), keeping its bit depth and channels,
+/// see [`filter::resample`].
+pub fn resample_wav(
+    wav_file: &Path,
+    output_file: &Path,
+    sample_rate: usize,
+) -> Result<(), CompressionError> {
+    let resampled = resample_wav_bytes(&fs::read(wav_file)?, sample_rate)?;
+    fs::write(output_file, resampled)?;
+    Ok(())
+}
+
+/// Resample the contents of a .wav file in memory, see [`resample_wav`].
+pub fn resample_wav_bytes(input: &[u8], sample_rate: usize) -> Result<Vec<u8>, CompressionError> {
+    if sample_rate == 0 {
+        return Err(CompressionError::InvalidParameter(
+            "sample rate must be greater than 0",
+        ));
+    }
+    let (metadata, channels) = read_wav(input)?;
+    let channels = channels
+        .iter()
+        .map(|channel| filter::resample(channel, metadata.sample_rate, sample_rate))
+        .collect::<Result<Channels, fft::FftError>>()?;
+    write_wav(
+        channels,
+        &WaveformMetadata::new(sample_rate, metadata.bit_rate),
+    )
+}
+
+/// Largest absolute difference between the samples of two .wav files, such as an original and
+/// its decompressed copy. Returns an error if they differ in channels or length.
+pub fn max_sample_error(original: &[u8], decompressed: &[u8]) -> Result<f32, CompressionError> {
+    let (_, original) = read_wav(original)?;
+    let (_, decompressed) = read_wav(decompressed)?;
+    let same_shape = original.len() == decompressed.len()
+        && original
+            .iter()
+            .zip(decompressed.iter())
+            .all(|(a, b)| a.len() == b.len());
+    if !same_shape {
+        return Err(CompressionError::InvalidParameter(
+            "waveforms differ in channels or length",
+        ));
+    }
+    Ok(original
+        .iter()
+        .flatten()
+        .zip(decompressed.iter().flatten())
+        .map(|(a, b)| (a - b).abs())
+        .fold(0., f32::max))
+}
+
+/// Properties of an audio file compressed by [`compress_wav`] or its variants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedInfo {
+    pub sample_rate: usize,
+    pub bit_rate: usize,
+    pub channel_count: usize,
+    /// Number of samples in each channel of the original
+    pub original_size: usize,
+    /// Size of the overlapping frames, or None if each channel was transformed as a single frame
+    pub frame_size: Option<usize>,
+    /// Number of retained (complex) frequencies in all frames of all channels
+    pub coefficient_count: usize,
+    /// Whether the original samples are restored exactly, see [`compress_wav_lossless`]
+    pub lossless: bool,
+}
+
+/// Read the properties of a compressed audio file, without decompressing it.
+pub fn read_wav_metadata(compressed_file: &Path) -> Result<CompressedInfo, CompressionError> {
+    read_wav_metadata_bytes(&fs::read(compressed_file)?)
+}
+
+/// Read the properties of a compressed audio file in memory, see [`read_wav_metadata`].
+pub fn read_wav_metadata_bytes(compressed: &[u8]) -> Result<CompressedInfo, CompressionError> {
+    let decoded = deserialize_compressed(compressed)?;
+    Ok(CompressedInfo {
+        sample_rate: decoded.sample_rate,
+        bit_rate: decoded.bit_rate,
+        channel_count: decoded.channel_count,
+        original_size: decoded.original_size,
+        frame_size: decoded.frame_size,
+        coefficient_count: decoded.coefficient_count()?,
+        lossless: decoded.residual.is_some(),
+    })
+}
+
+/// Restore the waveform of each channel by the inverse of the transform it was compressed with.
+fn decompress_channels(
+    compressed: &[u8],
+    transform: &dyn Transform,
+) -> Result<(WaveformMetadata, Channels), CompressionError> {
+    let decoded = deserialize_compressed(compressed)?;
+    restore_channels(&decoded, &decoded.scattered_channels()?, transform)
+}
+
+/// Restore the waveform of each channel from the (scattered) frequencies of its frames.
+fn restore_channels(
+    decoded: &CompressedData,
+    frequencies: &[Frames],
+    transform: &dyn Transform,
+) -> Result<(WaveformMetadata, Channels), CompressionError> {
+    let steps = decoded.bin_steps()?;
+    let channels = frequencies
+        .iter()
+        .enumerate()
+        .map(|(c, frames)| {
+            if dump::dumping() {
+                let dequantized: Vec<Vec<Complex32>> = frames
+                    .iter()
+                    .zip(decoded.frame_scales())
+                    .map(|(frame, scale)| {
+                        frame
+                            .iter()
+                            .zip(&steps)
+                            .map(|((r, i), step)| {
+                                Complex32::new(*r as f32, *i as f32) * step * scale
+                            })
+                            .collect()
+                    })
+                    .collect();
+                dump::dump(&format!("wav_decoded_{c}"), &dequantized[..])?;
+            }
+            let waveform = decompress_channel(frames, &steps, decoded, transform)?;
+            dump::dump(&format!("wav_restored_{c}"), &waveform[..])?;
+            Ok(waveform)
+        })
+        .collect::<Result<Vec<_>, CompressionError>>()?;
+    let metadata = WaveformMetadata::new(decoded.sample_rate, decoded.bit_rate);
+    let Some(residual) = &decoded.residual else {
+        return Ok((metadata, channels));
+    };
+    // Correct the samples of the bit depth, which the original had
+    let restored = sample_values(interleave(&channels), decoded.bit_rate)?;
+    let residual = entropy::decode_wide(residual, restored.len())?;
+    if residual.len() != restored.len() {
+        return Err(EntropyError::Malformed.into());
+    }
+    let values: Vec<i32> = restored
+        .iter()
+        .zip(residual)
+        .map(|(restored, residual)| restored.wrapping_add(residual))
+        .collect();
+    let samples = from_sample_values(&values, decoded.bit_rate);
+    Ok((metadata, deinterleave(&samples, decoded.channel_count)))
+}
+
+/// Difference between the magnitudes of the retained frequencies of two compressed audio files
+/// (such as two compression levels of the same recording), bin by bin: positive where the first
+/// file keeps more.
+///
+/// Magnitudes are averaged over the frames and channels of each file, and only the bins that
+/// both files retain are compared. Returns an error if the files were transformed at different
+/// sizes or sample rates, whose bins are different frequencies.
+pub fn spectral_diff(a: &Path, b: &Path) -> Result<Vec<f32>, CompressionError> {
+    spectral_diff_bytes(&fs::read(a)?, &fs::read(b)?)
+}
+
+/// Compare the retained frequencies of two compressed audio files in memory, see
+/// [`spectral_diff`].
+pub fn spectral_diff_bytes(a: &[u8], b: &[u8]) -> Result<Vec<f32>, CompressionError> {
+    Ok(compare_spectra(a, b)?.1)
+}
+
+/// Plot the [`spectral_diff`] of two compressed audio files to an html page, written to the
+/// output directory as `<stem>_diff.html` after the first file. Returns the path of the page.
+pub fn plot_spectral_diff(
+    a: &Path,
+    b: &Path,
+    output_dir: &Path,
+    config: PlotConfig,
+) -> Result<PathBuf, CompressionError> {
+    let (decoded, diff) = compare_spectra(&fs::read(a)?, &fs::read(b)?)?;
+    let stem = a.file_stem().unwrap_or_default().to_string_lossy();
+    let file_path = output_dir.join(format!("{stem}_diff.html"));
+    let (freq_legend, diff): (Vec<f32>, Vec<f32>) =
+        fft::labeled_bins(&diff, decoded.sample_rate, decoded.transform_size()).unzip();
+    let trace = Scatter::new(freq_legend, diff)
+        .mode(Mode::Lines)
+        .name("")
+        .line(Line::new().color(NamedColor::IndianRed));
+    let title = format!("{} - {}", a.to_string_lossy(), b.to_string_lossy());
+    let layout = Layout::new()
+        .title(Title::new(&title))
+        .x_axis(Axis::new().title(Title::new("Frequency (Hz)")))
+        .y_axis(Axis::new().title(Title::new("Amplitude difference")))
+        .show_legend(false);
+    let mut plot = Plot::new();
+    plot.add_trace(trace);
+    plot.set_layout(config.apply(layout, 1900, 600));
+    plot.write_html(&file_path);
+    Ok(file_path)
+}
+
+/// Decode the first of two compressed audio files, and the difference of their spectra.
+fn compare_spectra(a: &[u8], b: &[u8]) -> Result<(CompressedData, Vec<f32>), CompressionError> {
+    let (a, b) = (deserialize_compressed(a)?, deserialize_compressed(b)?);
+    if (a.sample_rate, a.transform_size()) != (b.sample_rate, b.transform_size()) {
+        return Err(CompressionError::InvalidParameter(
+            "files must be transformed at the same size and sample rate",
+        ));
+    }
+    // Zip stops at the shorter spectrum
+    let diff = a
+        .spectrum()?
+        .iter()
+        .zip(b.spectrum()?)
+        .map(|(a, b)| a - b)
+        .collect();
+    Ok((a, diff))
+}
+
+/// Results of [`analyze_waveform`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaveformAnalysis {
+    /// Path of the html page with the plots
+    pub path: PathBuf,
+    /// Strongest frequencies of the first channel as (frequency in Hz, amplitude) pairs,
+    /// strongest first, see [`fft::dominant_frequencies`]
+    pub dominant_frequencies: Vec<(f32, f32)>,
+}
+
+/// Number of dominant frequencies reported by [`analyze_waveform`].
+const DOMINANT_FREQUENCY_COUNT: usize = 5;
+
+/// Produce an html page with interactive plots of the time domain and frequency domain.
+///
+/// The window function is applied to the waveform before transforming to the frequency domain,
+/// and the windowed waveform is extended to the transform size by the pad mode. The page is
+/// written to the output directory as `<stem>_analysis.html`, and its path is returned with the
+/// dominant frequencies.
+pub fn analyze_waveform(
+    wav_file: &Path,
+    output_dir: &Path,
+    window: WindowKind,
+    pad: fft::PadMode,
+    config: PlotConfig,
+) -> Result<WaveformAnalysis, CompressionError> {
+    analyze(wav_file, None, output_dir, window, pad, config)
+}
+
+/// Analyze a .wav file like [`analyze_waveform`], overlaying the spectrum of a compressed file
+/// (typically of the same recording) as it decompresses on the spectrum of the original, to
+/// show what the compression discarded.
+///
+/// The first channel of the decompressed audio is cut or extended with silence to the length
+/// of the original, and windowed and padded the same way, so the bins of both spectra are the
+/// same frequencies. Returns an error if the sample rates differ.
+pub fn analyze_waveform_overlay(
+    wav_file: &Path,
+    compressed_file: &Path,
+    output_dir: &Path,
+    window: WindowKind,
+    pad: fft::PadMode,
+    config: PlotConfig,
+) -> Result<WaveformAnalysis, CompressionError> {
+    analyze(
+        wav_file,
+        Some(compressed_file),
+        output_dir,
+        window,
+        pad,
+        config,
+    )
+}
+
+fn analyze(
+    wav_file: &Path,
+    compressed_file: Option<&Path>,
+    output_dir: &Path,
+    window: WindowKind,
+    pad: fft::PadMode,
+    config: PlotConfig,
+) -> Result<WaveformAnalysis, CompressionError> {
+    let file_path = plotting::analysis_path(wav_file, output_dir);
+    let (metadata, channels) = load_wav_file(wav_file)?;
+    // Only the first channel is analyzed
+    let mut waveform = channels.into_iter().next().unwrap_or_default();
+    let spectrogram = fft::stft(&waveform, SPECTROGRAM_FRAME_SIZE, SPECTROGRAM_HOP, window)?;
+    let spectrum = |waveform: Vec<f32>| windowed_spectrum(waveform, window, pad);
+    let freq_bins = spectrum(waveform.clone())?;
+    let dominant_frequencies =
+        fft::dominant_frequencies(&freq_bins, metadata.sample_rate, DOMINANT_FREQUENCY_COUNT);
+    let mut spectra = vec![freq_bins];
+    if let Some(compressed_file) = compressed_file {
+        let (restored_metadata, restored) =
+            decompress_channels(&fs::read(compressed_file)?, &FftTransform)?;
+        if restored_metadata.sample_rate != metadata.sample_rate {
+            return Err(CompressionError::InvalidParameter(
+                "files must have the same sample rate",
+            ));
+        }
+        let mut restored = restored.into_iter().next().unwrap_or_default();
+        restored.resize(waveform.len(), 0.);
+        spectra.push(spectrum(restored)?);
+    }
+    fft::round_sample_size_up(&mut waveform);
+    plot(
+        waveform.clone(),
+        spectra,
+        spectrogram,
+        &metadata,
+        &file_path,
+        &wav_file.to_string_lossy(),
+        config,
+    );
+    Ok(WaveformAnalysis {
+        path: file_path,
+        dominant_frequencies,
+    })
+}
+
+/// Frequency bins (see [`fft::frequency_bins`]) of a waveform, windowed and then padded up to
+/// the next power of two.
+fn windowed_spectrum(
+    mut waveform: Vec<f32>,
+    window: WindowKind,
+    pad: fft::PadMode,
+) -> Result<Vec<f32>, fft::FftError> {
+    window::apply_window(&mut waveform, window);
+    fft::round_sample_size_up_with(&mut waveform, pad);
+    let time_domain = fft::convert_sample(&waveform);
+    fft::fft(&time_domain).map(|transformed| fft::frequency_bins(&transformed))
+}
+
+/// Plot the spectra of several .wav files in one report, a row per file, to compare them.
+///
+/// The first channel of each file is windowed and padded like in [`analyze_waveform`]. The rows
+/// share a frequency axis in Hz, so files of different sample rates line up. The report is
+/// named after the first file, like `song_comparison.html`. Returns an error if there are no
+/// files.
+pub fn analyze_waveforms(
+    wav_files: &[PathBuf],
+    output_dir: &Path,
+    window: WindowKind,
+    pad: fft::PadMode,
+    config: PlotConfig,
+) -> Result<PathBuf, CompressionError> {
+    let first = wav_files.first().ok_or(CompressionError::EmptyInput)?;
+    let stem = first.file_stem().unwrap_or_default().to_string_lossy();
+    let file_path = output_dir.join(format!("{stem}_comparison.html"));
+    let mut plot = Plot::new();
+    for (row, wav_file) in wav_files.iter().enumerate() {
+        let (metadata, channels) = load_wav_file(wav_file)?;
+        let waveform = channels.into_iter().next().unwrap_or_default();
+        let sample_size = waveform.len().next_power_of_two();
+        let freq_bins = windowed_spectrum(waveform, window, pad)?;
+        let (freq_legend, freq_bins): (Vec<f32>, Vec<f32>) =
+            fft::labeled_bins(&freq_bins, metadata.sample_rate, sample_size).unzip();
+        let name = wav_file.file_name().unwrap_or_default().to_string_lossy();
+        plot.add_trace(
+            Scatter::new(freq_legend, freq_bins)
+                .mode(Mode::Lines)
+                .name(&name)
+                .x_axis("x1")
+                .y_axis(format!("y{}", row + 1)),
+        );
+    }
+    let layout = Layout::new()
+        .grid(
+            LayoutGrid::new()
+                .rows(wav_files.len())
+                .columns(1)
+                .pattern(GridPattern::Coupled)
+                .row_order(RowOrder::TopToBottom),
+        )
+        .title(Title::new("Spectra"))
+        .x_axis(Axis::new().title(Title::new("Frequency (Hz)")));
+    plot.set_layout(config.apply(layout, 1900, 300 * wav_files.len()));
+    plot.write_html(&file_path);
+    Ok(file_path)
+}
+
+/// Number of samples in each column of the spectrogram.
+const SPECTROGRAM_FRAME_SIZE: usize = 1024;
+/// Number of samples between the columns of the spectrogram.
+const SPECTROGRAM_HOP: usize = SPECTROGRAM_FRAME_SIZE / 2;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct WaveformMetadata {
+    pub sample_rate: usize,
+    pub bit_rate: usize,
+}
+
+impl WaveformMetadata {
+    pub fn new(sample_rate: usize, bit_rate: usize) -> WaveformMetadata {
+        WaveformMetadata {
+            sample_rate,
+            bit_rate,
+        }
+    }
+
+    pub fn freq_resolution(&self, sample_size: usize) -> f32 {
+        self.sample_rate as f32 / sample_size as f32
+    }
+
+    /// Number of (non-redundant) frequency bins to keep for a frequency cutoff.
+    pub fn highest_bin(&self, freq_cutoff: usize, sample_size: usize) -> usize {
+        let highest_bin = f32::ceil(freq_cutoff as f32 / self.freq_resolution(sample_size));
+        (highest_bin as usize).min(sample_size / 2 + 1)
+    }
+}
+
+/// Waveform of each channel.
+type Channels = Vec<Vec<f32>>;
+
+/// Retained (quantized) frequencies of each frame in a channel.
+type Frames = Vec<Vec<(i16, i16)>>;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CompressedData {
+    sample_rate: usize,
+    original_size: usize,
+    bit_rate: usize,
+    /// Size of the overlapping frames, or None if the waveform was transformed as a single frame
+    frame_size: Option<usize>,
+    channel_count: usize,
+    frame_count: usize,
+    /// Entropy coded frequencies of all frames in all channels
+    frequencies: Vec<u8>,
+    cutoff_zeros: usize,
+    /// Value of a single quantized unit of the frequencies
+    quantization_step: f32,
+    /// Entropy coded mask (1 = kept) of the frequency bins of each channel, if the kept
+    /// frequencies were chosen by magnitude rather than by a cutoff
+    kept_bins: Option<Vec<u8>>,
+    /// Entropy coded difference between the original and the decompressed (interleaved) sample
+    /// values, see [`sample_values`], if compressed losslessly
+    residual: Option<Vec<u8>>,
+    /// Entropy coded position (channel * retained bins + bin) of each frequency, if the
+    /// frequencies are stored by descending magnitude, see [`compress_wav_progressive`]
+    order: Option<Vec<u8>>,
+    /// Entropy coded weight of the quantization step of each retained bin in units of
+    /// [`WEIGHT_UNIT`], as differences from the previous bin, if weighted by a curve, see
+    /// [`compress_wav_weighted`]
+    weights: Option<Vec<u8>>,
+    /// Each of the waveforms concatenated in frames, if concatenated, see [`concat_compressed`]
+    segments: Option<Vec<Segment>>,
+    /// Number of coefficients of the transform of each frame, if not the `n / 2 + 1` of the
+    /// FFT, see [`compress_wav_with_transform`]
+    bin_count: Option<usize>,
+}
+
+/// A waveform of a concatenated compressed file, whose frames follow those of the previous one.
+#[derive(Serialize, Deserialize, Debug)]
+struct Segment {
+    original_size: usize,
+    quantization_step: f32,
+}
+
+impl CompressedData {
+    fn new(
+        sample_rate: usize,
+        original_size: usize,
+        bit_rate: usize,
+        frame_size: Option<usize>,
+        channels: &[Frames],
+        cutoff_zeros: usize,
+        quantization_step: f32,
+    ) -> Result<CompressedData, EntropyError> {
+        let values: Vec<i16> = channels
+            .iter()
+            .flatten()
+            .flatten()
+            .flat_map(|(re, im)| [*re, *im])
+            .collect();
+        Ok(CompressedData {
+            sample_rate,
+            original_size,
+            bit_rate,
+            frame_size,
+            channel_count: channels.len(),
+            frame_count: channels.first().map_or(0, Vec::len),
+            frequencies: entropy::encode(&values)?,
+            cutoff_zeros,
+            quantization_step,
+            kept_bins: None,
+            residual: None,
+            order: None,
+            weights: None,
+            segments: None,
+            bin_count: None,
+        })
+    }
+
+    /// Number of samples in each transformed frame.
+    fn transform_size(&self) -> usize {
+        self.frame_size
+            .unwrap_or(self.original_size.next_power_of_two())
+    }
+
+    /// Number of frequency bins of each transformed frame, retained or not.
+    fn bin_count(&self) -> usize {
+        self.bin_count.unwrap_or(self.transform_size() / 2 + 1)
+    }
+
+    /// Number of frequency bins of all frames of all channels, retained or not: an upper bound
+    /// of the decoded frequencies (twice as many values) and masks.
+    fn total_bin_count(&self) -> usize {
+        self.bin_count()
+            .saturating_mul(self.frame_count)
+            .saturating_mul(self.channel_count)
+    }
+
+    /// Quantization step of each frequency bin of a frame: the same for all bins unless the
+    /// quantization was weighted.
+    fn bin_steps(&self) -> Result<Vec<f32>, EntropyError> {
+        let Some(weights) = &self.weights else {
+            return Ok(vec![self.quantization_step; self.bin_count()]);
+        };
+        let bin_count = self.bin_count().saturating_sub(self.cutoff_zeros);
+        let differences = entropy::decode(weights, bin_count)?;
+        if differences.len() != bin_count {
+            return Err(EntropyError::Malformed);
+        }
+        Ok(differences
+            .iter()
+            .scan(0i16, |weight, difference| {
+                *weight = weight.wrapping_add(*difference);
+                Some(self.quantization_step * *weight as f32 / WEIGHT_UNIT)
+            })
+            .collect())
+    }
+
+    /// Number of samples of each concatenated waveform (see [`concat_compressed`]) with the
+    /// scale of its quantization step, or the whole waveform if not concatenated.
+    fn segments(&self) -> Vec<(usize, f32)> {
+        match &self.segments {
+            Some(segments) => segments
+                .iter()
+                .map(|segment| {
+                    let scale = segment.quantization_step / self.quantization_step;
+                    (segment.original_size, scale)
+                })
+                .collect(),
+            None => vec![(self.original_size, 1.)],
+        }
+    }
+
+    /// Scale of the quantization step of each frame of a channel, see [`Self::segments`].
+    fn frame_scales(&self) -> Vec<f32> {
+        let Some(frame_size) = self.frame_size else {
+            return vec![1.; self.frame_count];
+        };
+        self.segments()
+            .into_iter()
+            .flat_map(|(size, scale)| iter::repeat_n(scale, frame_starts(size, frame_size).count()))
+            .collect()
+    }
+
+    /// Number of retained frequencies, without decoding them.
+    fn coefficient_count(&self) -> Result<usize, EntropyError> {
+        match &self.kept_bins {
+            Some(masks) => Ok(entropy::decode(masks, self.total_bin_count())?
+                .iter()
+                .filter(|bit| **bit != 0)
+                .count()),
+            None => {
+                let bin_count = self.bin_count().saturating_sub(self.cutoff_zeros);
+                Ok(bin_count * self.frame_count * self.channel_count)
+            }
+        }
+    }
+
+    /// Decode the quantized frequencies of each frame in each channel like [`Self::channels`],
+    /// with zeros in place of the bins dropped by [`compress_wav_topk`], and in the order of
+    /// the bins if stored by magnitude.
+    fn scattered_channels(&self) -> Result<Vec<Frames>, EntropyError> {
+        if let Some(order) = &self.order {
+            return self.ordered_channels(order, usize::MAX);
+        }
+        let channels = self.channels()?;
+        let Some(masks) = &self.kept_bins else {
+            return Ok(channels);
+        };
+        let masks = entropy::decode(masks, self.total_bin_count())?;
+        channels
+            .iter()
+            .enumerate()
+            .map(|(c, frames)| scatter_kept_bins(frames, &masks, c, self))
+            .collect()
+    }
+
+    /// Decode the first `count` frequencies stored by descending magnitude (see
+    /// [`compress_wav_progressive`]) into the bins of their single frame, leaving the rest
+    /// zero.
+    fn ordered_channels(&self, order: &[u8], count: usize) -> Result<Vec<Frames>, EntropyError> {
+        let values = entropy::decode(&self.frequencies, self.total_bin_count().saturating_mul(2))?;
+        let positions = entropy::decode_wide(order, self.total_bin_count())?;
+        let bin_count = self.bin_count().saturating_sub(self.cutoff_zeros);
+        if self.frame_count != 1 || values.len() != positions.len() * 2 {
+            return Err(EntropyError::Malformed);
+        }
+        let mut channels = vec![vec![vec![(0, 0); bin_count]]; self.channel_count];
+        for (position, pair) in positions.iter().zip(values.chunks_exact(2)).take(count) {
+            let position = usize::try_from(*position).map_err(|_| EntropyError::Malformed)?;
+            let bin = channels
+                .get_mut(position / bin_count.max(1))
+                .and_then(|frames| frames[0].get_mut(position % bin_count.max(1)))
+                .ok_or(EntropyError::Malformed)?;
+            *bin = (pair[0], pair[1]);
+        }
+        Ok(channels)
+    }
+
+    /// Average magnitude of each retained frequency bin over the frames of all channels.
+    fn spectrum(&self) -> Result<Vec<f32>, EntropyError> {
+        let channels = self.scattered_channels()?;
+        let frame_scales = self.frame_scales();
+        let frames: Vec<(&Vec<(i16, i16)>, &f32)> = channels
+            .iter()
+            .flat_map(|frames| frames.iter().zip(&frame_scales))
+            .collect();
+        let bin_count = frames
+            .iter()
+            .map(|(frame, _)| frame.len())
+            .max()
+            .unwrap_or(0);
+        let mut spectrum = vec![0.; bin_count];
+        for (frame, scale) in &frames {
+            for (magnitude, (re, im)) in spectrum.iter_mut().zip(frame.iter()) {
+                *magnitude += Complex32::new(*re as f32, *im as f32).norm() * *scale;
+            }
+        }
+        let scale = 1. / frames.len().max(1) as f32;
+        spectrum
+            .iter_mut()
+            .zip(self.bin_steps()?)
+            .for_each(|(magnitude, step)| *magnitude *= step * scale);
+        Ok(spectrum)
+    }
+
+    /// Decode the quantized frequencies of each frame in each channel.
+    fn channels(&self) -> Result<Vec<Frames>, EntropyError> {
+        let values = entropy::decode(&self.frequencies, self.total_bin_count().saturating_mul(2))?;
+        let frame_total = self.channel_count * self.frame_count;
+        let frame_len = values.len() / frame_total.max(1);
+        if frame_len * frame_total != values.len() || !frame_len.is_multiple_of(2) {
+            return Err(EntropyError::Malformed);
+        }
+        let frames: Frames = (0..frame_total)
+            .map(|f| {
+                values[f * frame_len..(f + 1) * frame_len]
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0], pair[1]))
+                    .collect()
+            })
+            .collect();
+        Ok((0..self.channel_count)
+            .map(|c| frames[c * self.frame_count..(c + 1) * self.frame_count].to_vec())
+            .collect())
+    }
+}
+
+/// Start of each frame of a waveform padded by half a frame, with frames overlapping by half.
+///
+/// Every sample of the waveform is covered by exactly two frames.
+fn frame_starts(sample_size: usize, frame_size: usize) -> impl Iterator<Item = usize> {
+    let hop = frame_size / 2;
+    (0..sample_size + hop).step_by(hop)
+}
+
+/// Square root of the (periodic) Hann window, applied to a frame both before the transform
+/// (analysis) and after the inverse (synthesis).
+///
+/// Their product is the Hann window, which sums to 1 over frames overlapping by half (the
+/// constant overlap-add condition), so the frames are restored without amplitude modulation.
+fn frame_window(frame_size: usize) -> Vec<f32> {
+    (0..frame_size)
+        .map(|i| window::window_value(WindowKind::Hann, i, frame_size).sqrt())
+        .collect()
+}
+
+/// Transform a frame and keep only the frequencies below the highest bin.
+///
+/// Only the non-redundant half of the spectrum is transformed, see [`fft::rfft`].
+fn compress_frame(frame: &[f32], highest_bin: usize) -> Result<Vec<Complex32>, fft::FftError> {
+    let mut freq_domain = fft::rfft(frame)?;
+    freq_domain.truncate(highest_bin);
+    Ok(freq_domain)
+}
+
+fn validate_quantization(quantization: f32) -> Result<(), CompressionError> {
+    match quantization.partial_cmp(&1.) {
+        Some(Ordering::Greater | Ordering::Equal) => Ok(()),
+        _ => Err(CompressionError::InvalidParameter(
+            "quantization must be no smaller than 1",
+        )),
+    }
+}
+
+/// A cutoff of 0 Hz would discard every frequency. Callers deriving the cutoff from a float
+/// get 0 for NaN and negative values, which are rejected the same way.
+fn validate_freq_cutoff(freq_cutoff: usize) -> Result<(), CompressionError> {
+    match freq_cutoff {
+        0 => Err(CompressionError::InvalidParameter(
+            "frequency cutoff must be greater than 0",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Quantize the frequencies of all frames to integers, returning the quantization step.
+///
+/// At a quantization of 1 the largest frequency component spans the full range of i16.
+fn quantize(channels: &[Vec<Vec<Complex32>>], quantization: f32) -> (Vec<Frames>, f32) {
+    quantize_weighted(channels, quantization, &[])
+}
+
+/// Quantize like [`quantize`], with the step of each bin scaled by its weight (bins past the
+/// weights are not scaled).
+fn quantize_weighted(
+    channels: &[Vec<Vec<Complex32>>],
+    quantization: f32,
+    scales: &[f32],
+) -> (Vec<Frames>, f32) {
+    let peak = channels
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|c| c.re.abs().max(c.im.abs()))
+        .fold(0., f32::max);
+    let step = match peak > 0. {
+        true => peak * quantization / i16::MAX as f32,
+        false => 1.,
+    };
+    let quantized = channels
+        .iter()
+        .map(|frames| {
+            frames
+                .iter()
+                .map(|frame| {
+                    frame
+                        .iter()
+                        .zip(scales.iter().chain(iter::repeat(&1.)))
+                        .map(|(c, scale)| {
+                            let step = step * scale;
+                            ((c.re / step).round() as i16, (c.im / step).round() as i16)
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    (quantized, step)
+}
+
+/// Place the kept frequencies of a channel at their positions, zeroing the other frequencies.
+fn scatter_kept_bins(
+    frames: &Frames,
+    masks: &[i16],
+    channel: usize,
+    decoded: &CompressedData,
+) -> Result<Frames, EntropyError> {
+    let bin_count = decoded.bin_count();
+    let mask = masks
+        .get(channel * bin_count..(channel + 1) * bin_count)
+        .ok_or(EntropyError::Malformed)?;
+    let mut kept = frames.first().ok_or(EntropyError::Malformed)?.iter();
+    let scattered = mask
+        .iter()
+        .map(|bit| match bit {
+            0 => Some((0, 0)),
+            _ => kept.next().copied(),
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(EntropyError::Malformed)?;
+    Ok(vec![scattered])
+}
+
+/// Restore a channel's waveform from its frames.
+fn decompress_channel(
+    frames: &Frames,
+    steps: &[f32],
+    decoded: &CompressedData,
+    transform: &dyn Transform,
+) -> Result<Vec<f32>, fft::FftError> {
+    let original_size = decoded.original_size;
+    let Some(frame_size) = decoded.frame_size else {
+        let mut waveform = decompress_frame(&frames[0], steps, decoded, transform)?;
+        waveform.truncate(original_size);
+        return Ok(waveform);
+    };
+    let hop = frame_size / 2;
+    let mut frames = frames.iter();
+    let mut waveform = Vec::with_capacity(original_size);
+    // Each concatenated waveform is restored separately, as it was compressed
+    for (segment_size, scale) in decoded.segments() {
+        let steps: Vec<f32> = steps.iter().map(|step| step * scale).collect();
+        let mut overlap_add = OverlapAdd::new(frame_size);
+        let mut segment = Vec::with_capacity(segment_size + frame_size);
+        for frame in frames
+            .by_ref()
+            .take(frame_starts(segment_size, frame_size).count())
+        {
+            segment.extend(overlap_add.push(&decompress_frame(frame, &steps, decoded, transform)?));
+        }
+        // Drop the padding at the front, and what the last frames cover past the end
+        segment.truncate(segment_size + hop);
+        segment.drain(..hop);
+        waveform.extend(segment);
+    }
+    Ok(waveform)
+}
+
+/// Overlap-adds windowed frames into the padded waveform one frame at a time, keeping only the
+/// samples that later frames still overlap.
+struct OverlapAdd {
+    window: Vec<f32>,
+    samples: Vec<f32>,
+    window_sums: Vec<f32>,
+}
+
+impl OverlapAdd {
+    fn new(frame_size: usize) -> OverlapAdd {
+        OverlapAdd {
+            window: frame_window(frame_size),
+            samples: vec![0.; frame_size],
+            window_sums: vec![0.; frame_size],
+        }
+    }
+
+    /// Add the next frame, returning the (hop) samples that no later frame overlaps.
+    fn push(&mut self, frame: &[f32]) -> Vec<f32> {
+        let hop = self.window.len() / 2;
+        for (i, w) in self.window.iter().enumerate() {
+            self.samples[i] += frame[i] * w;
+            self.window_sums[i] += w * w;
+        }
+        // The sums are 1 for even frame sizes, dividing by them also corrects odd frame sizes
+        let finished = self.samples[..hop]
+            .iter()
+            .zip(&self.window_sums[..hop])
+            .map(|(sample, sum)| match *sum > 1e-6 {
+                true => sample / sum,
+                false => *sample,
+            })
+            .collect();
+        for buffer in [&mut self.samples, &mut self.window_sums] {
+            buffer.rotate_left(hop);
+            let frame_size = buffer.len();
+            buffer[frame_size - hop..].fill(0.);
+        }
+        finished
+    }
+}
+
+/// Restore a frame's waveform from its retained frequencies.
+fn decompress_frame(
+    frequencies: &[(i16, i16)],
+    steps: &[f32],
+    decoded: &CompressedData,
+    transform: &dyn Transform,
+) -> Result<Vec<f32>, fft::FftError> {
+    let mut freq_domain: Vec<Complex32> = frequencies
+        .iter()
+        .zip(steps)
+        .map(|((r, i), step)| Complex32::new(*r as f32 * step, *i as f32 * step))
+        .collect();
+    freq_domain.append(&mut vec![Complex32::default(); decoded.cutoff_zeros]);
+    // The inverse of the FFT restores the conjugate mirror of the retained frequencies
+    let waveform = transform.inverse(&freq_domain, decoded.transform_size())?;
+    Ok(waveform.iter().map(|x| x.re).collect())
+}
+
+/// Quantize and serialize the retained frequencies of waveforms transformed as a single frame,
+/// weighting the quantization by the curve unless it is empty.
+fn serialize_single_frame(
+    metadata: &WaveformMetadata,
+    original_size: usize,
+    channels: &[Vec<Vec<Complex32>>],
+    bin_count: usize,
+    quantization: f32,
+    curve: &[(f32, f32)],
+) -> Result<Vec<u8>, CompressionError> {
+    let padded_size = original_size.next_power_of_two();
+    let highest_bin = channels[0][0].len();
+    let weights = match curve.is_empty() {
+        true => vec![],
+        false => bin_weights(curve, metadata.freq_resolution(padded_size), highest_bin),
+    };
+    let scales: Vec<f32> = weights.iter().map(|w| *w as f32 / WEIGHT_UNIT).collect();
+    let (channels, quantization_step) = quantize_weighted(channels, quantization, &scales);
+    let mut compressed = CompressedData::new(
+        metadata.sample_rate,
+        original_size,
+        metadata.bit_rate,
+        None,
+        &channels,
+        bin_count - highest_bin,
+        quantization_step,
+    )?;
+    if bin_count != padded_size / 2 + 1 {
+        compressed.bin_count = Some(bin_count);
+    }
+    if !weights.is_empty() {
+        // Neighbouring bins mostly share their weight, or differ by a little along a slope
+        let differences: Vec<i16> = weights
+            .iter()
+            .scan(0, |previous, weight| {
+                let difference = weight - *previous;
+                *previous = *weight;
+                Some(difference)
+            })
+            .collect();
+        compressed.weights = Some(entropy::encode(&differences)?);
+    }
+    serialize_compressed(&compressed)
+}
+
+fn serialize_compressed(compressed: &CompressedData) -> Result<Vec<u8>, CompressionError> {
+    // Variable length integers let smaller quantized values take fewer bytes
+    let encoded = bincode::DefaultOptions::new().serialize(compressed)?;
+    Ok(header::with_header(WAV_MAGIC, WAV_VERSION, &encoded))
+}
+
+fn deserialize_compressed(compressed: &[u8]) -> Result<CompressedData, CompressionError> {
+    let encoded = header::strip_header(WAV_MAGIC, WAV_VERSION, compressed)?;
+    Ok(bincode::DefaultOptions::new().deserialize(encoded)?)
+}
+
+/// Load a .wav file, deinterleaving the samples of each channel.
+fn load_wav_file(path: &Path) -> Result<(WaveformMetadata, Channels), CompressionError> {
+    read_wav(&fs::read(path)?)
+}
+
+/// Load a .wav file, averaging the samples of its channels into a single waveform.
+fn load_wav_file_mono(path: &Path) -> Result<(WaveformMetadata, Vec<f32>), CompressionError> {
+    read_wav_mono(&fs::read(path)?)
+}
+
+/// Read the contents of a .wav file, averaging the samples of its channels.
+fn read_wav_mono(bytes: &[u8]) -> Result<(WaveformMetadata, Vec<f32>), CompressionError> {
+    let (metadata, channels) = read_wav(bytes)?;
+    let count = channels.len() as f32;
+    let waveform = (0..channels[0].len())
+        .map(|i| channels.iter().map(|channel| channel[i]).sum::<f32>() / count)
         .collect();
-    freq_domain.append(&mut vec![Complex32::default(); decoded.cutoff_zeros]);
-    let time_domain = fft::fft_inverse(&freq_domain);
-    let mut waveform: Vec<f32> = time_domain.iter().map(|c| c.re as f32).collect();
-    waveform.drain(decoded.original_size..);
-    let metadata = WaveformMetadata::new(decoded.sample_rate, decoded.bit_rate);
-    write_wav_file(output_file, waveform, &metadata)?;
-    Ok(())
+    Ok((metadata, waveform))
 }
 
-/// Produce an html page with interactive plots of the time domain and frequency domain.
-pub fn analyze_waveform(
-    wav_file: &PathBuf,
-    output_dir: &PathBuf,
-) -> Result<PathBuf, Box<dyn Error>> {
-    let file_path = output_dir.join("analysis.html");
-    let (metadata, mut waveform) = load_wav_file(&wav_file)?;
-    fft::round_sample_size_up(&mut waveform);
-    let time_domain = fft::convert_sample(&waveform);
-    let freq_bins = fft::frequency_bins(&fft::fft(&time_domain));
-    println!("Writing analysis to: {:?}", file_path);
-    plot(
-        waveform.clone(),
-        freq_bins,
-        &metadata,
-        &file_path,
-        &wav_file.as_path().to_string_lossy().to_string(),
+/// Read the contents of a .wav file (or a .flac file with the `flac` feature), deinterleaving the
+/// samples of each channel.
+///
+/// Returns an error if the file has no samples, as there is nothing to transform.
+fn read_wav(bytes: &[u8]) -> Result<(WaveformMetadata, Channels), CompressionError> {
+    if bytes.starts_with(FLAC_MAGIC) {
+        #[cfg(feature = "flac")]
+        return read_flac(bytes);
+        #[cfg(not(feature = "flac"))]
+        return Err(FormatError::UnsupportedFormat.into());
+    }
+    let (header, data) = wav::read(&mut Cursor::new(bytes))?;
+    let channel_count = header.channel_count as usize;
+    if channel_count == 0 {
+        return Err(FormatError::UnsupportedChannels.into());
+    }
+    let samples: Vec<f32> = match data {
+        BitDepth::Eight(d) => d.iter().map(|x| *x as f32).collect(),
+        BitDepth::Sixteen(d) => d.iter().map(|x| *x as f32).collect(),
+        BitDepth::TwentyFour(d) => d.iter().map(|x| *x as f32).collect(),
+        BitDepth::ThirtyTwoFloat(d) => d,
+        BitDepth::Empty => return Err(FormatError::UnsupportedFormat.into()),
+    };
+    if samples.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let metadata = WaveformMetadata::new(
+        header.sampling_rate as usize,
+        header.bits_per_sample as usize,
     );
-    Ok(file_path)
+    Ok((metadata, deinterleave(&samples, channel_count)))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct WaveformMetadata {
-    pub sample_rate: usize,
-    pub bit_rate: usize,
+/// Decode the contents of a .flac file, deinterleaving the samples of each channel.
+///
+/// The samples are scaled like those of a .wav file of the same bit depth: 8-bit samples are
+/// unsigned and 24-bit samples are kept in the upper bytes of an i32.
+#[cfg(feature = "flac")]
+fn read_flac(bytes: &[u8]) -> Result<(WaveformMetadata, Channels), CompressionError> {
+    let mut reader = claxon::FlacReader::new(Cursor::new(bytes))?;
+    let info = reader.streaminfo();
+    let (offset, scale) = match info.bits_per_sample {
+        8 => (128., 1.),
+        16 => (0., 1.),
+        24 => (0., 256.),
+        _ => return Err(FormatError::UnsupportedFormat.into()),
+    };
+    let samples = reader
+        .samples()
+        .map(|sample| Ok(sample? as f32 * scale + offset))
+        .collect::<Result<Vec<f32>, claxon::Error>>()?;
+    if samples.is_empty() {
+        return Err(CompressionError::EmptyInput);
+    }
+    let metadata = WaveformMetadata::new(info.sample_rate as usize, info.bits_per_sample as usize);
+    Ok((metadata, deinterleave(&samples, info.channels as usize)))
 }
 
-impl WaveformMetadata {
-    pub fn new(sample_rate: usize, bit_rate: usize) -> WaveformMetadata {
-        WaveformMetadata {
-            sample_rate,
-            bit_rate,
-        }
+/// Read raw PCM audio of the given format, deinterleaving the samples of each channel.
+///
+/// Returns an error if the audio has no samples or ends in the middle of a frame.
+fn read_raw_pcm(
+    bytes: &[u8],
+    format: PcmFormat,
+) -> Result<(WaveformMetadata, Channels), CompressionError> {
+    if format.channel_count == 0 {
+        return Err(FormatError::UnsupportedChannels.into());
     }
-
-    pub fn freq_resolution(&self, sample_size: usize) -> f32 {
-        self.sample_rate as f32 / sample_size as f32
+    let samples: Vec<f32> = match format.bit_depth {
+        8 => bytes.iter().map(|x| *x as f32).collect(),
+        16 => bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32)
+            .collect(),
+        // Kept in the upper bytes of an i32, like the wav crate does
+        24 => bytes
+            .chunks_exact(3)
+            .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32)
+            .collect(),
+        32 => bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        _ => return Err(FormatError::UnsupportedFormat.into()),
+    };
+    if !bytes
+        .len()
+        .is_multiple_of(format.bit_depth / 8 * format.channel_count)
+    {
+        return Err(CompressionError::InvalidParameter(
+            "raw PCM audio must be a whole number of frames",
+        ));
+    }
+    if samples.is_empty() {
+        return Err(CompressionError::EmptyInput);
     }
+    let metadata = WaveformMetadata::new(format.sample_rate, format.bit_depth);
+    Ok((metadata, deinterleave(&samples, format.channel_count)))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CompressedData {
-    sample_rate: usize,
-    original_size: usize,
-    bit_rate: usize,
-    frequencies: Vec<(f32, f32)>,
-    cutoff_zeros: usize,
+/// Separate interleaved samples into the waveform of each channel.
+fn deinterleave(samples: &[f32], channel_count: usize) -> Channels {
+    (0..channel_count)
+        .map(|c| {
+            samples
+                .iter()
+                .skip(c)
+                .step_by(channel_count)
+                .copied()
+                .collect()
+        })
+        .collect()
 }
 
-impl CompressedData {
-    fn new(
-        sample_rate: usize,
-        original_size: usize,
-        bit_rate: usize,
-        frequencies: Vec<(f32, f32)>,
-        cutoff_zeros: usize,
-    ) -> CompressedData {
-        CompressedData {
-            sample_rate,
-            original_size,
-            bit_rate,
-            frequencies,
-            cutoff_zeros,
-        }
+/// Encode channels as raw PCM audio of the bit depth, interleaving the samples of each channel.
+fn write_raw_pcm(
+    channels: Channels,
+    metadata: &WaveformMetadata,
+) -> Result<Vec<u8>, CompressionError> {
+    let bytes = match to_track(interleave(&channels), metadata.bit_rate)? {
+        BitDepth::Eight(d) => d,
+        BitDepth::Sixteen(d) => d.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        BitDepth::TwentyFour(d) => d
+            .iter()
+            .flat_map(|x| {
+                let [_, low, middle, high] = x.to_le_bytes();
+                [low, middle, high]
+            })
+            .collect(),
+        BitDepth::ThirtyTwoFloat(d) => d.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        BitDepth::Empty => Vec::new(),
+    };
+    Ok(bytes)
+}
+
+/// Write the RIFF header of a .wav file, up to the start of the samples (of the data size).
+fn write_wav_header(
+    writer: &mut impl Write,
+    metadata: &WaveformMetadata,
+    channel_count: usize,
+    data_size: usize,
+) -> Result<(), CompressionError> {
+    let format = match metadata.bit_rate {
+        32 => WAV_FORMAT_IEEE_FLOAT,
+        _ => WAV_FORMAT_PCM,
+    };
+    let header: [u8; 16] = Header::new(
+        format,
+        channel_count as u16,
+        metadata.sample_rate as u32,
+        metadata.bit_rate as u16,
+    )
+    .into();
+    let riff_size = 4 + (8 + header.len()) + 8 + data_size + data_size % 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(riff_size as u32).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&(header.len() as u32).to_le_bytes())?;
+    writer.write_all(&header)?;
+    writer.write_all(b"data")?;
+    writer.write_all(&(data_size as u32).to_le_bytes())?;
+    Ok(())
+}
+
+/// Encode the contents of a .wav file, interleaving the samples of each channel.
+fn write_wav(channels: Channels, metadata: &WaveformMetadata) -> Result<Vec<u8>, CompressionError> {
+    write_wav_dithered(channels, metadata, None)
+}
+
+/// Interleave the channels into the contents of a .wav file, adding dither (from the seed)
+/// before the samples are rounded to an integer bit depth.
+fn write_wav_dithered(
+    channels: Channels,
+    metadata: &WaveformMetadata,
+    dither_seed: Option<u64>,
+) -> Result<Vec<u8>, CompressionError> {
+    let format = match metadata.bit_rate {
+        32 => WAV_FORMAT_IEEE_FLOAT,
+        _ => WAV_FORMAT_PCM,
+    };
+    let header = Header::new(
+        format,
+        channels.len() as u16,
+        metadata.sample_rate as u32,
+        metadata.bit_rate as u16,
+    );
+    let mut waveform = interleave(&channels);
+    if let (Some(seed), 8 | 16 | 24) = (dither_seed, metadata.bit_rate) {
+        // The wav crate keeps 24-bit samples in the upper bytes of an i32
+        let step = match metadata.bit_rate {
+            24 => 256.,
+            _ => 1.,
+        };
+        let mut dither = Dither::new(seed);
+        waveform
+            .iter_mut()
+            .for_each(|x| *x += dither.noise() * step);
     }
+    let track = to_track(waveform, metadata.bit_rate)?;
+    let mut bytes = Cursor::new(Vec::new());
+    wav::write(header, &track, &mut bytes)?;
+    Ok(bytes.into_inner())
 }
 
-fn load_wav_file(path: &PathBuf) -> Result<(WaveformMetadata, Vec<f32>), Box<dyn Error>> {
-    let mut inp_file = File::open(Path::new(path))?;
-    let (header, data) = wav::read(&mut inp_file)?;
-    if header.channel_count != 1 {
-        return Err(Box::new(FormatError::UnsupportedChannels));
+/// Encode the channels (mono or stereo) as an .mp3 file.
+#[cfg(feature = "mp3")]
+fn write_mp3(channels: Channels, metadata: &WaveformMetadata) -> Result<Vec<u8>, CompressionError> {
+    use mp3lame_encoder::{Bitrate, BuildError, Builder, DualPcm, FlushNoGap, MonoPcm};
+    if !(1..=2).contains(&channels.len()) {
+        return Err(FormatError::UnsupportedChannels.into());
     }
-    let waveform: Vec<f32> = match data {
-        BitDepth::Eight(d) => d.iter().map(|x| x.clone() as f32).collect(),
-        BitDepth::Sixteen(d) => d.iter().map(|x| x.clone() as f32).collect(),
-        BitDepth::TwentyFour(d) => d.iter().map(|x| x.clone() as f32).collect(),
-        BitDepth::ThirtyTwoFloat(d) => d.iter().map(|x| x.clone() as f32).collect(),
-        BitDepth::Empty => return Err(Box::new(FormatError::UnsupportedFormat)),
+    // The encoder takes samples from -1 to 1, like those of a 32-bit float .wav file
+    let (offset, scale) = match metadata.bit_rate {
+        8 => (128., 128.),
+        16 => (0., 32768.),
+        24 => (0., 2147483648.),
+        32 => (0., 1.),
+        _ => return Err(FormatError::UnsupportedFormat.into()),
     };
-    let metadata = WaveformMetadata::new(
-        header.sampling_rate as usize,
-        header.bits_per_sample as usize,
-    );
-    Ok((metadata, waveform))
+    let channels: Channels = channels
+        .iter()
+        .map(|channel| {
+            channel
+                .iter()
+                .map(|x| ((x - offset) / scale).clamp(-1., 1.))
+                .collect()
+        })
+        .collect();
+    let mut encoder = Builder::new()
+        .ok_or(BuildError::NoMem)?
+        .with_num_channels(channels.len() as u8)?
+        .with_sample_rate(metadata.sample_rate as u32)?
+        .with_brate(Bitrate::Kbps192)?
+        // Otherwise the first frame is left empty for a tag written after encoding
+        .with_to_write_vbr_tag(false)?
+        .build()?;
+    let sample_size = channels.first().map_or(0, Vec::len);
+    let mut bytes = Vec::with_capacity(mp3lame_encoder::max_required_buffer_size(sample_size));
+    match channels.as_slice() {
+        [mono] => encoder.encode_to_vec(MonoPcm(mono), &mut bytes)?,
+        [left, right] => encoder.encode_to_vec(DualPcm { left, right }, &mut bytes)?,
+        _ => return Err(FormatError::UnsupportedChannels.into()),
+    };
+    // The last frames take up to 7200 bytes
+    bytes.reserve(7200);
+    encoder.flush_to_vec::<FlushNoGap>(&mut bytes)?;
+    Ok(bytes)
 }
 
-fn write_wav_file(
-    path: &PathBuf,
-    waveform: Vec<f32>,
-    metadata: &WaveformMetadata,
-) -> Result<(), Box<dyn Error>> {
-    let mut out_file = File::create(Path::new(path))?;
-    let header = Header::new(1, 1, metadata.sample_rate as u32, metadata.bit_rate as u16);
-    let track = match metadata.bit_rate {
-        8 => BitDepth::Eight(waveform.iter().map(|x| x.clone() as u8).collect()),
-        16 => BitDepth::Sixteen(waveform.iter().map(|x| x.clone() as i16).collect()),
-        24 => BitDepth::TwentyFour(waveform.iter().map(|x| x.clone() as i32).collect()),
+/// Merge the waveforms of each channel into interleaved samples.
+fn interleave(channels: &Channels) -> Vec<f32> {
+    let sample_size = channels.iter().map(Vec::len).max().unwrap_or(0);
+    (0..sample_size)
+        .flat_map(|i| channels.iter().map(move |channel| channel[i]))
+        .collect()
+}
+
+/// Convert samples to the bit depth, rounding them to the nearest value (truncating toward zero
+/// would bias them) and clamping them to its range so they cannot wrap around.
+fn to_track(waveform: Vec<f32>, bit_rate: usize) -> Result<BitDepth, CompressionError> {
+    let track = match bit_rate {
+        8 => BitDepth::Eight(
+            waveform
+                .iter()
+                .map(|x| x.round().clamp(u8::MIN as f32, u8::MAX as f32) as u8)
+                .collect(),
+        ),
+        16 => BitDepth::Sixteen(
+            waveform
+                .iter()
+                .map(|x| x.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+                .collect(),
+        ),
+        // The wav crate keeps 24-bit samples in the upper bytes of an i32, the lowest is dropped
+        24 => BitDepth::TwentyFour(
+            waveform
+                .iter()
+                .map(|x| ((x / 256.).round() * 256.).clamp(i32::MIN as f32, i32::MAX as f32) as i32)
+                .collect(),
+        ),
         32 => BitDepth::ThirtyTwoFloat(waveform),
-        _ => return Err(Box::new(FormatError::UnsupportedFormat)),
+        _ => return Err(FormatError::UnsupportedFormat.into()),
     };
-    wav::write(header, &track, &mut out_file)?;
-    Ok(())
+    Ok(track)
+}
+
+/// Integer value of each sample as it is written at the bit depth, see [`to_track`].
+///
+/// 24-bit samples are shifted down from the upper bytes, and the bits of 32-bit float samples
+/// are reinterpreted, which keeps the values of close samples close.
+fn sample_values(waveform: Vec<f32>, bit_rate: usize) -> Result<Vec<i32>, CompressionError> {
+    let values = match to_track(waveform, bit_rate)? {
+        BitDepth::Eight(d) => d.into_iter().map(i32::from).collect(),
+        BitDepth::Sixteen(d) => d.into_iter().map(i32::from).collect(),
+        BitDepth::TwentyFour(d) => d.into_iter().map(|x| x >> 8).collect(),
+        BitDepth::ThirtyTwoFloat(d) => d.into_iter().map(|x| x.to_bits() as i32).collect(),
+        BitDepth::Empty => Vec::new(),
+    };
+    Ok(values)
 }
 
+/// Samples of the integer values at the bit depth, undoing [`sample_values`] exactly.
+fn from_sample_values(values: &[i32], bit_rate: usize) -> Vec<f32> {
+    values
+        .iter()
+        .map(|&value| match bit_rate {
+            24 => (value << 8) as f32,
+            32 => f32::from_bits(value as u32),
+            _ => value as f32,
+        })
+        .collect()
+}
+
+/// Reproducible noise with a triangular distribution (TPDF), spanning one quantization step
+/// either way.
+struct Dither {
+    state: u64,
+}
+
+impl Dither {
+    fn new(seed: u64) -> Dither {
+        Dither { state: seed }
+    }
+
+    fn noise(&mut self) -> f32 {
+        self.uniform() - self.uniform()
+    }
+
+    /// Uniform value in [0, 1), from the SplitMix64 generator.
+    fn uniform(&mut self) -> f32 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Plot the waveform, its spectrogram and its spectrum, overlaid by a second (reconstructed)
+/// spectrum if given.
 fn plot(
     waveform: Vec<f32>,
-    freq_bins: Vec<f32>,
+    spectra: Vec<Vec<f32>>,
+    spectrogram: Vec<Vec<f32>>,
     metadata: &WaveformMetadata,
-    file_path: &PathBuf,
+    file_path: &Path,
     title: &str,
+    config: PlotConfig,
 ) {
     let sample_size = waveform.len();
     let waveform_legend = (0..sample_size)
@@ -198,22 +2469,50 @@ fn plot(
     let waveform_trace = Scatter::new(waveform_legend, waveform)
         .mode(Mode::Lines)
         .name("")
+        .show_legend(false)
         .line(Line::new().color(NamedColor::Blue))
         .x_axis("x1")
         .y_axis("y1");
-    let freq_legend = (0..freq_bins.len())
-        .map(|x| x as f32 * metadata.freq_resolution(sample_size))
+    let overlaid = spectra.len() > 1;
+    let freq_bins_traces: Vec<_> = spectra
+        .iter()
+        .zip([
+            ("Original", NamedColor::IndianRed),
+            ("Reconstructed", NamedColor::SeaGreen),
+        ])
+        .map(|(freq_bins, (name, color))| {
+            let (freq_legend, freq_bins): (Vec<f32>, Vec<f32>) =
+                fft::labeled_bins(freq_bins, metadata.sample_rate, sample_size).unzip();
+            Scatter::new(freq_legend, freq_bins)
+                .mode(Mode::Lines)
+                .name(if overlaid { name } else { "" })
+                .show_legend(overlaid)
+                .line(Line::new().color(color))
+                .x_axis("x2")
+                .y_axis("y2")
+        })
         .collect();
-    let freq_bins_trace = Scatter::new(freq_legend, freq_bins)
-        .mode(Mode::Lines)
+    let time_legend = (0..spectrogram.len())
+        .map(|x| (x * SPECTROGRAM_HOP) as f32 / metadata.sample_rate as f32)
+        .collect();
+    let first_column = spectrogram.first().map_or(&[][..], Vec::as_slice);
+    let bin_count = first_column.len();
+    let spectrogram_freq_legend =
+        fft::labeled_bins(first_column, metadata.sample_rate, SPECTROGRAM_FRAME_SIZE)
+            .map(|(frequency, _)| frequency)
+            .collect();
+    // The heatmap takes rows of frequencies, while the spectrogram has columns of time
+    let magnitudes: Vec<Vec<f32>> = (0..bin_count)
+        .map(|bin| spectrogram.iter().map(|column| column[bin]).collect())
+        .collect();
+    let spectrogram_trace = HeatMap::new(time_legend, spectrogram_freq_legend, magnitudes)
         .name("")
-        .line(Line::new().color(NamedColor::IndianRed))
-        .x_axis("x2")
-        .y_axis("y2");
+        .x_axis("x3")
+        .y_axis("y3");
     let layout = Layout::new()
         .grid(
             LayoutGrid::new()
-                .rows(2)
+                .rows(3)
                 .columns(1)
                 .pattern(GridPattern::Independent)
                 .row_order(RowOrder::TopToBottom),
@@ -223,12 +2522,1326 @@ fn plot(
         .y_axis(Axis::new().title(Title::new("Amplitude")))
         .x_axis2(Axis::new().title(Title::new("Frequency (Hz)")))
         .y_axis2(Axis::new().title(Title::new("Amplitude")))
-        .show_legend(false)
-        .width(1900)
-        .height(800);
+        .x_axis3(Axis::new().title(Title::new("Time (seconds)")))
+        .y_axis3(Axis::new().title(Title::new("Frequency (Hz)")))
+        .show_legend(overlaid);
+    let layout = config.apply(layout, 1900, 1200);
     let mut plot = Plot::new();
     plot.add_trace(waveform_trace);
-    plot.add_trace(freq_bins_trace);
+    for trace in freq_bins_traces {
+        plot.add_trace(trace);
+    }
+    plot.add_trace(spectrogram_trace);
     plot.set_layout(layout);
     plot.write_html(file_path);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::{DctTransform, PartialFftTransform, SpectrumPart};
+    use std::f32::consts::PI;
+
+    fn write_wav_file(
+        path: &Path,
+        channels: Channels,
+        metadata: &WaveformMetadata,
+    ) -> Result<(), CompressionError> {
+        fs::write(path, write_wav(channels, metadata)?)?;
+        Ok(())
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compression_wav_{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn tone(sample_size: usize, sample_rate: usize, frequency: f32, amplitude: f32) -> Vec<f32> {
+        (0..sample_size)
+            .map(|x| (2. * PI * frequency * x as f32 / sample_rate as f32).sin() * amplitude)
+            .collect()
+    }
+
+    #[test]
+    fn blocked_round_trip() {
+        let dir = test_dir("blocked_round_trip");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let decompressed = dir.join("decompressed.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        write_wav_file(&original, vec![tone(10000, 8000, 440., 1000.)], &metadata).unwrap();
+        let (_, waveform) = load_wav_file(&original).unwrap();
+        let waveform = &waveform[0];
+        // Keep every frequency bin so the frames can be restored exactly
+        compress_wav_blocked(&original, &compressed, 8000, 1., 1024, |_| {}).unwrap();
+        decompress_wav(&compressed, &decompressed).unwrap();
+        let (restored_metadata, restored) = load_wav_file(&decompressed).unwrap();
+        assert_eq!(restored_metadata.sample_rate, 8000);
+        assert_eq!(restored[0].len(), waveform.len());
+        for (x, y) in waveform.iter().zip(restored[0].iter()) {
+            assert!((x - y).abs() <= 1.5, "{x} != {y}");
+        }
+    }
+
+    #[test]
+    fn concat_blocked() {
+        let dir = test_dir("concat_blocked");
+        let (first, second) = (dir.join("first.cwv"), dir.join("second.cwv"));
+        let (concatenated, decompressed) = (dir.join("both.cwv"), dir.join("both.wav"));
+        let metadata = WaveformMetadata::new(8000, 16);
+        // Different lengths (not a whole number of frames) and loudness
+        let waveforms = [
+            [tone(5000, 8000, 440., 1000.), tone(5000, 8000, 300., 500.)],
+            [tone(3333, 8000, 660., 2000.), tone(3333, 8000, 200., 1500.)],
+        ];
+        let mut expected = vec![Vec::new(); 2];
+        for (waveform, path) in waveforms.iter().zip([&first, &second]) {
+            let input = write_wav(waveform.to_vec(), &metadata).unwrap();
+            let compressed = compress_wav_blocked_bytes(&input, 8000, 1., 512, |_| {}).unwrap();
+            fs::write(path, &compressed).unwrap();
+            let (_, restored) = read_wav(&decompress_wav_bytes(&compressed).unwrap()).unwrap();
+            for (channel, restored) in expected.iter_mut().zip(restored) {
+                channel.extend(restored);
+            }
+        }
+        concat_compressed(&[&first, &second], &concatenated).unwrap();
+        decompress_wav(&concatenated, &decompressed).unwrap();
+        let (_, restored) = load_wav_file(&decompressed).unwrap();
+        assert_eq!(restored, expected);
+        for (channel, restored) in restored.iter().enumerate() {
+            let original = waveforms.iter().flat_map(|waveforms| &waveforms[channel]);
+            for (x, y) in original.zip(restored) {
+                assert!((x - y).abs() <= 1.5, "{x} != {y}");
+            }
+        }
+        // Concatenated files can be concatenated again
+        concat_compressed(&[&concatenated, &first], &concatenated).unwrap();
+        let (_, restored) =
+            decompress_channels(&fs::read(&concatenated).unwrap(), &FftTransform).unwrap();
+        assert_eq!(restored[0].len(), 5000 + 3333 + 5000);
+    }
+
+    #[test]
+    fn concat_mismatched() {
+        let concat_error = |a: &[u8], b: &[u8]| match concat_compressed_bytes(&[a, b]) {
+            Err(CompressionError::InvalidParameter(message)) => message,
+            result => panic!("expected an invalid parameter, got {result:?}"),
+        };
+        let blocked = |sample_rate, frame_size| {
+            let metadata = WaveformMetadata::new(sample_rate, 16);
+            let input = write_wav(vec![tone(2000, sample_rate, 440., 1000.)], &metadata).unwrap();
+            compress_wav_blocked_bytes(&input, 4000, 1., frame_size, |_| {}).unwrap()
+        };
+        let reference = blocked(8000, 256);
+        assert!(concat_error(&reference, &blocked(16000, 256)).contains("sample rate"));
+        assert!(concat_error(&reference, &blocked(8000, 512)).contains("frame size"));
+        let metadata = WaveformMetadata::new(8000, 16);
+        let input = write_wav(vec![tone(2000, 8000, 440., 1000.)], &metadata).unwrap();
+        let single_frame = compress_wav_bytes(&input, 4000, 1., |_| {}).unwrap();
+        assert!(concat_error(&reference, &single_frame).contains("in frames"));
+        assert!(matches!(
+            concat_compressed_bytes(&[]),
+            Err(CompressionError::EmptyInput)
+        ));
+    }
+
+    #[test]
+    fn blocked_without_seams() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        // A period of 16 samples, which does not fit a whole number of times in a frame
+        let waveform = tone(8000, 8000, 500., 8000.);
+        let original = write_wav(vec![waveform], &metadata).unwrap();
+        // The cutoff drops the leakage of unwindowed frames, which would modulate the tone
+        let compressed = compress_wav_blocked_bytes(&original, 1000, 1., 250, |_| {}).unwrap();
+        let (_, restored) = read_wav(&decompress_wav_bytes(&compressed).unwrap()).unwrap();
+        // The envelope over each period
+        let envelope: Vec<f32> = restored[0]
+            .chunks_exact(16)
+            .map(|chunk| (chunk.iter().map(|x| x * x).sum::<f32>() / 16.).sqrt())
+            .collect();
+        let mean = envelope.iter().sum::<f32>() / envelope.len() as f32;
+        let variance = envelope
+            .iter()
+            .map(|x| (x / mean - 1.).powi(2))
+            .sum::<f32>()
+            / envelope.len() as f32;
+        assert!((mean - 8000. / 2f32.sqrt()).abs() < 10., "{mean}");
+        assert!(variance < 1e-5, "{variance}");
+    }
+
+    /// Discards what is written, recording the largest single write.
+    #[derive(Default)]
+    struct LargestWrite(usize);
+
+    impl Write for LargestWrite {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 = self.0.max(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streaming_matches_in_memory() {
+        let dir = test_dir("streaming_matches_in_memory");
+        // Odd sample counts and frame sizes, and an 8-bit mono file with an odd data size
+        let cases = [
+            (8, 1, 4001, 256),
+            (16, 2, 6000, 255),
+            (24, 2, 3000, 512),
+            (32, 1, 5000, 300),
+        ];
+        for (bit_rate, channel_count, sample_size, frame_size) in cases {
+            let amplitude = match bit_rate {
+                8 => 100.,
+                16 => 8000.,
+                24 => 8000. * 256.,
+                _ => 0.5,
+            };
+            let offset = if bit_rate == 8 { 128. } else { 0. };
+            let channels = (0..channel_count)
+                .map(|c| {
+                    let waveform = tone(sample_size, 8000, 300. + 200. * c as f32, amplitude);
+                    waveform.iter().map(|x| x + offset).collect()
+                })
+                .collect();
+            let metadata = WaveformMetadata::new(8000, bit_rate);
+            let original = write_wav(channels, &metadata).unwrap();
+            let compressed =
+                compress_wav_blocked_bytes(&original, 2000, 1., frame_size, |_| {}).unwrap();
+            let mut streamed = Vec::new();
+            decompress_wav_to_writer(&compressed, &mut streamed).unwrap();
+            assert_eq!(
+                streamed,
+                decompress_wav_bytes(&compressed).unwrap(),
+                "{bit_rate}"
+            );
+        }
+        // Single frames are restored whole
+        let original = write_wav(
+            vec![tone(1000, 8000, 440., 1000.)],
+            &WaveformMetadata::new(8000, 16),
+        )
+        .unwrap();
+        let compressed = compress_wav_bytes(&original, 2000, 1., |_| {}).unwrap();
+        let (compressed_file, output) = (dir.join("tone.cwv"), dir.join("tone.wav"));
+        fs::write(&compressed_file, &compressed).unwrap();
+        decompress_wav_streaming(&compressed_file, &output).unwrap();
+        assert_eq!(
+            fs::read(&output).unwrap(),
+            decompress_wav_bytes(&compressed).unwrap()
+        );
+    }
+
+    #[test]
+    fn streaming_in_frames() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let original = write_wav(vec![tone(200_000, 8000, 440., 1000.)], &metadata).unwrap();
+        let compressed = compress_wav_blocked_bytes(&original, 2000, 1., 1024, |_| {}).unwrap();
+        let mut writer = LargestWrite::default();
+        decompress_wav_to_writer(&compressed, &mut writer).unwrap();
+        // Nothing larger than a hop of samples is held for writing
+        assert_eq!(writer.0, 512 * 2);
+    }
+
+    #[test]
+    fn clamp_out_of_range() {
+        let dir = test_dir("clamp_out_of_range");
+        let ranges = [
+            (16, i16::MIN as f32, i16::MAX as f32),
+            (24, i32::MIN as f32, i32::MAX as f32),
+        ];
+        for (bit_rate, min, max) in ranges {
+            let path = dir.join(format!("ramp{bit_rate}.wav"));
+            // A ramp that overshoots the largest sample value
+            let ramp: Vec<f32> = (0..1000).map(|x| max * (0.9 + x as f32 * 0.0002)).collect();
+            let metadata = WaveformMetadata::new(8000, bit_rate);
+            write_wav_file(
+                &path,
+                vec![ramp.clone(), ramp.iter().map(|x| -x).collect()],
+                &metadata,
+            )
+            .unwrap();
+            let (_, restored) = load_wav_file(&path).unwrap();
+            for pair in restored[0].windows(2) {
+                assert!(pair[0] <= pair[1], "{bit_rate}-bit wrapped: {pair:?}");
+            }
+            for pair in restored[1].windows(2) {
+                assert!(pair[0] >= pair[1], "{bit_rate}-bit wrapped: {pair:?}");
+            }
+            assert!(restored[0][999] > max * 0.99);
+            assert!(restored[1][999] < min * 0.99);
+        }
+    }
+
+    #[test]
+    fn bit_depth_round_trip() {
+        // Each bit depth with a tone around the center of its range
+        let depths = [(8, 128., 100.), (24, 0., 1e9), (32, 0., 0.5)];
+        for (bit_rate, center, amplitude) in depths {
+            let metadata = WaveformMetadata::new(8000, bit_rate);
+            let waveform: Vec<f32> = tone(2048, 8000, 440., amplitude)
+                .iter()
+                .map(|x| x + center)
+                .collect();
+            let original = write_wav(vec![waveform], &metadata).unwrap();
+            let (_, waveform) = read_wav(&original).unwrap();
+            let compressed = compress_wav_bytes(&original, 8000, 1., |_| {}).unwrap();
+            let decompressed = decompress_wav_bytes(&compressed).unwrap();
+            let (restored_metadata, restored) = read_wav(&decompressed).unwrap();
+            assert_eq!(restored_metadata.bit_rate, bit_rate);
+            for (a, b) in waveform[0].iter().zip(&restored[0]) {
+                assert!(
+                    (a - b).abs() <= amplitude * 0.01,
+                    "{bit_rate}-bit: {a} != {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transform_trait_object() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = tone(3000, 8000, 440., 1000.)
+            .iter()
+            .zip(tone(3000, 8000, 2500., 300.))
+            .map(|(a, b)| a + b)
+            .collect();
+        let input = write_wav(vec![waveform.clone(), waveform], &metadata).unwrap();
+        // The FFT through the trait is the default transform
+        let fft: &dyn Transform = &FftTransform;
+        let compressed = compress_wav_bytes(&input, 3000, 1., |_| {}).unwrap();
+        let through_trait = compress_wav_with_transform_bytes(&input, 3000, 1., fft, |_| {});
+        assert_eq!(through_trait.unwrap(), compressed);
+        assert_eq!(
+            decompress_wav_with_transform_bytes(&compressed, fft).unwrap(),
+            decompress_wav_bytes(&compressed).unwrap()
+        );
+        // The DCT drops in, keeping twice the coefficients below the same cutoff
+        let dct: &dyn Transform = &DctTransform;
+        let compressed = compress_wav_with_transform_bytes(&input, 3000, 1., dct, |_| {}).unwrap();
+        let decoded = deserialize_compressed(&compressed).unwrap();
+        assert_eq!(decoded.coefficient_count().unwrap(), 2 * 2 * 1536);
+        // Keeping every coefficient, only the quantization is lost
+        let compressed = compress_wav_with_transform_bytes(&input, 4000, 1., dct, |_| {}).unwrap();
+        let decompressed = decompress_wav_with_transform_bytes(&compressed, dct).unwrap();
+        let error = max_sample_error(&input, &decompressed).unwrap();
+        assert!(error <= 2., "{error}");
+        assert!(decompress_wav_bytes(&compressed).is_err());
+    }
+
+    #[test]
+    fn partial_spectrum_chirp() {
+        // A chirp from 200 to 2000 Hz in the middle quarter of the signal, silence around it
+        let metadata = WaveformMetadata::new(8000, 16);
+        let window = 1536..2560;
+        let waveform: Vec<f32> = (0..4096)
+            .map(|x| match window.contains(&x) {
+                true => {
+                    let t = (x - window.start) as f32 / 8000.;
+                    (2. * PI * (200. * t + 7000. * t * t)).sin() * 3000.
+                }
+                false => 0.,
+            })
+            .collect();
+        let input = write_wav(vec![waveform], &metadata).unwrap();
+        let windowed_energy = |part: Option<SpectrumPart>| {
+            let compressed = match part {
+                Some(part) => {
+                    let transform = PartialFftTransform(part);
+                    compress_wav_with_transform_bytes(&input, 4000, 1., &transform, |_| {})
+                }
+                None => compress_wav_bytes(&input, 4000, 1., |_| {}),
+            };
+            // The altered spectrum decompresses like any other
+            let decompressed = decompress_wav_bytes(&compressed.unwrap()).unwrap();
+            let (_, channels) = read_wav(&decompressed).unwrap();
+            let energy: Vec<f32> = channels[0].iter().map(|x| x * x).collect();
+            energy[window.clone()].iter().sum::<f32>() / energy.iter().sum::<f32>()
+        };
+        assert!(windowed_energy(None) > 0.99);
+        // Without the phases the energy leaves the chirp for the ends of the signal
+        let magnitude_only = windowed_energy(Some(SpectrumPart::Magnitude));
+        assert!(magnitude_only < 0.5, "{magnitude_only}");
+        let phase_only = windowed_energy(Some(SpectrumPart::Phase));
+        assert!(
+            phase_only > magnitude_only,
+            "{phase_only} <= {magnitude_only}"
+        );
+    }
+
+    #[test]
+    fn float_round_trip() {
+        let metadata = WaveformMetadata::new(8000, 32);
+        let waveform: Vec<f32> = (0..3000)
+            .map(|x| (x as f32 * 0.05).sin() * 0.7 + (x as f32 * 0.31).cos() * 0.3)
+            .collect();
+        let original = write_wav(vec![waveform.clone()], &metadata).unwrap();
+        let compressed = compress_wav_bytes(&original, 4000, 1., |_| {}).unwrap();
+        let decompressed = decompress_wav_bytes(&compressed).unwrap();
+        let (header, data) = wav::read(&mut Cursor::new(&decompressed)).unwrap();
+        assert_eq!(header.audio_format, WAV_FORMAT_IEEE_FLOAT);
+        let BitDepth::ThirtyTwoFloat(restored) = data else {
+            panic!("expected float samples");
+        };
+        // Not rounded to integer steps, only to those of the quantized frequencies
+        for (a, b) in waveform.iter().zip(&restored) {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+        let compressed = compress_wav_lossless_bytes(&original, 4000, 1., |_| {}).unwrap();
+        let (_, restored) = read_wav(&decompress_wav_bytes(&compressed).unwrap()).unwrap();
+        assert_eq!(restored[0], waveform);
+    }
+
+    #[test]
+    fn quantization_reduces_size() {
+        let dir = test_dir("quantization_reduces_size");
+        let original = dir.join("original.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = tone(8000, 8000, 440., 3000.)
+            .iter()
+            .zip(tone(8000, 8000, 1250., 2000.))
+            .enumerate()
+            .map(|(x, (a, b))| a + b + ((x * 7919) % 1000) as f32)
+            .collect();
+        write_wav_file(&original, vec![waveform], &metadata).unwrap();
+        let sizes: Vec<u64> = [1., 10., 100., 1000.]
+            .iter()
+            .map(|quantization| {
+                let compressed = dir.join(format!("compressed{quantization}.cwv"));
+                compress_wav(&original, &compressed, 4000, *quantization, |_| {}).unwrap();
+                std::fs::metadata(&compressed).unwrap().len()
+            })
+            .collect();
+        for pair in sizes.windows(2) {
+            assert!(pair[1] < pair[0], "{sizes:?}");
+        }
+        let invalid = compress_wav(&original, &dir.join("invalid.cwv"), 4000, 0.5, |_| {});
+        assert!(matches!(
+            invalid,
+            Err(CompressionError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn silence_compresses() {
+        let dir = test_dir("silence_compresses");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let decompressed = dir.join("decompressed.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let mut waveform = vec![0.; 16384];
+        waveform[1000..1100].copy_from_slice(&tone(100, 8000, 440., 1000.));
+        write_wav_file(&original, vec![waveform], &metadata).unwrap();
+        compress_wav_blocked(&original, &compressed, 8000, 100., 256, |_| {}).unwrap();
+        // Each retained frequency of each frame would otherwise take at least 2 bytes
+        let compressed_size = std::fs::metadata(&compressed).unwrap().len();
+        assert!(compressed_size < 16384 / 4);
+        decompress_wav(&compressed, &decompressed).unwrap();
+        let (_, restored) = load_wav_file(&decompressed).unwrap();
+        assert_eq!(restored[0].len(), 16384);
+        assert!(restored[0][..768].iter().all(|sample| *sample == 0.));
+        assert!(restored[0][1280..].iter().all(|sample| *sample == 0.));
+    }
+
+    #[test]
+    fn stats_match_file_sizes() {
+        let dir = test_dir("stats_match_file_sizes");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let metadata = WaveformMetadata::new(8000, 16);
+        write_wav_file(&original, vec![tone(4096, 8000, 440., 1000.)], &metadata).unwrap();
+        let stats = compress_wav(&original, &compressed, 2000, 1., |_| {}).unwrap();
+        let original_bytes = fs::metadata(&original).unwrap().len();
+        let compressed_bytes = fs::metadata(&compressed).unwrap().len();
+        assert_eq!(stats.original_bytes, original_bytes);
+        assert_eq!(stats.compressed_bytes, compressed_bytes);
+        assert_eq!(stats.ratio, original_bytes as f64 / compressed_bytes as f64);
+    }
+
+    #[test]
+    fn options_builder() {
+        let dir = test_dir("options_builder");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let metadata = WaveformMetadata::new(8000, 16);
+        write_wav_file(&original, vec![tone(4096, 8000, 440., 1000.)], &metadata).unwrap();
+        let input = fs::read(&original).unwrap();
+
+        // The options of the other functions give the same files
+        let options = CompressOptions::new()
+            .freq_cutoff(3000)
+            .quantization(2.)
+            .frame_size(512);
+        let stats = compress_wav_with(&original, &compressed, &options, |_| {}).unwrap();
+        assert_eq!(stats.cutoff_snr, None);
+        assert_eq!(
+            read_wav_metadata(&compressed).unwrap().frame_size,
+            Some(512)
+        );
+        let blocked = compress_wav_blocked_bytes(&input, 3000, 2., 512, |_| {}).unwrap();
+        assert_eq!(fs::read(&compressed).unwrap(), blocked);
+        let curve = vec![(0., 4.), (1000., 1.)];
+        let options = CompressOptions::new()
+            .quantization_curve(curve.clone())
+            .freq_cutoff(3000);
+        let weighted = compress_wav_weighted_bytes(&input, 3000, 1., &curve, |_| {}).unwrap();
+        assert_eq!(
+            compress_wav_with_bytes(&input, &options, |_| {}).unwrap(),
+            weighted
+        );
+        let stats = compress_wav_with(&original, &compressed, &options, |_| {}).unwrap();
+        assert!(stats.cutoff_snr.is_some());
+        let result = compress_wav_with_bytes(&input, &options.frame_size(512), |_| {});
+        assert!(
+            matches!(result, Err(CompressionError::InvalidParameter(_))),
+            "{result:?}"
+        );
+        // Curves are checked like those of compress_wav_weighted
+        for curve in [
+            vec![(0., 0.)],
+            vec![(0., 1.), (1000., 3000.)],
+            vec![(1000., 1.), (0., 2.)],
+            vec![(0., f32::NAN)],
+        ] {
+            let options = CompressOptions::new().quantization_curve(curve.clone());
+            let result = compress_wav_with_bytes(&input, &options, |_| {});
+            assert!(
+                matches!(result, Err(CompressionError::InvalidParameter(_))),
+                "{curve:?}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn combined_spectra() {
+        let dir = test_dir("combined_spectra");
+        let files = [("low.wav", 8000, 440.), ("high.wav", 16000, 5000.)].map(
+            |(name, sample_rate, frequency)| {
+                let path = dir.join(name);
+                let metadata = WaveformMetadata::new(sample_rate, 16);
+                let waveform = tone(4096, sample_rate, frequency, 1000.);
+                write_wav_file(&path, vec![waveform], &metadata).unwrap();
+                path
+            },
+        );
+        let report = analyze_waveforms(
+            &files,
+            &dir,
+            WindowKind::Hann,
+            fft::PadMode::Zero,
+            PlotConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(report, dir.join("low_comparison.html"));
+        let html = fs::read_to_string(&report).unwrap();
+        assert!(html.contains(r#""name": "low.wav""#));
+        assert!(html.contains(r#""name": "high.wav""#));
+        assert!(html.contains(r#""yaxis": "y2""#));
+        assert!(html.contains("Frequency (Hz)"));
+        let no_files = analyze_waveforms(
+            &[],
+            &dir,
+            WindowKind::Hann,
+            fft::PadMode::Zero,
+            PlotConfig::default(),
+        );
+        assert!(matches!(no_files, Err(CompressionError::EmptyInput)));
+    }
+
+    #[test]
+    fn cutoff_energy_ratio() {
+        let bins = [3., 4., 0., 5.].map(Complex32::from);
+        // 9 + 16 retained of 25 discarded
+        assert!(cutoff_snr(&bins, 2).abs() < 1e-4);
+        let bins = [100., 50., 0.5].map(Complex32::from);
+        assert!(cutoff_snr(&bins, 2) > 40.);
+        assert_eq!(cutoff_snr(&bins, 3), f32::INFINITY);
+
+        // A tone far below the cutoff loses almost nothing to it
+        let dir = test_dir("cutoff_energy_ratio");
+        let original = dir.join("original.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        write_wav_file(&original, vec![tone(4096, 8000, 440., 1000.)], &metadata).unwrap();
+        let compressed = dir.join("compressed.cwv");
+        let stats = compress_wav(&original, &compressed, 2000, 1., |_| {}).unwrap();
+        assert!(stats.cutoff_snr.unwrap() > 20., "{stats:?}");
+        let stats = compress_wav(&original, &compressed, 200, 1., |_| {}).unwrap();
+        assert!(stats.cutoff_snr.unwrap() < 0., "{stats:?}");
+    }
+
+    fn decompress_error(name: &str, contents: &[u8]) -> FormatError {
+        let dir = test_dir(name);
+        let compressed = dir.join("compressed.cwv");
+        std::fs::write(&compressed, contents).unwrap();
+        match decompress_wav(&compressed, &dir.join("decompressed.wav")) {
+            Err(CompressionError::Format(error)) => error,
+            result => panic!("expected a format error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_header() {
+        let truncated = decompress_error("truncated_header", b"CWV");
+        assert!(matches!(truncated, FormatError::Truncated));
+        let bmp_header = decompress_error("bmp_header", b"CBM1\x01\x00\x00");
+        assert!(matches!(bmp_header, FormatError::BadMagic));
+        let old_version = decompress_error("old_version", b"CWV1\x01\x00\x00");
+        assert!(matches!(old_version, FormatError::UnsupportedVersion(1)));
+        let old_version = decompress_error("old_version", b"CWV1\x09\x00\x00");
+        assert!(matches!(old_version, FormatError::UnsupportedVersion(9)));
+        let future_version = decompress_error("future_version", b"CWV1\x0b\x00\x00");
+        assert!(matches!(
+            future_version,
+            FormatError::UnsupportedVersion(11)
+        ));
+        let missing_footer = decompress_error("missing_footer", b"CWV1\x0a\x00\x00");
+        assert!(matches!(missing_footer, FormatError::Truncated));
+    }
+
+    #[test]
+    fn corrupted_data() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let original = write_wav(vec![tone(2048, 8000, 440., 1000.)], &metadata).unwrap();
+        let compressed = compress_wav_bytes(&original, 2000, 1., |_| {}).unwrap();
+        let mut flipped = compressed.clone();
+        flipped[compressed.len() / 2] ^= 0x10;
+        let flipped = decompress_error("flipped_byte", &flipped);
+        assert!(matches!(flipped, FormatError::Corrupted));
+        let truncated = decompress_error("truncated_data", &compressed[..compressed.len() - 10]);
+        assert!(matches!(truncated, FormatError::Corrupted));
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        for (bit_rate, amplitude, offset) in [
+            (8, 100., 128.),
+            (16, 8000., 0.),
+            (24, 2e9, 0.),
+            (32, 0.5, 0.),
+        ] {
+            let metadata = WaveformMetadata::new(8000, bit_rate);
+            let channels = [(440., 1.), (1000., 0.5)]
+                .iter()
+                .map(|(frequency, scale)| {
+                    tone(3000, 8000, *frequency, amplitude * scale)
+                        .iter()
+                        .map(|x| x + offset)
+                        .collect()
+                })
+                .collect();
+            let original = write_wav(channels, &metadata).unwrap();
+            let compressed = compress_wav_lossless_bytes(&original, 2000, 4., |_| {}).unwrap();
+            assert_eq!(
+                decompress_wav_bytes(&compressed).unwrap(),
+                original,
+                "{bit_rate}"
+            );
+            if bit_rate == 16 {
+                let lossy = compress_wav_bytes(&original, 2000, 4., |_| {}).unwrap();
+                assert!(lossy.len() < compressed.len());
+                assert!(compressed.len() < original.len());
+            }
+        }
+    }
+
+    #[test]
+    fn in_memory_round_trip() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform = tone(2048, 8000, 440., 1000.);
+        let original = write_wav(vec![waveform.clone()], &metadata).unwrap();
+        let compressed = compress_wav_bytes(&original, 8000, 1., |_| {}).unwrap();
+        let decompressed = decompress_wav_bytes(&compressed).unwrap();
+        let (_, restored) = read_wav(&decompressed).unwrap();
+        for (a, b) in waveform.iter().zip(&restored[0]) {
+            assert!((a - b).abs() <= 2., "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let channels = vec![tone(3000, 8000, 440., 1000.); 2];
+        let original = write_wav(channels, &metadata).unwrap();
+        let compressed = compress_wav_bytes(&original, 2000, 1., |_| {}).unwrap();
+        let info = read_wav_metadata_bytes(&compressed).unwrap();
+        let expected = CompressedInfo {
+            sample_rate: 8000,
+            bit_rate: 16,
+            channel_count: 2,
+            original_size: 3000,
+            frame_size: None,
+            // The bins below 2000 Hz of 4096 padded samples
+            coefficient_count: 2 * 1024,
+            lossless: false,
+        };
+        assert_eq!(info, expected);
+        let compressed = compress_wav_topk_bytes(&original, 0.05, |_| {}).unwrap();
+        assert_eq!(
+            read_wav_metadata_bytes(&compressed)
+                .unwrap()
+                .coefficient_count,
+            2 * 103
+        );
+        let compressed = compress_wav_blocked_bytes(&original, 8000, 1., 512, |_| {}).unwrap();
+        let info = read_wav_metadata_bytes(&compressed).unwrap();
+        assert_eq!(info.frame_size, Some(512));
+        assert_eq!(
+            info.coefficient_count,
+            2 * frame_starts(3000, 512).count() * 257
+        );
+    }
+
+    #[test]
+    fn quantization_relative_to_peak() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let quiet: Vec<f32> = tone(4096, 8000, 440., 100.)
+            .iter()
+            .map(|x| x.round())
+            .collect();
+        // Scaling by a power of two is exact, so the transforms scale exactly too
+        let loud: Vec<f32> = quiet.iter().map(|x| x * 8.).collect();
+        let compress = |waveform: Vec<f32>| {
+            let original = write_wav(vec![waveform], &metadata).unwrap();
+            compress_wav_bytes(&original, 2000, 4., |_| {}).unwrap()
+        };
+        let (quiet, loud) = (compress(quiet), compress(loud));
+        let decode = |compressed: &[u8]| -> CompressedData {
+            let encoded = header::strip_header(WAV_MAGIC, WAV_VERSION, compressed).unwrap();
+            bincode::DefaultOptions::new().deserialize(encoded).unwrap()
+        };
+        let (quiet_data, loud_data) = (decode(&quiet), decode(&loud));
+        assert_eq!(
+            quiet_data.channels().unwrap(),
+            loud_data.channels().unwrap()
+        );
+        assert_eq!(
+            quiet_data.quantization_step * 8.,
+            loud_data.quantization_step
+        );
+        let (_, quiet) = decompress_channels(&quiet, &FftTransform).unwrap();
+        let (_, loud) = decompress_channels(&loud, &FftTransform).unwrap();
+        let scaled: Vec<f32> = quiet[0].iter().map(|x| x * 8.).collect();
+        assert_eq!(scaled, loud[0]);
+    }
+
+    #[test]
+    fn real_inverse() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform = tone(3000, 8000, 440., 1000.);
+        let original = write_wav(vec![waveform], &metadata).unwrap();
+        let compressed = compress_wav_bytes(&original, 1000, 1., |_| {}).unwrap();
+        let encoded = header::strip_header(WAV_MAGIC, WAV_VERSION, &compressed).unwrap();
+        let decoded: CompressedData = bincode::DefaultOptions::new().deserialize(encoded).unwrap();
+        let frame = &decoded.channels().unwrap()[0][0];
+        let restored = decompress_frame(
+            frame,
+            &decoded.bin_steps().unwrap(),
+            &decoded,
+            &FftTransform,
+        )
+        .unwrap();
+        // The full spectrum of the retained bins, zeros and their conjugate mirror
+        let step = decoded.quantization_step;
+        let mut spectrum: Vec<Complex32> = frame
+            .iter()
+            .map(|(r, i)| Complex32::new(*r as f32, *i as f32) * step)
+            .collect();
+        let transform_size = decoded.transform_size();
+        spectrum.resize(transform_size / 2 + 1, Complex32::default());
+        let mirror: Vec<Complex32> = spectrum[1..transform_size / 2]
+            .iter()
+            .rev()
+            .map(|x| x.conj())
+            .collect();
+        spectrum.extend(mirror);
+        let inverse = fft::fft_inverse(&spectrum).unwrap();
+        let rms = (inverse.iter().map(|x| x.im * x.im).sum::<f32>() / inverse.len() as f32).sqrt();
+        assert!(rms < 1e-3, "imaginary RMS {rms}");
+        for (a, b) in restored.iter().zip(&inverse) {
+            assert!((a - b.re).abs() < 1e-2, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn cutoff_preserves_amplitude() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform = tone(4096, 8000, 440., 1000.);
+        let original = write_wav(vec![waveform.clone()], &metadata).unwrap();
+        let rms = |w: &[f32]| (w.iter().map(|x| x * x).sum::<f32>() / w.len() as f32).sqrt();
+        for compressed in [
+            compress_wav_bytes(&original, 1000, 1., |_| {}).unwrap(),
+            compress_wav_blocked_bytes(&original, 1000, 1., 1024, |_| {}).unwrap(),
+        ] {
+            let decompressed = decompress_wav_bytes(&compressed).unwrap();
+            let (_, restored) = read_wav(&decompressed).unwrap();
+            let (expected, actual) = (rms(&waveform), rms(&restored[0]));
+            assert!(
+                (expected - actual).abs() < expected * 0.05,
+                "{expected} != {actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn compress_to_size() {
+        let dir = test_dir("compress_to_size");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = tone(8000, 8000, 440., 3000.)
+            .iter()
+            .enumerate()
+            .map(|(x, a)| a + ((x * 7919) % 1000) as f32)
+            .collect();
+        write_wav_file(&original, vec![waveform], &metadata).unwrap();
+        let full = compress_wav(&original, &compressed, 4000, 1., |_| {}).unwrap();
+        let target = full.compressed_bytes / 3;
+        let mut fractions = Vec::new();
+        let stats =
+            compress_wav_to_size(&original, &compressed, target, |f| fractions.push(f)).unwrap();
+        assert!(stats.compressed_bytes <= target);
+        assert!(stats.compressed_bytes * 100 >= target * 95, "{stats:?}");
+        assert_eq!(
+            fs::metadata(&compressed).unwrap().len(),
+            stats.compressed_bytes
+        );
+        let freq_cutoff = stats.freq_cutoff.unwrap();
+        assert!(freq_cutoff > 0 && freq_cutoff < 4000);
+        // At most one attempt for each bit of the Nyquist frequency
+        assert!(fractions.len() <= 13);
+        assert_eq!(fractions.last(), Some(&1.));
+        decompress_wav(&compressed, &dir.join("decompressed.wav")).unwrap();
+        assert!(matches!(
+            compress_wav_to_size(&original, &compressed, 10, |_| {}),
+            Err(CompressionError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn topk_preserves_energy() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        // A strong high tone that a low frequency cutoff would drop
+        let waveform: Vec<f32> = tone(8192, 8000, 100., 3000.)
+            .iter()
+            .zip(tone(8192, 8000, 3000., 3000.))
+            .enumerate()
+            .map(|(x, (a, b))| a + b + ((x * 7919) % 100) as f32)
+            .collect();
+        let original = write_wav(vec![waveform.clone()], &metadata).unwrap();
+        let energy = |w: &[f32]| w.iter().map(|x| x * x).sum::<f32>();
+        let error_energy = |compressed: &[u8]| {
+            let (_, restored) = read_wav(&decompress_wav_bytes(compressed).unwrap()).unwrap();
+            let errors: Vec<f32> = waveform
+                .iter()
+                .zip(&restored[0])
+                .map(|(a, b)| a - b)
+                .collect();
+            energy(&errors) / energy(&waveform)
+        };
+        // Keep 5% of the 4097 bins, either the largest or the lowest
+        let topk = compress_wav_topk_bytes(&original, 0.05, |_| {}).unwrap();
+        let kept_count = (4097. * 0.05f32).ceil();
+        let freq_cutoff = (kept_count * 8000. / 8192.) as usize;
+        let cutoff = compress_wav_bytes(&original, freq_cutoff, 1., |_| {}).unwrap();
+        let (topk_error, cutoff_error) = (error_energy(&topk), error_energy(&cutoff));
+        assert!(topk_error < 0.05, "{topk_error}");
+        assert!(
+            topk_error * 5. < cutoff_error,
+            "{topk_error} vs {cutoff_error}"
+        );
+        for fraction in [0., 1.5, f32::NAN] {
+            assert!(matches!(
+                compress_wav_topk_bytes(&original, fraction, |_| {}),
+                Err(CompressionError::InvalidParameter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn dither_whitens_error() {
+        let metadata = WaveformMetadata::new(8000, 8);
+        // A quiet tone spanning a few quantization steps, periodic in the sample
+        let waveform: Vec<f32> = tone(4096, 8000, 8000. * 37. / 4096., 2.7)
+            .iter()
+            .map(|x| x + 128.3)
+            .collect();
+        // Spectral flatness of the quantization error: near 0 for tones, higher for noise
+        let flatness = |bytes: &[u8]| {
+            let (_, restored) = read_wav(bytes).unwrap();
+            let errors: Vec<f32> = waveform
+                .iter()
+                .zip(&restored[0])
+                .map(|(a, b)| b - a)
+                .collect();
+            let spectrum = fft::rfft(&errors).unwrap();
+            let power: Vec<f64> = spectrum[1..]
+                .iter()
+                .map(|c| c.norm_sqr() as f64 + 1e-12)
+                .collect();
+            let geometric = (power.iter().map(|p| p.ln()).sum::<f64>() / power.len() as f64).exp();
+            geometric / (power.iter().sum::<f64>() / power.len() as f64)
+        };
+        let plain = write_wav(vec![waveform.clone()], &metadata).unwrap();
+        let dithered = write_wav_dithered(vec![waveform.clone()], &metadata, Some(7)).unwrap();
+        let (plain_flatness, dithered_flatness) = (flatness(&plain), flatness(&dithered));
+        assert!(plain_flatness < 0.1, "{plain_flatness}");
+        assert!(dithered_flatness > 0.3, "{dithered_flatness}");
+        let again = write_wav_dithered(vec![waveform], &metadata, Some(7)).unwrap();
+        assert_eq!(dithered, again);
+    }
+
+    #[test]
+    fn progress_increases() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform = tone(4096, 8000, 440., 1000.);
+        let original = write_wav(vec![waveform.clone(), waveform], &metadata).unwrap();
+        let mut fractions = Vec::new();
+        compress_wav_blocked_bytes(&original, 8000, 1., 1024, |f| fractions.push(f)).unwrap();
+        // Two channels of 9 frames each (covering the padding), and the final encoding step
+        assert_eq!(fractions.len(), 19);
+        assert!(fractions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(fractions.last(), Some(&1.));
+    }
+
+    #[test]
+    fn analysis_spectrogram() {
+        let dir = test_dir("analysis_spectrogram");
+        let original = dir.join("original.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        write_wav_file(&original, vec![tone(8000, 8000, 440., 1000.)], &metadata).unwrap();
+        let analysis = analyze_waveform(
+            &original,
+            &dir,
+            WindowKind::Hann,
+            fft::PadMode::Reflect,
+            PlotConfig::default(),
+        )
+        .unwrap();
+        let html = fs::read_to_string(&analysis.path).unwrap();
+        assert!(html.contains(r#""type": "heatmap""#));
+        let (frequency, _) = analysis.dominant_frequencies[0];
+        assert!((frequency - 440.).abs() < 1., "{frequency} Hz");
+        assert!(html.contains(r#""width": 1900"#));
+        let config = PlotConfig {
+            width: Some(1280),
+            height: None,
+            dark_mode: true,
+        };
+        let analysis = analyze_waveform(
+            &original,
+            &dir,
+            WindowKind::Hann,
+            fft::PadMode::Reflect,
+            config,
+        )
+        .unwrap();
+        let html = fs::read_to_string(&analysis.path).unwrap();
+        assert!(html.contains(r#""width": 1280"#));
+        assert!(html.contains(r#""height": 1200"#));
+        assert!(html.contains(r#""template""#));
+    }
+
+    #[test]
+    fn analysis_overlay() {
+        let dir = test_dir("analysis_overlay");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = tone(3000, 8000, 440., 1000.)
+            .iter()
+            .zip(tone(3000, 8000, 3000., 500.))
+            .map(|(a, b)| a + b)
+            .collect();
+        write_wav_file(&original, vec![waveform], &metadata).unwrap();
+        compress_wav(&original, &compressed, 2000, 1., |_| {}).unwrap();
+        let (window, pad) = (WindowKind::Hann, fft::PadMode::Reflect);
+        let frequency_traces = |html: &str| html.matches(r#""xaxis": "x2""#).count();
+        let analysis = analyze_waveform(&original, &dir, window, pad, PlotConfig::default());
+        let html = fs::read_to_string(analysis.unwrap().path).unwrap();
+        assert_eq!(frequency_traces(&html), 1);
+        let analysis = analyze_waveform_overlay(
+            &original,
+            &compressed,
+            &dir,
+            window,
+            pad,
+            PlotConfig::default(),
+        )
+        .unwrap();
+        let html = fs::read_to_string(&analysis.path).unwrap();
+        assert_eq!(frequency_traces(&html), 2);
+        assert!(html.contains(r#""name": "Reconstructed""#));
+        // The dominant frequencies are those of the original, above the cutoff too
+        assert!(analysis
+            .dominant_frequencies
+            .iter()
+            .any(|(frequency, _)| (frequency - 3000.).abs() < 5.));
+    }
+
+    #[test]
+    fn analysis_file_names() {
+        let dir = test_dir("analysis_file_names");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let analyses: Vec<PathBuf> = [("low", 300.), ("high", 2000.)]
+            .into_iter()
+            .map(|(name, frequency)| {
+                let original = dir.join(format!("{name}.wav"));
+                let waveform = tone(4096, 8000, frequency, 1000.);
+                write_wav_file(&original, vec![waveform], &metadata).unwrap();
+                let (window, pad) = (WindowKind::Hann, fft::PadMode::Reflect);
+                let config = PlotConfig::default();
+                analyze_waveform(&original, &dir, window, pad, config)
+                    .unwrap()
+                    .path
+            })
+            .collect();
+        assert_eq!(analyses[0], dir.join("low_analysis.html"));
+        assert_eq!(analyses[1], dir.join("high_analysis.html"));
+        assert!(analyses.iter().all(|analysis| analysis.is_file()));
+    }
+
+    #[test]
+    fn empty_waveform() {
+        let empty = write_wav(vec![vec![]], &WaveformMetadata::new(8000, 16)).unwrap();
+        let result = compress_wav_bytes(&empty, 8000, 1., |_| {});
+        assert!(
+            matches!(result, Err(CompressionError::EmptyInput)),
+            "{result:?}"
+        );
+        let result = compress_wav_blocked_bytes(&empty, 8000, 1., 1024, |_| {});
+        assert!(
+            matches!(result, Err(CompressionError::EmptyInput)),
+            "{result:?}"
+        );
+    }
+
+    #[cfg(feature = "flac")]
+    #[test]
+    fn flac_input() {
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tone.flac");
+        let flac = fs::read(fixture).unwrap();
+        let (metadata, channels) = read_wav(&flac).unwrap();
+        assert_eq!(metadata.sample_rate, 8000);
+        assert_eq!(metadata.bit_rate, 16);
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].len(), 1000);
+        // The fixture is a 440 Hz tone with an amplitude of 1000
+        let expected = tone(1000, 8000, 440., 1000.);
+        for (x, y) in channels[0].iter().zip(expected.iter()) {
+            assert!((x - y).abs() <= 1.5, "{x} != {y}");
+        }
+        let compressed = compress_wav_bytes(&flac, 8000, 1., |_| {}).unwrap();
+        let (_, restored) = read_wav(&decompress_wav_bytes(&compressed).unwrap()).unwrap();
+        assert_eq!(restored[0].len(), 1000);
+    }
+
+    #[cfg(feature = "mp3")]
+    #[test]
+    fn mp3_output() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let (left, right) = (tone(4000, 8000, 440., 1000.), tone(4000, 8000, 300., 2000.));
+        for channels in [vec![left.clone()], vec![left.clone(), right.clone()]] {
+            let original = write_wav(channels, &metadata).unwrap();
+            let compressed = compress_wav_bytes(&original, 4000, 1., |_| {}).unwrap();
+            let mp3 = decompress_mp3_bytes(&compressed).unwrap();
+            // Starts with the 11 set bits that synchronize an MPEG audio frame
+            assert!(mp3.len() > 4, "{mp3:?}");
+            assert_eq!((mp3[0], mp3[1] & 0xe0), (0xff, 0xe0));
+        }
+        let original = write_wav(vec![left.clone(), right, left], &metadata).unwrap();
+        let compressed = compress_wav_bytes(&original, 4000, 1., |_| {}).unwrap();
+        assert!(matches!(
+            decompress_mp3_bytes(&compressed),
+            Err(CompressionError::Format(FormatError::UnsupportedChannels))
+        ));
+    }
+
+    #[test]
+    fn spectral_diff_of_levels() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = tone(3000, 8000, 440., 1000.)
+            .iter()
+            .zip(tone(3000, 8000, 3000., 500.))
+            .map(|(a, b)| a + b)
+            .collect();
+        let original = write_wav(vec![waveform], &metadata).unwrap();
+        let full = compress_wav_bytes(&original, 4000, 1., |_| {}).unwrap();
+        let half = compress_wav_bytes(&original, 2000, 1., |_| {}).unwrap();
+        let diff = spectral_diff_bytes(&full, &full).unwrap();
+        // Bins up to (not including) the Nyquist frequency of 4000 Hz
+        assert_eq!(diff.len(), 4096 / 2);
+        assert!(diff.iter().all(|x| *x == 0.));
+        // Only the bins below 2000 Hz are compared, which both files keep
+        let diff = spectral_diff_bytes(&full, &half).unwrap();
+        assert_eq!(diff.len(), 1024);
+        assert!(diff.iter().all(|x| *x == 0.));
+        let framed = compress_wav_blocked_bytes(&original, 4000, 1., 1024, |_| {}).unwrap();
+        assert!(spectral_diff_bytes(&full, &framed).is_err());
+    }
+
+    #[test]
+    fn resample_file() {
+        let metadata = WaveformMetadata::new(48000, 16);
+        let (left, right) = (
+            tone(4800, 48000, 440., 1000.),
+            tone(4800, 48000, 300., 2000.),
+        );
+        let original = write_wav(vec![left, right], &metadata).unwrap();
+        let resampled = resample_wav_bytes(&original, 44100).unwrap();
+        let (metadata, channels) = read_wav(&resampled).unwrap();
+        assert_eq!((metadata.sample_rate, metadata.bit_rate), (44100, 16));
+        assert_eq!(channels.len(), 2);
+        let expected = tone(4410, 44100, 300., 2000.);
+        for (x, y) in channels[1].iter().zip(&expected) {
+            assert!((x - y).abs() <= 1., "{x} != {y}");
+        }
+        assert!(resample_wav_bytes(&original, 0).is_err());
+    }
+
+    #[test]
+    fn invalid_freq_cutoff() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let original = write_wav(vec![tone(2048, 8000, 440., 1000.)], &metadata).unwrap();
+        // As derived from a compression level by the CLI
+        for level in [f32::NAN, -5.] {
+            let freq_cutoff = (4000. / level).ceil() as usize;
+            for result in [
+                compress_wav_bytes(&original, freq_cutoff, 1., |_| {}),
+                compress_wav_blocked_bytes(&original, freq_cutoff, 1., 512, |_| {}),
+            ] {
+                assert!(matches!(
+                    result,
+                    Err(CompressionError::InvalidParameter(
+                        "frequency cutoff must be greater than 0"
+                    ))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_quantization() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        // Tones and noise across the whole spectrum
+        let waveform: Vec<f32> = tone(8192, 8000, 500., 2000.)
+            .iter()
+            .zip(tone(8192, 8000, 3000., 2000.))
+            .enumerate()
+            .map(|(x, (a, b))| a + b + ((x * 7919) % 1000) as f32 - 500.)
+            .collect();
+        let original = write_wav(vec![waveform], &metadata).unwrap();
+        // Significant bits of the retained frequencies below 1000 Hz and above 2000 Hz
+        let band_bits = |compressed: &[u8]| {
+            let decoded = deserialize_compressed(compressed).unwrap();
+            let frame = &decoded.channels().unwrap()[0][0];
+            let resolution = metadata.freq_resolution(decoded.transform_size());
+            let bits = |range: std::ops::Range<f32>| -> u32 {
+                frame
+                    .iter()
+                    .enumerate()
+                    .filter(|(bin, _)| range.contains(&(*bin as f32 * resolution)))
+                    .flat_map(|(_, (re, im))| [*re, *im])
+                    .map(|value| 16 - value.unsigned_abs().leading_zeros())
+                    .sum()
+            };
+            (bits(0.0..1000.), bits(2000.0..4001.))
+        };
+        let flat = band_bits(&compress_wav_bytes(&original, 4000, 1., |_| {}).unwrap());
+        let curve = parse_quantization_curve("0:1, 1000:1, 2000:16 4000:16").unwrap();
+        let weighted = compress_wav_weighted_bytes(&original, 4000, 1., &curve, |_| {}).unwrap();
+        let (gentle, aggressive) = band_bits(&weighted);
+        // The higher band is as wide as the lower one, but keeps fewer bits
+        assert!(aggressive < gentle, "{aggressive} >= {gentle}");
+        assert!(aggressive < flat.1, "{aggressive} >= {}", flat.1);
+        assert_eq!(gentle, flat.0);
+        // The steps are restored from the file
+        let error = max_sample_error(&original, &decompress_wav_bytes(&weighted).unwrap()).unwrap();
+        assert!(error < 500., "{error}");
+
+        for invalid in ["", "0:1 100", "1000:1 0:2", "0:0.5", "0:4000"] {
+            assert!(matches!(
+                parse_quantization_curve(invalid),
+                Err(CompressionError::InvalidParameter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn progressive_decode() {
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = [(440., 1000.), (1200., 400.), (3000., 100.)]
+            .iter()
+            .map(|(frequency, amplitude)| tone(3000, 8000, *frequency, *amplitude))
+            .reduce(|a, b| a.iter().zip(b).map(|(a, b)| a + b).collect())
+            .unwrap();
+        let original = write_wav(vec![waveform.clone(), waveform.clone()], &metadata).unwrap();
+        let compressed = compress_wav_progressive_bytes(&original, 4000, 1., |_| {}).unwrap();
+        let rms_error = |fraction: f32| {
+            let decompressed = decompress_wav_partial_bytes(&compressed, fraction).unwrap();
+            let (_, channels) = read_wav(&decompressed).unwrap();
+            let squares: f32 = channels
+                .iter()
+                .flat_map(|channel| channel.iter().zip(&waveform))
+                .map(|(x, y)| (x - y).powi(2))
+                .sum();
+            (squares / (2 * waveform.len()) as f32).sqrt()
+        };
+        let errors: Vec<f32> = [0., 0.01, 0.05, 0.2, 0.5, 1.]
+            .into_iter()
+            .map(rms_error)
+            .collect();
+        assert!(errors.windows(2).all(|w| w[1] < w[0]), "{errors:?}");
+        // All the frequencies restore the same waveform as without the order
+        let plain = compress_wav_bytes(&original, 4000, 1., |_| {}).unwrap();
+        assert_eq!(
+            decompress_wav_partial_bytes(&compressed, 1.).unwrap(),
+            decompress_wav_bytes(&plain).unwrap()
+        );
+        assert_eq!(
+            decompress_wav_bytes(&compressed).unwrap(),
+            decompress_wav_bytes(&plain).unwrap()
+        );
+        assert!(decompress_wav_partial_bytes(&compressed, 1.5).is_err());
+        assert!(decompress_wav_partial_bytes(&plain, 0.5).is_err());
+    }
+
+    #[test]
+    fn round_samples() {
+        let waveform = vec![0.6, 1.4, -0.6, 2.5];
+        assert_eq!(sample_values(waveform.clone(), 16).unwrap(), [1, 1, -1, 3]);
+        let unsigned: Vec<f32> = waveform.iter().map(|x| x + 128.).collect();
+        assert_eq!(sample_values(unsigned, 8).unwrap(), [129, 129, 127, 131]);
+        let upper_bytes: Vec<f32> = waveform.iter().map(|x| x * 256.).collect();
+        assert_eq!(sample_values(upper_bytes, 24).unwrap(), [1, 1, -1, 3]);
+    }
+
+    #[test]
+    fn raw_pcm_round_trip() {
+        let dir = test_dir("raw_pcm_round_trip");
+        let original = dir.join("original.pcm");
+        let compressed = dir.join("compressed.cwv");
+        let decompressed = dir.join("decompressed.pcm");
+        let format = PcmFormat {
+            sample_rate: 8000,
+            bit_depth: 16,
+            channel_count: 2,
+        };
+        let (left, right) = (tone(3000, 8000, 440., 1000.), tone(3000, 8000, 300., 2000.));
+        let raw: Vec<u8> = left
+            .iter()
+            .zip(right.iter())
+            .flat_map(|(l, r)| [*l as i16, *r as i16])
+            .flat_map(i16::to_le_bytes)
+            .collect();
+        fs::write(&original, &raw).unwrap();
+        // Keep every frequency bin so the channels are only affected by quantization
+        compress_raw_pcm(&original, &compressed, format, 8000, 1., |_| {}).unwrap();
+        decompress_raw_pcm(&compressed, &decompressed).unwrap();
+        let restored = fs::read(&decompressed).unwrap();
+        assert_eq!(restored.len(), raw.len());
+        let (_, channels) = read_raw_pcm(&raw, format).unwrap();
+        let (_, restored) = read_raw_pcm(&restored, format).unwrap();
+        for (channel, restored) in channels.iter().zip(restored.iter()) {
+            for (x, y) in channel.iter().zip(restored.iter()) {
+                assert!((x - y).abs() <= 10., "{x} != {y}");
+            }
+        }
+        // Reading and writing are exact at every bit depth
+        for bit_depth in [8, 16, 24, 32] {
+            let format = PcmFormat {
+                bit_depth,
+                ..format
+            };
+            let raw: Vec<u8> = (0..bit_depth / 8 * 2 * 50)
+                .map(|x| (x * 37 % 256) as u8)
+                .collect();
+            let (metadata, channels) = read_raw_pcm(&raw, format).unwrap();
+            assert_eq!(channels[0].len(), 50);
+            assert_eq!(write_raw_pcm(channels, &metadata).unwrap(), raw);
+        }
+        assert!(read_raw_pcm(&raw[..raw.len() - 1], format).is_err());
+    }
+
+    #[test]
+    fn downmix_to_mono() {
+        let dir = test_dir("downmix_to_mono");
+        let original = dir.join("original.wav");
+        let downmixed = dir.join("downmixed.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let left: Vec<f32> = (0..1000).map(|x| (x % 200) as f32 * 10.).collect();
+        let right: Vec<f32> = (0..1000).map(|x| (x % 50) as f32 * -30.).collect();
+        write_wav_file(&original, vec![left.clone(), right.clone()], &metadata).unwrap();
+        let (mono_metadata, mono) = load_wav_file_mono(&original).unwrap();
+        assert_eq!(mono_metadata.sample_rate, 8000);
+        assert_eq!(mono_metadata.bit_rate, 16);
+        assert_eq!(mono.len(), left.len());
+        for ((l, r), m) in left.iter().zip(right.iter()).zip(mono.iter()) {
+            assert_eq!((l + r) / 2., *m);
+        }
+        // The written file is a mono file of the same format
+        downmix_wav(&original, &downmixed).unwrap();
+        let (restored_metadata, restored) = load_wav_file(&downmixed).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored_metadata.bit_rate, 16);
+        assert_eq!(restored[0], mono);
+    }
+
+    #[test]
+    fn stereo_round_trip() {
+        let dir = test_dir("stereo_round_trip");
+        let original = dir.join("original.wav");
+        let compressed = dir.join("compressed.cwv");
+        let decompressed = dir.join("decompressed.wav");
+        let metadata = WaveformMetadata::new(8000, 16);
+        let channels = vec![tone(5000, 8000, 440., 1000.), tone(5000, 8000, 300., 2000.)];
+        write_wav_file(&original, channels, &metadata).unwrap();
+        let (_, channels) = load_wav_file(&original).unwrap();
+        // Keep every frequency bin so the channels are only affected by quantization
+        crate::compress_wav(&original, &compressed, 8000, 1., |_| {}).unwrap();
+        crate::decompress_wav(&compressed, &decompressed).unwrap();
+        let (_, restored) = load_wav_file(&decompressed).unwrap();
+        assert_eq!(restored.len(), 2);
+        for (channel, restored) in channels.iter().zip(restored.iter()) {
+            assert_eq!(channel.len(), restored.len());
+            for (x, y) in channel.iter().zip(restored.iter()) {
+                assert!((x - y).abs() <= 10., "{x} != {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn dumped_stages() {
+        let dir = test_dir("dumped_stages").join("stages");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let metadata = WaveformMetadata::new(8000, 16);
+        let waveform: Vec<f32> = (0..3000).map(|x| (x % 100) as f32).collect();
+        let input = write_wav(vec![waveform.clone(), waveform], &metadata).unwrap();
+        // Nothing is dumped unless a directory is set
+        compress_wav_bytes(&input, 1000, 1., |_| {}).unwrap();
+        assert!(!dir.exists());
+        dump::set_dump_dir(Some(dir.clone()));
+        let compressed = compress_wav_bytes(&input, 1000, 1., |_| {}).unwrap();
+        decompress_wav_bytes(&compressed).unwrap();
+        dump::set_dump_dir(None);
+        // Padded to 4096 samples and cut at 1000 Hz
+        for c in 0..2 {
+            for (stage, descr, shape) in [
+                ("wav_time_domain", "'<f4'", "(4096,)"),
+                ("wav_frequency_domain", "'<c8'", "(2049,)"),
+                ("wav_retained", "'<c8'", "(512,)"),
+                ("wav_decoded", "'<c8'", "(1, 512)"),
+                ("wav_restored", "'<f4'", "(3000,)"),
+            ] {
+                let header = dump::read_header(&dir.join(format!("{stage}_{c}.npy")));
+                assert_eq!(header, (descr.into(), shape.into()), "{stage}_{c}");
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! # Haar wavelet
+//! The 2D Haar wavelet transform, an alternative to the FFT for images with sharp edges: its
+//! basis functions are steps rather than waves, so dropping coefficients blurs edges into
+//! blocks instead of ringing around them.
+//!
+
+use std::f32::consts::SQRT_2;
+
+/// The (orthonormal) Haar wavelet transform of a channel, decomposed level by level until the
+/// average of the whole channel remains, in the top left coefficient.
+///
+/// The transform needs power of two sizes, so the channel is first padded to the next power of
+/// two in each dimension, repeating its last row and column. The coefficients are of the padded
+/// size.
+pub fn haar_2d(channel: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    let (width, height) = (channel.first().map_or(0, Vec::len), channel.len());
+    if width == 0 {
+        return Vec::new();
+    }
+    let mut plane: Vec<Vec<f32>> = (0..height.next_power_of_two())
+        .map(|y| {
+            let row = &channel[y.min(height - 1)];
+            (0..width.next_power_of_two())
+                .map(|x| row[x.min(width - 1)])
+                .collect()
+        })
+        .collect();
+    for (width, height) in levels(&plane) {
+        if width > 1 {
+            for row in plane[..height].iter_mut() {
+                haar_step(&mut row[..width]);
+            }
+        }
+        if height > 1 {
+            for x in 0..width {
+                let mut column: Vec<f32> = plane[..height].iter().map(|row| row[x]).collect();
+                haar_step(&mut column);
+                plane.iter_mut().zip(column).for_each(|(row, c)| row[x] = c);
+            }
+        }
+    }
+    plane
+}
+
+/// Restore a (padded) channel from its coefficients, see [`haar_2d`]. Crop it to restore the
+/// original size.
+pub fn haar_2d_inverse(coefficients: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    let mut plane = coefficients.to_vec();
+    for (width, height) in levels(&plane).into_iter().rev() {
+        if height > 1 {
+            for x in 0..width {
+                let mut column: Vec<f32> = plane[..height].iter().map(|row| row[x]).collect();
+                haar_step_inverse(&mut column);
+                plane.iter_mut().zip(column).for_each(|(row, c)| row[x] = c);
+            }
+        }
+        if width > 1 {
+            for row in plane[..height].iter_mut() {
+                haar_step_inverse(&mut row[..width]);
+            }
+        }
+    }
+    plane
+}
+
+/// Size of the region transformed at each level, halving each dimension down to 1.
+fn levels(plane: &[Vec<f32>]) -> Vec<(usize, usize)> {
+    let (mut width, mut height) = (plane.first().map_or(0, Vec::len), plane.len());
+    let mut levels = Vec::new();
+    while width > 1 || height > 1 {
+        levels.push((width, height));
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+    levels
+}
+
+/// Replace pairs of values by their scaled sums (in the first half) and differences (in the
+/// second half).
+fn haar_step(values: &mut [f32]) {
+    let half = values.len() / 2;
+    let pairs: Vec<(f32, f32)> = values.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+    for (i, (a, b)) in pairs.into_iter().enumerate() {
+        values[i] = (a + b) / SQRT_2;
+        values[half + i] = (a - b) / SQRT_2;
+    }
+}
+
+fn haar_step_inverse(values: &mut [f32]) {
+    let half = values.len() / 2;
+    let pairs: Vec<(f32, f32)> = (0..half).map(|i| (values[i], values[half + i])).collect();
+    for (i, (sum, difference)) in pairs.into_iter().enumerate() {
+        values[2 * i] = (sum + difference) / SQRT_2;
+        values[2 * i + 1] = (sum - difference) / SQRT_2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(width: usize, height: usize) -> Vec<Vec<f32>> {
+        (0..height)
+            .map(|y| (0..width).map(|x| ((x * 7 + y * 13) % 50) as f32).collect())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for (width, height) in [(8, 8), (5, 3), (1, 6), (16, 2)] {
+            let channel = channel(width, height);
+            let coefficients = haar_2d(&channel);
+            assert_eq!(coefficients.len(), height.next_power_of_two());
+            assert_eq!(coefficients[0].len(), width.next_power_of_two());
+            let restored = haar_2d_inverse(&coefficients);
+            for (row, restored) in channel.iter().zip(&restored) {
+                for (x, y) in row.iter().zip(restored) {
+                    assert!((x - y).abs() < 1e-3, "{width}x{height}: {x} != {y}");
+                }
+            }
+        }
+        assert!(haar_2d(&[]).is_empty());
+    }
+
+    #[test]
+    fn average_and_energy() {
+        let constant = vec![vec![10.; 4]; 4];
+        let coefficients = haar_2d(&constant);
+        // All of a constant channel is in the average, scaled by the orthonormal steps
+        assert!((coefficients[0][0] - 40.).abs() < 1e-4);
+        let rest: f32 = coefficients.iter().flatten().skip(1).map(|c| c.abs()).sum();
+        assert!(rest < 1e-4);
+        let energy = |plane: &[Vec<f32>]| plane.iter().flatten().map(|x| x * x).sum::<f32>();
+        let channel = channel(8, 4);
+        let (before, after) = (energy(&channel), energy(&haar_2d(&channel)));
+        assert!((before - after).abs() / before < 1e-5);
+    }
+}
@@ -0,0 +1,116 @@
+//! Window functions for tapering samples before a transform, reducing spectral leakage.
+//!
+
+use std::f32::consts::PI;
+
+/// Shape of the window function.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum WindowKind {
+    #[default]
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+/// Multiply samples in place by the window function.
+pub fn apply_window(samples: &mut [f32], kind: WindowKind) {
+    let sample_size = samples.len();
+    for (i, sample) in samples.iter_mut().enumerate() {
+        *sample *= window_value(kind, i, sample_size);
+    }
+}
+
+/// Value of the (periodic) window function at an index for a given window size.
+pub fn window_value(kind: WindowKind, index: usize, size: usize) -> f32 {
+    let phase = 2. * PI * index as f32 / size as f32;
+    match kind {
+        WindowKind::Rectangular => 1.,
+        WindowKind::Hann => 0.5 - 0.5 * phase.cos(),
+        WindowKind::Hamming => 0.54 - 0.46 * phase.cos(),
+        WindowKind::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2. * phase).cos(),
+    }
+}
+
+/// Largest deviation of the overlap-added window from its average, relative to the average,
+/// for which [`cola_gain`] considers it constant.
+const COLA_TOLERANCE: f32 = 1e-3;
+
+/// Constant sum of the window over frames of `frame_size` samples starting every `hop` samples,
+/// or None if the sum varies (or the hop is zero or larger than the frame size).
+///
+/// Frames that are windowed and overlap-added (as by an inverse short-time Fourier transform)
+/// reconstruct the signal scaled by this gain only when it is constant: the constant overlap-add
+/// (COLA) condition. Otherwise the reconstruction is modulated at the hop.
+pub fn cola_gain(kind: WindowKind, frame_size: usize, hop: usize) -> Option<f32> {
+    if hop == 0 || hop > frame_size {
+        return None;
+    }
+    // Every sample past the first frame is covered by frames at the same offsets as one of these
+    let sums: Vec<f32> = (0..hop)
+        .map(|offset| {
+            (offset..frame_size)
+                .step_by(hop)
+                .map(|i| window_value(kind, i, frame_size))
+                .sum()
+        })
+        .collect();
+    let (min, max) = sums
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+            (min.min(*x), max.max(*x))
+        });
+    let gain = sums.iter().sum::<f32>() / hop as f32;
+    match max - min <= COLA_TOLERANCE * gain {
+        true => Some(gain),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft;
+
+    /// Number of frequency bins with at least 1% of the peak amplitude.
+    fn spread(kind: WindowKind) -> usize {
+        // A tone between two bins, the worst case for leakage
+        let mut sample: Vec<f32> = (0..1024)
+            .map(|x| (2. * PI * 100.5 * x as f32 / 1024.).sin())
+            .collect();
+        apply_window(&mut sample, kind);
+        let bins = fft::frequency_bins(&fft::fft(&fft::convert_sample(&sample)).unwrap());
+        let peak = bins.iter().cloned().fold(0., f32::max);
+        bins.iter().filter(|x| **x > peak * 0.01).count()
+    }
+
+    #[test]
+    fn hann_narrows_tone() {
+        let rectangular = spread(WindowKind::Rectangular);
+        let hann = spread(WindowKind::Hann);
+        assert!(hann < rectangular, "{hann} >= {rectangular}");
+    }
+
+    #[test]
+    fn constant_overlap_add() {
+        let hann = cola_gain(WindowKind::Hann, 1024, 512).unwrap();
+        assert!((hann - 1.).abs() < 1e-6, "{hann}");
+        assert!(cola_gain(WindowKind::Hann, 1024, 256).is_some());
+        assert!(cola_gain(WindowKind::Blackman, 999, 333).is_some());
+        assert_eq!(cola_gain(WindowKind::Rectangular, 1024, 1024), Some(1.));
+        // A hop of 90% leaves dips between the frames
+        assert_eq!(cola_gain(WindowKind::Hann, 1000, 900), None);
+        assert_eq!(cola_gain(WindowKind::Rectangular, 1024, 1000), None);
+        assert_eq!(cola_gain(WindowKind::Hann, 1024, 0), None);
+        assert_eq!(cola_gain(WindowKind::Hann, 1024, 2048), None);
+    }
+
+    #[test]
+    fn window_edges() {
+        let mut sample = vec![1.; 8];
+        apply_window(&mut sample, WindowKind::Hann);
+        assert_eq!(sample[0], 0.);
+        assert!((sample[4] - 1.).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,325 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("compression_cli_{name}"));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[allow(deprecated)]
+fn write_wav(path: &PathBuf) {
+    let header = wav::Header::new(wav::WAV_FORMAT_PCM, 1, 8000, 16);
+    let samples = (0..4000)
+        .map(|x| ((2. * PI * 440. * x as f32 / 8000.).sin() * 1000.) as i16)
+        .collect();
+    let mut file = fs::File::create(path).unwrap();
+    wav::write(header, &wav::BitDepth::Sixteen(samples), &mut file).unwrap();
+}
+
+fn write_bmp(path: &PathBuf) {
+    let mut image = bmp::Image::new(32, 32);
+    for (x, y) in image.coordinates() {
+        image.set_pixel(x, y, bmp::Pixel::new((x * 8) as u8, (y * 8) as u8, 100));
+    }
+    image.save(path).unwrap();
+}
+
+#[test]
+fn compress_directory() {
+    let dir = test_dir("compress_directory");
+    let (input, output) = (dir.join("input"), dir.join("output"));
+    fs::create_dir_all(&input).unwrap();
+    fs::create_dir_all(&output).unwrap();
+    write_wav(&input.join("tone.wav"));
+    write_bmp(&input.join("gradient.bmp"));
+    fs::write(input.join("notes.txt"), "not a media file").unwrap();
+    fs::write(input.join("broken.wav"), "not audio either").unwrap();
+
+    let result = Command::new(env!("CARGO_BIN_EXE_compression"))
+        .arg("compress")
+        .arg(&input)
+        .arg("--output-dir")
+        .arg(&output)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let stderr = String::from_utf8_lossy(&result.stderr);
+
+    // The broken file is reported, and fails the batch without stopping it
+    assert!(!result.status.success());
+    assert!(stderr.contains("broken.wav"), "{stderr}");
+    assert!(output.join("tone.cwv").is_file());
+    assert!(output.join("gradient.cbm").is_file());
+    assert!(stdout.contains("tone.wav: "), "{stdout}");
+    assert!(stdout.contains("gradient.bmp: "), "{stdout}");
+    assert!(!stdout.contains("notes.txt"), "{stdout}");
+    assert!(stdout.contains("Processed 2 files (1 failed)"), "{stdout}");
+}
+
+#[test]
+fn nested_output_dir() {
+    let dir = test_dir("nested_output_dir");
+    let original = dir.join("tone.wav");
+    write_wav(&original);
+    let compress = |output: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_compression"))
+            .arg("compress")
+            .arg(&original)
+            .arg("--output-dir")
+            .arg(output)
+            .output()
+            .unwrap()
+    };
+
+    let output = dir.join("nested").join("output");
+    let result = compress(&output);
+    assert!(result.status.success());
+    assert!(output.join("tone.cwv").is_file());
+
+    // A file in the way of the directory
+    fs::write(dir.join("blocker"), "").unwrap();
+    let result = compress(&dir.join("blocker").join("output"));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success());
+    assert!(
+        stderr.contains("cannot create output directory"),
+        "{stderr}"
+    );
+}
+
+#[cfg(feature = "mp3")]
+#[test]
+fn mp3_conflicts_with_partial() {
+    let dir = test_dir("mp3_conflicts_with_partial");
+    let result = Command::new(env!("CARGO_BIN_EXE_compression"))
+        .arg("decompress")
+        .arg(dir.join("tone.cwv"))
+        .args(["--mp3", "--partial", "0.5"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(!result.status.success());
+    assert!(stderr.contains("cannot be used with"), "{stderr}");
+}
+
+#[test]
+fn quiet_output() {
+    let dir = test_dir("quiet_output");
+    let original = dir.join("tone.wav");
+    write_wav(&original);
+    let run = |mode: &str, file: &PathBuf, quiet: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_compression"));
+        command.arg(mode).arg(file).arg("--output-dir").arg(&dir);
+        if quiet {
+            command.arg("--quiet");
+        }
+        command.output().unwrap()
+    };
+
+    let result = run("compress", &original, false);
+    assert!(result.status.success());
+    assert!(!result.stdout.is_empty());
+    // Neither the library nor the CLI print anything when quiet
+    for (mode, file) in [("compress", original), ("decompress", dir.join("tone.cwv"))] {
+        let result = run(mode, &file, true);
+        assert!(result.status.success());
+        assert!(result.stdout.is_empty(), "{:?}", result.stdout);
+    }
+    assert!(dir.join("tone_decompressed.wav").is_file());
+}
+
+#[test]
+fn verify_round_trip() {
+    let dir = test_dir("verify_round_trip");
+    let original = dir.join("tone.wav");
+    write_wav(&original);
+    let compress = Command::new(env!("CARGO_BIN_EXE_compression"))
+        .arg("compress")
+        .arg(&original)
+        .arg("--output-dir")
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(compress.status.success());
+    let compressed = dir.join("tone.cwv");
+    let verify = |compressed: &PathBuf| {
+        Command::new(env!("CARGO_BIN_EXE_compression"))
+            .arg("verify")
+            .arg(&original)
+            .arg(compressed)
+            .output()
+            .unwrap()
+    };
+
+    let result = verify(&compressed);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{stdout}");
+    assert!(stdout.contains("Max error: "), "{stdout}");
+
+    // Overwrite the end of the compressed data
+    let mut corrupted = fs::read(&compressed).unwrap();
+    let length = corrupted.len();
+    corrupted[length / 2..].fill(0xff);
+    let corrupted_file = dir.join("corrupted.cwv");
+    fs::write(&corrupted_file, corrupted).unwrap();
+    assert!(!verify(&corrupted_file).status.success());
+}
+
+#[test]
+fn dry_run_size() {
+    let dir = test_dir("dry_run_size");
+    write_wav(&dir.join("tone.wav"));
+    write_bmp(&dir.join("gradient.bmp"));
+    let compress = |file: &str, dry_run: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_compression"));
+        command
+            .arg("compress")
+            .arg(dir.join(file))
+            .arg("--output-dir")
+            .arg(&dir);
+        if dry_run {
+            command.arg("--dry-run");
+        }
+        let result = command.output().unwrap();
+        assert!(result.status.success());
+        String::from_utf8_lossy(&result.stdout).into_owned()
+    };
+
+    for (file, compressed) in [("tone.wav", "tone.cwv"), ("gradient.bmp", "gradient.cbm")] {
+        let stdout = compress(file, true);
+        assert!(!dir.join(compressed).exists(), "{stdout}");
+        let size: u64 = stdout
+            .split_once(" bytes)")
+            .and_then(|(before, _)| before.rsplit_once('(')?.1.parse().ok())
+            .unwrap_or_else(|| panic!("{stdout}"));
+        compress(file, false);
+        assert_eq!(fs::metadata(dir.join(compressed)).unwrap().len(), size);
+    }
+}
+
+#[test]
+fn analysis_path() {
+    let dir = test_dir("analysis_path");
+    let original = dir.join("tone.wav");
+    write_wav(&original);
+    // Without --open nothing is launched, so this passes where no browser is available
+    let result = Command::new(env!("CARGO_BIN_EXE_compression"))
+        .arg("analyze")
+        .arg(&original)
+        .arg("--output-dir")
+        .arg(&dir)
+        .env("PATH", "")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{stdout}");
+    let analysis = dir.join("tone_analysis.html");
+    assert!(stdout.contains(&format!("{analysis:?}")), "{stdout}");
+    assert!(
+        stdout.contains("Dominant frequencies:\n  439.5 Hz"),
+        "{stdout}"
+    );
+    assert!(analysis.is_file());
+}
+
+#[test]
+fn compressed_info() {
+    let dir = test_dir("compressed_info");
+    let original = dir.join("tone.wav");
+    write_wav(&original);
+    let run = |file: &PathBuf, info: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_compression"));
+        match info {
+            true => command.arg("info").arg(file),
+            false => command
+                .arg("compress")
+                .arg(file)
+                .arg("--output-dir")
+                .arg(&dir),
+        };
+        command.output().unwrap()
+    };
+    assert!(run(&original, false).status.success());
+
+    let result = run(&dir.join("tone.cwv"), true);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{stdout}");
+    assert!(stdout.contains("Sample rate: 8000 Hz"), "{stdout}");
+    assert!(stdout.contains("Original size: 4000 samples"), "{stdout}");
+    // Nothing is decompressed
+    assert!(!dir.join("tone_decompressed.wav").exists());
+    assert!(!run(&original, true).status.success());
+}
+
+#[test]
+fn subcommands() {
+    let dir = test_dir("subcommands");
+    let (tone, gradient) = (dir.join("tone.wav"), dir.join("gradient.bmp"));
+    write_wav(&tone);
+    write_bmp(&gradient);
+    let run = |args: &[&dyn AsRef<std::ffi::OsStr>]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_compression"));
+        for arg in args {
+            command.arg(arg);
+        }
+        let result = command.output().unwrap();
+        let stdout = String::from_utf8_lossy(&result.stdout).to_string();
+        (result.status.success(), stdout)
+    };
+
+    for file in [&tone, &gradient] {
+        let (success, stdout) = run(&[&"compress", file, &"--output-dir", &dir]);
+        assert!(success, "{stdout}");
+        assert!(stdout.contains("Compression ratio: "), "{stdout}");
+    }
+    let (compressed_tone, compressed_gradient) = (dir.join("tone.cwv"), dir.join("gradient.cbm"));
+    for file in [&compressed_tone, &compressed_gradient] {
+        let (success, stdout) = run(&[&"decompress", file, &"--output-dir", &dir]);
+        assert!(success, "{stdout}");
+        assert!(stdout.contains("Decompressed to: "), "{stdout}");
+    }
+    assert!(dir.join("tone_decompressed.wav").is_file());
+    assert!(dir.join("gradient_decompressed.bmp").is_file());
+
+    let (success, stdout) = run(&[&"analyze", &gradient, &"--output-dir", &dir]);
+    assert!(success, "{stdout}");
+    assert!(dir.join("gradient_analysis.html").is_file());
+
+    let (success, stdout) = run(&[&"info", &compressed_gradient]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains("Original size: 32x32"), "{stdout}");
+
+    // A level of 10 leaves little of so small an image
+    let (success, stdout) = run(&[
+        &"verify",
+        &gradient,
+        &compressed_gradient,
+        &"--max-error",
+        &"255",
+    ]);
+    assert!(success, "{stdout}");
+    assert!(stdout.contains("Max error: "), "{stdout}");
+
+    let (success, stdout) = run(&[
+        &"diff",
+        &compressed_tone,
+        &compressed_tone,
+        &"--output-dir",
+        &dir,
+    ]);
+    assert!(success, "{stdout}");
+    assert!(dir.join("tone_diff.html").is_file());
+
+    // Each subcommand takes only the files it works on, and only its own flags
+    assert!(!run(&[&"compress", &compressed_tone, &"--output-dir", &dir]).0);
+    assert!(!run(&[&"decompress", &tone, &"--output-dir", &dir]).0);
+    assert!(!run(&[&"info", &tone]).0);
+    assert!(!run(&[&"decompress", &compressed_tone, &"--dct"]).0);
+    assert!(!run(&[&tone]).0);
+}